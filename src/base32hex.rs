@@ -0,0 +1,58 @@
+// The "Extended Hex" base 32 alphabet, which unlike ordinary base 32 keeps the sort order of the
+// encoded data. NSEC3 uses it for hashed owner names, without padding.
+// https://www.rfc-editor.org/rfc/rfc4648#section-7
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let mut buffer = 0_u16;
+    let mut bits = 0;
+    for &b in bytes {
+        buffer = (buffer << 8) | b as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        text.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    text
+}
+
+// Decodes unpadded text in either case. Leftover bits at the end must be zero, as they would
+// be if the text came from `encode`.
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 5 / 8);
+    let mut buffer = 0_u16;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    if buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_rejects_bad_digits() {
+        let bytes = [0x00, 0xff, 0x10, 0x20, 0x30];
+        let text = encode(&bytes);
+        assert_eq!(text, "03VH081G");
+        assert_eq!(decode(&text).unwrap(), bytes);
+        assert_eq!(decode(&text.to_lowercase()).unwrap(), bytes);
+        assert!(decode("W").is_none());
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::header::ResponseCode;
+use crate::message::DNSMessage;
+use crate::question::Question;
+use crate::record::{QClass, QType, Rdata};
+
+// Upper bound on how long anything is cached, whatever TTL upstream gave it
+// https://www.rfc-editor.org/rfc/rfc8767#section-4
+pub const DEFAULT_MAX_TTL: u32 = 604_800;
+
+// Upper bound on how long negative answers are cached, at the top of the range RFC 2308 suggests
+// https://www.rfc-editor.org/rfc/rfc2308#section-5
+pub const DEFAULT_NEGATIVE_MAX_TTL: u32 = 10_800;
+
+// How many responses are kept before the ones closest to expiring are dropped for new ones
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+// Names compare case-insensitively, so are lowercased for the key. How the query was asked is
+// part of the key too: answers for DNSSEC aware clients carry signatures that others don't want,
+// and upstream sizes its answers to the UDP payload size in the client's OPT record, so one
+// fetched for a client with a large buffer may be too big for a client with a smaller one, or
+// one without EDNS at all.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct CacheKey {
+    name: Vec<Vec<u8>>,
+    qtype: QType,
+    qclass: QClass,
+    payload_size: Option<u16>,
+    dnssec_ok: bool,
+}
+
+impl CacheKey {
+    fn new(question: &Question, query: &DNSMessage) -> Self {
+        CacheKey {
+            name: question
+                .name
+                .iter()
+                .map(|label| label.to_ascii_lowercase())
+                .collect(),
+            qtype: question.qtype,
+            qclass: question.qclass,
+            payload_size: query.edns().map(|opt| opt.class.into()),
+            dnssec_ok: query.dnssec_ok(),
+        }
+    }
+}
+
+struct Entry {
+    response: DNSMessage,
+    stored: Instant,
+    ttl: u32,
+}
+
+impl Entry {
+    fn expires(&self) -> Instant {
+        self.stored + Duration::from_secs(self.ttl as u64)
+    }
+}
+
+// Responses from upstream, kept until the records in them expire. Each response is cached for
+// the smallest TTL among its records, clamped to the configured bounds. Negative answers have a
+// cap of their own, as a name that doesn't exist yet often soon will. Once the cache is full,
+// expired responses are purged to make room, then those closest to expiring.
+pub struct Cache {
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+    min_ttl: u32,
+    max_ttl: u32,
+    negative_max_ttl: u32,
+    max_entries: usize,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            entries: Mutex::new(HashMap::new()),
+            min_ttl: 0,
+            max_ttl: DEFAULT_MAX_TTL,
+            negative_max_ttl: DEFAULT_NEGATIVE_MAX_TTL,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+impl Cache {
+    /// Raises the time responses are cached for to at least `min_ttl` seconds.
+    pub fn with_min_ttl(mut self, min_ttl: u32) -> Self {
+        self.min_ttl = min_ttl;
+        self
+    }
+
+    /// Caps the time responses are cached for to `max_ttl` seconds.
+    pub fn with_max_ttl(mut self, max_ttl: u32) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// Caps the time negative answers (NXDOMAIN, or no records of the type asked for) are
+    /// cached for to `negative_max_ttl` seconds, in place of the bounds on other responses.
+    pub fn with_negative_max_ttl(mut self, negative_max_ttl: u32) -> Self {
+        self.negative_max_ttl = negative_max_ttl;
+        self
+    }
+
+    /// Caps the number of responses held at `max_entries`.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// The cached response to `question` asked in a query like `query`, with its TTLs reduced by
+    /// the time it has been cached.
+    pub fn get(&self, question: &Question, query: &DNSMessage) -> Option<DNSMessage> {
+        let key = CacheKey::new(question, query);
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key)?;
+        let age = entry.stored.elapsed().as_secs();
+        if age >= entry.ttl as u64 {
+            entries.remove(&key);
+            return None;
+        }
+
+        let mut response = entry.response.clone();
+        let age = age as u32;
+        for record in response
+            .answers
+            .iter_mut()
+            .chain(&mut response.authority)
+            .chain(&mut response.additional)
+            .filter(|record| record.rtype != QType::Opt)
+        {
+            // A record may have been cached for longer than its own TTL because of the floor
+            record.ttl = record.ttl.saturating_sub(age);
+        }
+        Some(response)
+    }
+
+    /// Caches `response` as the answer to `question` asked in `query`, if it can be cached at
+    /// all.
+    pub fn insert(&self, question: &Question, query: &DNSMessage, response: &DNSMessage) {
+        let Some(ttl) = self.ttl_for(response) else {
+            return;
+        };
+        let key = CacheKey::new(question, query);
+        let entry = Entry {
+            response: response.clone(),
+            stored: Instant::now(),
+            ttl,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let now = Instant::now();
+            entries.retain(|_, entry| entry.expires() > now);
+            while entries.len() >= self.max_entries {
+                let Some(soonest) = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires())
+                    .map(|(key, _)| key.clone())
+                else {
+                    return;
+                };
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key, entry);
+    }
+
+    /// Whether there is an unexpired response cached for `question` asked in a query like
+    /// `query`.
+    pub fn contains(&self, question: &Question, query: &DNSMessage) -> bool {
+        self.get(question, query).is_some()
+    }
+
+    /// How many responses are held, including any that have expired but not been purged yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // How long to cache a response for, or `None` if it mustn't be cached. A TTL of 0 means a
+    // record may be used for the transaction in progress but not cached, so a response holding
+    // one is never cached, however high the floor.
+    // https://www.rfc-editor.org/rfc/rfc2181#section-8
+    fn ttl_for(&self, response: &DNSMessage) -> Option<u32> {
+        let flags = &response.header.header_flags;
+        if flags.truncation
+            || !matches!(
+                flags.response_code,
+                ResponseCode::NoError | ResponseCode::NxDomain
+            )
+        {
+            return None;
+        }
+        let ttl = response
+            .answers
+            .iter()
+            .chain(&response.authority)
+            .chain(&response.additional)
+            .filter(|record| record.rtype != QType::Opt)
+            .map(|record| record.ttl)
+            .min()?;
+        if ttl == 0 {
+            return None;
+        }
+        if is_negative(response) {
+            return Some(negative_ttl(response, ttl).min(self.negative_max_ttl));
+        }
+        Some(ttl.max(self.min_ttl).min(self.max_ttl))
+    }
+}
+
+// Whether a response says the name doesn't exist, or has no records of the type asked for
+fn is_negative(response: &DNSMessage) -> bool {
+    response.header.header_flags.response_code == ResponseCode::NxDomain
+        || response.answers.is_empty()
+}
+
+// A negative answer is cached for the lesser of the SOA's TTL and its minimum field, where the
+// SOA is in the authority section; `ttl` is the smallest TTL in the response
+// https://www.rfc-editor.org/rfc/rfc2308#section-5
+fn negative_ttl(response: &DNSMessage, ttl: u32) -> u32 {
+    response
+        .authority
+        .iter()
+        .filter_map(|record| match record.rdata {
+            Rdata::Soa { minimum, .. } => Some(minimum),
+            _ => None,
+        })
+        .fold(ttl, u32::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::edns;
+    use crate::name::parse_name;
+    use crate::record::{Rdata, ResourceRecord};
+
+    fn question(name: &str) -> Question {
+        Question {
+            name: parse_name(name),
+            qtype: QType::A,
+            qclass: QClass::In,
+        }
+    }
+
+    // A query asking `question`, without EDNS
+    fn query(question: &Question) -> DNSMessage {
+        let mut query =
+            DNSMessage::from_bytes(&[0x12, 0x34, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        query.questions.push(question.clone());
+        query
+    }
+
+    // A response to `question` with a single A record with the given TTL
+    fn answer(question: &Question, ttl: u32) -> DNSMessage {
+        let mut response =
+            DNSMessage::from_bytes(&[0x12, 0x34, 0x81, 0x80, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        response.questions.push(question.clone());
+        response.answers.push(ResourceRecord {
+            name: question.name.clone(),
+            rtype: QType::A,
+            class: QClass::In,
+            ttl,
+            rdata: Rdata::A(Ipv4Addr::new(192, 0, 2, 1)),
+        });
+        response
+    }
+
+    #[test]
+    fn ttl_zero_is_never_cached() {
+        let cache = Cache::default().with_min_ttl(60);
+        let question = question("example.com");
+        cache.insert(&question, &query(&question), &answer(&question, 0));
+        assert!(!cache.contains(&question, &query(&question)));
+    }
+
+    #[test]
+    fn the_soonest_to_expire_is_evicted_when_full() {
+        let cache = Cache::default().with_max_entries(2);
+        let (long, short, new) = (
+            question("long.example"),
+            question("short.example"),
+            question("new.example"),
+        );
+        cache.insert(&long, &query(&long), &answer(&long, 3600));
+        cache.insert(&short, &query(&short), &answer(&short, 60));
+        cache.insert(&new, &query(&new), &answer(&new, 300));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.contains(&long, &query(&long)));
+        assert!(!cache.contains(&short, &query(&short)));
+        assert!(cache.contains(&new, &query(&new)));
+    }
+
+    #[test]
+    fn queries_with_and_without_edns_are_cached_apart() {
+        let cache = Cache::default();
+        let question = question("example.com");
+        let plain = query(&question);
+        cache.insert(&question, &plain, &answer(&question, 300));
+        let mut with_edns = plain.clone();
+        with_edns
+            .additional
+            .push(edns::opt_record(1232, Vec::new()));
+        assert!(cache.contains(&question, &plain));
+        assert!(!cache.contains(&question, &with_edns));
+
+        let mut dnssec = with_edns.clone();
+        dnssec.additional[0].ttl |= edns::DNSSEC_OK;
+        cache.insert(&question, &with_edns, &answer(&question, 300));
+        assert!(cache.contains(&question, &with_edns));
+        assert!(!cache.contains(&question, &dnssec));
+    }
+
+    #[test]
+    fn names_are_matched_case_insensitively() {
+        let cache = Cache::default();
+        cache.insert(
+            &question("Example.COM"),
+            &query(&question("Example.COM")),
+            &answer(&question("Example.COM"), 300),
+        );
+        let cached = cache
+            .get(&question("example.com"), &query(&question("example.com")))
+            .unwrap();
+        assert_eq!(cached.answers[0].ttl, 300);
+        assert!(!cache.contains(
+            &question("www.example.com"),
+            &query(&question("www.example.com"))
+        ));
+    }
+
+    #[test]
+    fn failures_and_truncated_responses_are_not_cached() {
+        let cache = Cache::default();
+        let question = question("example.com");
+        let mut response = answer(&question, 300);
+        response.header.header_flags.response_code = ResponseCode::ServFail;
+        cache.insert(&question, &query(&question), &response);
+        assert!(!cache.contains(&question, &query(&question)));
+
+        let mut response = answer(&question, 300);
+        response.header.header_flags.truncation = true;
+        cache.insert(&question, &query(&question), &response);
+        assert!(!cache.contains(&question, &query(&question)));
+    }
+
+    #[test]
+    fn ttls_are_clamped_to_the_bounds() {
+        let cache = Cache::default().with_min_ttl(60).with_max_ttl(600);
+        assert_eq!(cache.ttl_for(&answer(&question("a.example"), 5)), Some(60));
+        assert_eq!(
+            cache.ttl_for(&answer(&question("a.example"), 3600)),
+            Some(600)
+        );
+    }
+
+    // NXDOMAIN for `question`, with an SOA whose TTL and minimum are both `ttl`
+    fn nxdomain(question: &Question, ttl: u32) -> DNSMessage {
+        let mut response =
+            DNSMessage::from_bytes(&[0x12, 0x34, 0x81, 0x83, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        response.questions.push(question.clone());
+        response.authority.push(ResourceRecord {
+            name: parse_name("example.com"),
+            rtype: QType::Soa,
+            class: QClass::In,
+            ttl,
+            rdata: Rdata::Soa {
+                mname: parse_name("ns1.example.com"),
+                rname: parse_name("hostmaster.example.com"),
+                serial: 1,
+                refresh: 7200,
+                retry: 900,
+                expire: 1_209_600,
+                minimum: ttl,
+            },
+        });
+        response
+    }
+
+    #[test]
+    fn negative_answers_have_their_own_cap() {
+        let cache = Cache::default()
+            .with_min_ttl(600)
+            .with_max_ttl(86_400)
+            .with_negative_max_ttl(60);
+        let question = question("missing.example.com");
+        assert_eq!(cache.ttl_for(&nxdomain(&question, 86_400)), Some(60));
+        // Nor does the floor for positive answers apply
+        assert_eq!(cache.ttl_for(&nxdomain(&question, 30)), Some(30));
+        assert_eq!(cache.ttl_for(&answer(&question, 86_400)), Some(86_400));
+        assert_eq!(cache.ttl_for(&answer(&question, 30)), Some(600));
+
+        cache.insert(&question, &query(&question), &nxdomain(&question, 86_400));
+        let cached = cache.get(&question, &query(&question)).unwrap();
+        assert_eq!(
+            cached.header.header_flags.response_code,
+            ResponseCode::NxDomain
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config::Config;
+use crate::question::Question;
+use crate::record::{QClass, QType, Rdata, ResourceRecord};
+
+// Names in the CHAOS class that servers conventionally answer with their own identity
+// https://www.rfc-editor.org/rfc/rfc4892#section-2.3
+const IDENTITY_NAMES: [&[&str]; 2] = [&["id", "server"], &["hostname", "bind"]];
+
+// Answers the built in CHAOS TXT queries servers use to describe themselves, or returns `None`
+// if the question isn't one of them.
+pub(crate) fn answer(config: &Config, question: &Question) -> Option<ResourceRecord> {
+    if question.qclass != QClass::Ch || question.qtype != QType::Txt {
+        return None;
+    }
+    if !IDENTITY_NAMES
+        .iter()
+        .any(|name| is_name(&question.name, name))
+    {
+        return None;
+    }
+    Some(ResourceRecord {
+        name: question.name.clone(),
+        rtype: QType::Txt,
+        class: QClass::Ch,
+        ttl: 0,
+        rdata: Rdata::txt(&config.identity),
+    })
+}
+
+fn is_name(labels: &[Vec<u8>], name: &[&str]) -> bool {
+    labels.len() == name.len()
+        && labels
+            .iter()
+            .zip(name)
+            .all(|(label, expected)| label.eq_ignore_ascii_case(expected.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::name::parse_name;
+
+    fn question(name: &str, qtype: QType, qclass: QClass) -> Question {
+        Question {
+            name: parse_name(name),
+            qtype,
+            qclass,
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            identity: "ns1.example.com".to_string(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn id_server_returns_the_identity() {
+        let record = answer(&config(), &question("ID.Server", QType::Txt, QClass::Ch)).unwrap();
+        assert_eq!(record.name, parse_name("ID.Server"));
+        assert_eq!(record.class, QClass::Ch);
+        assert_eq!(record.rdata, Rdata::txt("ns1.example.com"));
+
+        let record = answer(
+            &config(),
+            &question("hostname.bind", QType::Txt, QClass::Ch),
+        );
+        assert_eq!(record.unwrap().rdata, Rdata::txt("ns1.example.com"));
+    }
+
+    #[test]
+    fn only_chaos_txt_is_answered() {
+        let config = config();
+        assert!(answer(&config, &question("id.server", QType::Txt, QClass::In)).is_none());
+        assert!(answer(&config, &question("id.server", QType::A, QClass::Ch)).is_none());
+        assert!(answer(&config, &question("version.bind", QType::Txt, QClass::Ch)).is_none());
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::cache;
+use crate::edns;
+use crate::resolver;
+
+const DEFAULT_AMPLIFICATION_THRESHOLD: f64 = 10.0;
+
+// Long enough for a client to follow up on an answer without reconnecting, short enough that
+// idle clients don't pile up
+// https://www.rfc-editor.org/rfc/rfc7766#section-6.2.3
+const DEFAULT_TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_TCP_MAX_SESSION: Duration = Duration::from_secs(120);
+const DEFAULT_TCP_MAX_CONNECTIONS: usize = 256;
+const DEFAULT_TCP_MAX_IN_FLIGHT: usize = 16;
+const DEFAULT_UDP_MAX_IN_FLIGHT: usize = 64;
+
+// Runtime settings for the server
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Returned in answer to id.server and hostname.bind CHAOS queries
+    pub identity: String,
+    // Upstream server to forward queries to. Without one we only answer from built in data.
+    pub resolver: Option<SocketAddr>,
+    // Bounds on how long forwarded responses are cached, in seconds, whatever their TTLs
+    pub cache_min_ttl: u32,
+    pub cache_max_ttl: u32,
+    // Cap on how long negative answers are cached, in seconds, used instead of the bounds above
+    pub cache_negative_max_ttl: u32,
+    // How many forwarded responses are cached before the ones closest to expiring are dropped
+    pub cache_max_entries: usize,
+    // Upstream servers for particular domains, used instead of `resolver` for names under them
+    pub forward_zones: Vec<ForwardZone>,
+    // EDNS option codes removed from queries before forwarding them, e.g. client subnet so
+    // clients' addresses aren't leaked upstream
+    pub strip_edns_options: Vec<u16>,
+    // UDP responses more than this many times the size of their query are logged and counted
+    pub amplification_threshold: f64,
+    // Wire bytes sent back to every query, with only the ID changed, for measuring raw socket
+    // throughput under load. Only ever set from --static-response, never in production.
+    pub static_response: Option<Vec<u8>>,
+    // Artificial delays before replying, for testing clients' timeout and retry handling.
+    // Only ever set from --debug-delay, never in production.
+    pub debug_delays: Vec<DebugDelay>,
+    // Once more than this many queries are in flight, only priority queries are answered and
+    // the rest get SERVFAIL. Without it no load is shed. Queries over UDP and TCP count alike.
+    pub load_shed_threshold: Option<u64>,
+    // Write names in responses out in full, for clients that mishandle compression pointers
+    pub disable_compression: bool,
+    // What ANY queries for names in our zones get back
+    pub any_response: AnyResponse,
+    // Forward queries with RD clear as if it were set. Otherwise they are only answered from our
+    // own data, and refused for names outside our zones.
+    pub forward_without_rd: bool,
+    // The UDP payload size advertised in our OPT records. Larger queries are still answered, but
+    // logged, as the client ignored what we advertised
+    pub udp_payload_size: u16,
+    // Sort MX and SRV answers into the order clients should try them in, rather than leaving the
+    // sorting to clients as they're expected to
+    pub sort_by_preference: bool,
+    // How long a TCP connection may sit idle between queries before we close it, as advertised to
+    // clients that ask with the EDNS keepalive option
+    pub tcp_idle_timeout: Duration,
+    // How long a TCP connection may stay open in all, however busy, so a client trickling bytes
+    // in can't hold one open forever
+    pub tcp_max_session: Duration,
+    // How many TCP connections may be open at once; more are closed as soon as they're accepted
+    pub tcp_max_connections: usize,
+    // How many queries pipelined on one TCP connection are answered at once; the rest wait to be
+    // read until one of those finishes
+    pub tcp_max_in_flight: usize,
+    // How many UDP queries on one socket are answered at once; the rest wait to be read until
+    // one of those finishes
+    pub udp_max_in_flight: usize,
+    // Log a hex dump of every query received and every response sent
+    pub hexdump: bool,
+    // How many CNAMEs to follow when answering from a zone or resolving upstream before giving
+    // up on the chain
+    pub max_cname_chain: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForwardZone {
+    // Queries for this name and everything below it are forwarded to `upstream`
+    pub suffix: Vec<Vec<u8>>,
+    pub upstream: SocketAddr,
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugDelay {
+    // Only delay replies to questions for this name, or to every query if not set
+    pub name: Option<Vec<Vec<u8>>>,
+    pub delay: Duration,
+}
+
+// ANY queries are answered with a single record rather than everything at the name, so they
+// can't be used for amplification
+// https://www.rfc-editor.org/rfc/rfc8482#section-4
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AnyResponse {
+    // A synthesized HINFO record saying only "RFC8482"
+    Hinfo,
+    // One of the records the name actually has, which is of some use to the client
+    SingleRecord,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            identity: system_hostname(),
+            resolver: None,
+            cache_min_ttl: 0,
+            cache_max_ttl: cache::DEFAULT_MAX_TTL,
+            cache_negative_max_ttl: cache::DEFAULT_NEGATIVE_MAX_TTL,
+            cache_max_entries: cache::DEFAULT_MAX_ENTRIES,
+            forward_zones: Vec::new(),
+            strip_edns_options: Vec::new(),
+            amplification_threshold: DEFAULT_AMPLIFICATION_THRESHOLD,
+            static_response: None,
+            debug_delays: Vec::new(),
+            load_shed_threshold: None,
+            disable_compression: false,
+            any_response: AnyResponse::Hinfo,
+            forward_without_rd: false,
+            udp_payload_size: edns::DEFAULT_UDP_PAYLOAD_SIZE,
+            sort_by_preference: false,
+            tcp_idle_timeout: DEFAULT_TCP_IDLE_TIMEOUT,
+            tcp_max_session: DEFAULT_TCP_MAX_SESSION,
+            tcp_max_connections: DEFAULT_TCP_MAX_CONNECTIONS,
+            tcp_max_in_flight: DEFAULT_TCP_MAX_IN_FLIGHT,
+            udp_max_in_flight: DEFAULT_UDP_MAX_IN_FLIGHT,
+            hexdump: false,
+            max_cname_chain: resolver::DEFAULT_MAX_CNAME_CHAIN,
+        }
+    }
+}
+
+// std has no portable hostname lookup, so read it from where Linux exposes it
+fn system_hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|hostname| hostname.trim().to_string())
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
@@ -0,0 +1,744 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::WriteError;
+use crate::name::{is_subdomain, same_name};
+use crate::record::{QType, Rdata, ResourceRecord};
+use crate::rsa;
+use crate::sha256;
+use crate::writer::Writer;
+
+// The only signing algorithm and DS digest type we can check
+// https://www.rfc-editor.org/rfc/rfc5702
+// https://www.rfc-editor.org/rfc/rfc4509
+const RSASHA256: u8 = 8;
+const DIGEST_SHA256: u8 = 2;
+
+// Only keys with the zone key flag set sign a zone's records, and the protocol is always 3
+// https://www.rfc-editor.org/rfc/rfc4034#section-2.1.1
+const ZONE_KEY_FLAG: u16 = 0x0100;
+const DNSKEY_PROTOCOL: u8 = 3;
+
+// The outcome of validating an answer
+// https://www.rfc-editor.org/rfc/rfc4035#section-4.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    // Every RRset in the answer was signed with a key the chain from a trust anchor vouches for
+    Secure,
+    // No trust anchor covers the name, so there is nothing to validate against, or the answer or
+    // a link in its chain is only signed with algorithms we can't check
+    Insecure,
+    // A trust anchor covers the name but the chain or a signature doesn't check out
+    Bogus,
+}
+
+// Validates answers against DNSSEC trust anchors, checking signatures as at the current time
+// unless given another
+#[derive(Default)]
+pub struct Validator {
+    time: Option<SystemTime>,
+    // Whether answers only signed with algorithms we can't check are Bogus rather than Insecure
+    require_known_algorithms: bool,
+}
+
+impl Validator {
+    /// Checks signatures' validity periods against `time` rather than the current time.
+    pub fn with_time(mut self, time: SystemTime) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Treats answers whose signatures, or whose chain's DS records, all use algorithms or
+    /// digests we don't implement as Bogus. By default they're Insecure, as if unsigned.
+    /// https://www.rfc-editor.org/rfc/rfc6840#section-5.2
+    pub fn with_require_known_algorithms(mut self, require_known_algorithms: bool) -> Self {
+        self.require_known_algorithms = require_known_algorithms;
+        self
+    }
+
+    /// Validates the RRsets in `answer`, which must include some at `name`, by following the
+    /// chain of trust from the closest of `anchors` (DS or DNSKEY records) down through the DS,
+    /// DNSKEY and RRSIG records in `delegations` at each zone cut to the zone that signed them.
+    ///
+    /// Only RSASHA256 signatures and SHA-256 DS digests can be checked, and RRsets or zones only
+    /// signed in other ways are Insecure unless `with_require_known_algorithms` is set. Denial of
+    /// existence is not validated, so an answer with nothing at `name` under a trust anchor is
+    /// Bogus.
+    pub fn validate_chain(
+        &self,
+        name: &[Vec<u8>],
+        answer: &[ResourceRecord],
+        delegations: &[ResourceRecord],
+        anchors: &[ResourceRecord],
+    ) -> Validation {
+        if !anchors
+            .iter()
+            .any(|anchor| is_subdomain(name, &anchor.name))
+        {
+            return Validation::Insecure;
+        }
+        let now = self
+            .time
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let chain = Chain {
+            // Serial number arithmetic makes the time wrap round in 2106
+            now: now as u32,
+            records: delegations.iter().chain(answer).collect(),
+            anchors,
+            require_known_algorithms: self.require_known_algorithms,
+        };
+
+        let mut rrsets: Vec<(&[Vec<u8>], QType)> = Vec::new();
+        for record in answer.iter().filter(|record| record.rtype != QType::Rrsig) {
+            if !rrsets
+                .iter()
+                .any(|(owner, rtype)| *rtype == record.rtype && same_name(owner, &record.name))
+            {
+                rrsets.push((&record.name, record.rtype));
+            }
+        }
+        if !rrsets.iter().any(|(owner, _)| same_name(owner, name)) {
+            return Validation::Bogus;
+        }
+
+        let mut result = Validation::Secure;
+        for (owner, rtype) in rrsets {
+            if !anchors
+                .iter()
+                .any(|anchor| is_subdomain(owner, &anchor.name))
+            {
+                result = Validation::Insecure;
+                continue;
+            }
+            match chain.verify(owner, rtype, |signer| chain.zone_keys(signer)) {
+                Validation::Secure => {}
+                Validation::Insecure => result = Validation::Insecure,
+                Validation::Bogus => return Validation::Bogus,
+            }
+        }
+        result
+    }
+}
+
+/// Validates `answer` for `name` as `Validator::validate_chain` does, as at the current time.
+pub fn validate_chain(
+    name: &[Vec<u8>],
+    answer: &[ResourceRecord],
+    delegations: &[ResourceRecord],
+    anchors: &[ResourceRecord],
+) -> Validation {
+    Validator::default().validate_chain(name, answer, delegations, anchors)
+}
+
+// The records being validated, and what they're checked against
+struct Chain<'a> {
+    now: u32,
+    records: Vec<&'a ResourceRecord>,
+    anchors: &'a [ResourceRecord],
+    require_known_algorithms: bool,
+}
+
+impl<'a> Chain<'a> {
+    // The keys of the zone at `zone` that are trusted to sign its records: the whole DNSKEY RRset
+    // once it's signed by a key an anchor or the parent's signed DS records vouch for. Otherwise
+    // how the zone's records are to be classified: Bogus if the chain is broken, or Insecure if
+    // it can't be followed for want of an algorithm.
+    fn zone_keys(&self, zone: &[Vec<u8>]) -> Result<Vec<&'a Rdata>, Validation> {
+        let anchored = rrset(self.anchors, zone, QType::Ds);
+        let anchor_keys = rrset(self.anchors, zone, QType::Dnskey);
+        // The root has no parent to hold DS records for it, so is only trusted through an anchor
+        let ds = if !anchored.is_empty() || !anchor_keys.is_empty() {
+            anchored
+        } else if zone.is_empty() {
+            return Err(Validation::Bogus);
+        } else {
+            let signed = self.verify(zone, QType::Ds, |signer| {
+                // The DS records belong to the parent, so must be signed from above the cut
+                if signer.len() < zone.len() {
+                    self.zone_keys(signer)
+                } else {
+                    Err(Validation::Bogus)
+                }
+            });
+            if signed != Validation::Secure {
+                return Err(signed);
+            }
+            rrset(self.records.iter().copied(), zone, QType::Ds)
+        };
+        // With no DS we can check, the zone can't be told apart from an unsigned one
+        // https://www.rfc-editor.org/rfc/rfc4035#section-5.2
+        if anchor_keys.is_empty() && !ds.iter().any(|ds| is_supported_ds(&ds.rdata)) {
+            return Err(self.unknown_algorithm());
+        }
+
+        let mut keys = rrset(self.records.iter().copied(), zone, QType::Dnskey);
+        keys.retain(|key| is_zone_key(&key.rdata));
+        let entry_keys: Vec<&Rdata> = keys
+            .iter()
+            .filter(|key| {
+                anchor_keys.iter().any(|anchor| anchor.rdata == key.rdata)
+                    || ds.iter().any(|ds| ds_matches(&ds.rdata, key))
+            })
+            .map(|key| &key.rdata)
+            .collect();
+        let signed = self.verify(zone, QType::Dnskey, |signer| {
+            if same_name(signer, zone) {
+                Ok(entry_keys.clone())
+            } else {
+                Err(Validation::Bogus)
+            }
+        });
+        match signed {
+            Validation::Secure => Ok(keys.iter().map(|key| &key.rdata).collect()),
+            other => Err(other),
+        }
+    }
+
+    // Whether some RRSIG over the RRset of type `rtype` at `owner` is valid now and checks out
+    // against one of the keys `keys_for` gives for its signer. If none does, the RRset is
+    // Insecure where it's only signed with algorithms we can't check or its signer's zone is
+    // Insecure, and otherwise Bogus.
+    fn verify<'k>(
+        &self,
+        owner: &[Vec<u8>],
+        rtype: QType,
+        keys_for: impl Fn(&[Vec<u8>]) -> Result<Vec<&'k Rdata>, Validation>,
+    ) -> Validation {
+        let records = rrset(self.records.iter().copied(), owner, rtype);
+        if records.is_empty() {
+            return Validation::Bogus;
+        }
+        let mut known_algorithm = false;
+        let mut unknown_algorithm = false;
+        let mut insecure_signer = false;
+        for signature in rrset(self.records.iter().copied(), owner, QType::Rrsig) {
+            let Rdata::Rrsig {
+                type_covered,
+                algorithm,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature: signature_bytes,
+                ..
+            } = &signature.rdata
+            else {
+                continue;
+            };
+            if *type_covered != rtype {
+                continue;
+            }
+            if *algorithm != RSASHA256 {
+                unknown_algorithm = true;
+                continue;
+            }
+            known_algorithm = true;
+            if !is_subdomain(owner, signer_name) || !in_window(self.now, *inception, *expiration) {
+                continue;
+            }
+            let Some(data) = signed_data(&signature.rdata, &records) else {
+                continue;
+            };
+            let keys = match keys_for(signer_name) {
+                Ok(keys) => keys,
+                Err(Validation::Insecure) => {
+                    insecure_signer = true;
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let verified = keys.into_iter().any(|key| match key {
+                Rdata::Dnskey {
+                    algorithm: RSASHA256,
+                    public_key,
+                    ..
+                } => {
+                    key_tag_of(key) == Some(*key_tag)
+                        && rsa::verify_sha256(public_key, &data, signature_bytes)
+                }
+                _ => false,
+            });
+            if verified {
+                return Validation::Secure;
+            }
+        }
+        if insecure_signer {
+            Validation::Insecure
+        } else if unknown_algorithm && !known_algorithm {
+            self.unknown_algorithm()
+        } else {
+            Validation::Bogus
+        }
+    }
+
+    // How records are classified when nothing we can check signs them
+    fn unknown_algorithm(&self) -> Validation {
+        if self.require_known_algorithms {
+            Validation::Bogus
+        } else {
+            Validation::Insecure
+        }
+    }
+}
+
+// The records of type `rtype` at `owner`
+fn rrset<'a>(
+    records: impl IntoIterator<Item = &'a ResourceRecord>,
+    owner: &[Vec<u8>],
+    rtype: QType,
+) -> Vec<&'a ResourceRecord> {
+    records
+        .into_iter()
+        .filter(|record| record.rtype == rtype && same_name(&record.name, owner))
+        .collect()
+}
+
+fn is_supported_ds(rdata: &Rdata) -> bool {
+    matches!(
+        rdata,
+        Rdata::Ds {
+            algorithm: RSASHA256,
+            digest_type: DIGEST_SHA256,
+            ..
+        }
+    )
+}
+
+fn is_zone_key(rdata: &Rdata) -> bool {
+    matches!(rdata, Rdata::Dnskey { flags, protocol, .. }
+        if flags & ZONE_KEY_FLAG != 0 && *protocol == DNSKEY_PROTOCOL)
+}
+
+// Whether `now` is from the inception to the expiration time, in serial number arithmetic
+// https://www.rfc-editor.org/rfc/rfc4034#section-3.1.5
+fn in_window(now: u32, inception: u32, expiration: u32) -> bool {
+    now.wrapping_sub(inception) as i32 >= 0 && expiration.wrapping_sub(now) as i32 >= 0
+}
+
+// The tag identifying a DNSKEY, a checksum of its RDATA
+// https://www.rfc-editor.org/rfc/rfc4034#appendix-B
+fn key_tag_of(key: &Rdata) -> Option<u16> {
+    let bytes = canonical_rdata(key).ok()?;
+    let mut sum: u32 = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        sum += if i % 2 == 0 {
+            (b as u32) << 8
+        } else {
+            b as u32
+        };
+    }
+    sum += (sum >> 16) & 0xffff;
+    Some(sum as u16)
+}
+
+// Whether a DS record is a digest of `key`: SHA-256 over the key's owner name and its RDATA
+// https://www.rfc-editor.org/rfc/rfc4034#section-5.1.4
+fn ds_matches(ds: &Rdata, key: &ResourceRecord) -> bool {
+    let Rdata::Ds {
+        key_tag,
+        algorithm,
+        digest_type: DIGEST_SHA256,
+        digest,
+    } = ds
+    else {
+        return false;
+    };
+    let Rdata::Dnskey {
+        algorithm: key_algorithm,
+        ..
+    } = &key.rdata
+    else {
+        return false;
+    };
+    let Ok(key_rdata) = canonical_rdata(&key.rdata) else {
+        return false;
+    };
+    let mut writer = Writer::uncompressed();
+    writer.write_name_uncompressed(&lowercase(&key.name));
+    writer.write_bytes(&key_rdata);
+    let Ok(data) = writer.finish() else {
+        return false;
+    };
+    algorithm == key_algorithm
+        && key_tag_of(&key.rdata) == Some(*key_tag)
+        && sha256::digest(&data) == digest[..]
+}
+
+// What an RRSIG's signature covers: its own RDATA up to the signature, then each record of the
+// RRset in canonical form, sorted by RDATA with duplicates dropped. Owners signed through a
+// wildcard have the labels beyond the RRSIG's count replaced by "*".
+// https://www.rfc-editor.org/rfc/rfc4034#section-3.1.8.1
+// https://www.rfc-editor.org/rfc/rfc4035#section-5.3.2
+fn signed_data(rrsig: &Rdata, rrset: &[&ResourceRecord]) -> Option<Vec<u8>> {
+    let Rdata::Rrsig {
+        type_covered,
+        algorithm,
+        labels,
+        original_ttl,
+        expiration,
+        inception,
+        key_tag,
+        signer_name,
+        signature: _,
+    } = rrsig
+    else {
+        return None;
+    };
+    let mut writer = Writer::uncompressed();
+    Rdata::Rrsig {
+        type_covered: *type_covered,
+        algorithm: *algorithm,
+        labels: *labels,
+        original_ttl: *original_ttl,
+        expiration: *expiration,
+        inception: *inception,
+        key_tag: *key_tag,
+        signer_name: lowercase(signer_name),
+        signature: Vec::new(),
+    }
+    .write(&mut writer);
+
+    let mut rdatas = rrset
+        .iter()
+        .map(|record| canonical_rdata(&record.rdata))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    rdatas.sort();
+    rdatas.dedup();
+
+    let owner = &rrset[0].name;
+    let mut owner_labels: Vec<Vec<u8>> = owner
+        .iter()
+        .skip_while(|label| label.as_slice() == b"*")
+        .map(|label| label.to_ascii_lowercase())
+        .collect();
+    let labels = *labels as usize;
+    if labels > owner_labels.len() {
+        return None;
+    }
+    if labels < owner_labels.len() {
+        owner_labels.drain(..owner_labels.len() - labels);
+        owner_labels.insert(0, b"*".to_vec());
+    }
+
+    for rdata in rdatas {
+        let record = rrset[0];
+        writer.write_name_uncompressed(&owner_labels);
+        writer.write_u16(record.rtype.into());
+        writer.write_u16(record.class.into());
+        writer.write_u32(*original_ttl);
+        writer.write_u16(rdata.len() as u16);
+        writer.write_bytes(&rdata);
+    }
+    writer.finish().ok()
+}
+
+// RDATA as it's signed, uncompressed and with the names in the types RFC 4034 lists lowercased
+// https://www.rfc-editor.org/rfc/rfc4034#section-6.2
+fn canonical_rdata(rdata: &Rdata) -> Result<Vec<u8>, WriteError> {
+    let canonical = match rdata {
+        Rdata::Ns(name) => Rdata::Ns(lowercase(name)),
+        Rdata::Cname(name) => Rdata::Cname(lowercase(name)),
+        Rdata::Dname(name) => Rdata::Dname(lowercase(name)),
+        Rdata::Mx {
+            preference,
+            exchange,
+        } => Rdata::Mx {
+            preference: *preference,
+            exchange: lowercase(exchange),
+        },
+        Rdata::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } => Rdata::Srv {
+            priority: *priority,
+            weight: *weight,
+            port: *port,
+            target: lowercase(target),
+        },
+        Rdata::Soa {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => Rdata::Soa {
+            mname: lowercase(mname),
+            rname: lowercase(rname),
+            serial: *serial,
+            refresh: *refresh,
+            retry: *retry,
+            expire: *expire,
+            minimum: *minimum,
+        },
+        other => other.clone(),
+    };
+    let mut writer = Writer::uncompressed();
+    canonical.write(&mut writer);
+    writer.finish()
+}
+
+fn lowercase(name: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    name.iter()
+        .map(|label| label.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::name::parse_name;
+    use crate::zone_file::load_records;
+
+    // A small signed hierarchy: the root and example., each with a single RSASHA256 key, the
+    // root's trusted through a DS anchor and example.'s through the DS the root signed for it.
+    // Every signature is valid from 2020 to 2070.
+    const ANCHOR: &str = "\
+        . 3600 IN DS 38840 8 2 (\n\
+            07AF5FD25E56CC413DCA25ED21BED07A E73F9E3FE466D244D61C3E4321198400 )\n";
+    const DELEGATIONS: &str = "\
+        . 3600 IN DNSKEY 257 3 8 (\n\
+            AwEAAbl4gRKAdDt4XSRDwg12ThJQ/54W0zTw6GrcmVIbw+Au4c5xMMG6\n\
+            Y4V9AvfKfmrnr1uycQ9VUt7wate8D9nYAJ1Hl++O8rKmc4Us+9NNCurZ\n\
+            xcj8ffYed6vMp7lbAREjpp9u4Y1kJLPDU7f4xLT6gAiljtO50HpUzAfJ\n\
+            EUMePECv )\n\
+        . 3600 IN RRSIG DNSKEY 8 0 3600 (\n\
+            20700101000000 20200101000000 38840 .\n\
+            fZwF3eZHmGDiZrdhbKNOrKbgFNJij7JeyX50YlOQCGeaoeIcVlMy+0K3\n\
+            FzGJ2roesVLAY/p7gIY8xrEtJnxDi82spL3ndiEk+PhsSo2KmQN651QO\n\
+            Y45w8f4HWyGLU8hf/FQnerdkzsiOPMmTlqdS/0sbaxCgFVnOmZLZPaSV\n\
+            d0g= )\n\
+        example. 3600 IN DS 50837 8 2 (\n\
+            8ECB5D0EDB17D52A2663BA7C0195725D AF2C3680E5DB780FDF610C174FD37B9F )\n\
+        example. 3600 IN RRSIG DS 8 1 3600 (\n\
+            20700101000000 20200101000000 38840 .\n\
+            ppQkgn/Sx+kCvKCbg4jq3LWPS1lZXUtnjP4ZJ8ovO/jhuUJwEEjRJTSH\n\
+            wrzJVMnx+pS3dr77aQJAiCspjEL6n8AahNCds1j0QcR4KPlVXDsWq4Wm\n\
+            PLu6K2+2ilONfzudPoJoRxk/rN0biNEmyxtYs6gbRW+VNKADQZMAH1ML\n\
+            HTk= )\n\
+        example. 3600 IN DNSKEY 257 3 8 (\n\
+            AwEAAdlVvzVvFCbneiySdkpyC+sCinu/nFk8KWEBn+lBULAjKw3888sW\n\
+            WqZnOAyIqAfENilQM43jGAQu9ya0jKhAWD1/4yQwk+QIma6LN3UWgE2x\n\
+            lNFIqty5bPzXFMqD4IK2XeP5H0dkK7wQiMIUoqr675AIqZQXmNyWZrtP\n\
+            uBFRS/A3 )\n\
+        example. 3600 IN RRSIG DNSKEY 8 1 3600 (\n\
+            20700101000000 20200101000000 50837 example.\n\
+            kP0ztr0vLZszVSFdb4IRUTFUhH4UdJZQcCzS1jbH4HPT/JKl3YGA1ZDn\n\
+            JPIS1JkKmk/ebJe0oLooP/xWVGWOaEcXK7DgbKlCpQPwQ0aHmm9pQ4Kg\n\
+            2BYsUtQ3wbXO9QEuGBmxbKz9Ns3Rn4Pv6aDNbDmmaVcftBStd5g70CiV\n\
+            uZQ= )\n";
+    const ANSWER: &str = "\
+        www.example. 3600 IN A 192.0.2.2\n\
+        www.example. 3600 IN A 192.0.2.1\n\
+        www.example. 3600 IN RRSIG A 8 2 3600 (\n\
+            20700101000000 20200101000000 50837 example.\n\
+            D3mf9l0DsbWwKmIIfPmk79zF+mrAkse5pmPdEvV8wl0nYJgMFM/XJwkG\n\
+            0AdodDYXmwS+SOeXuxqZAyOmhToXzCkypVyQ9aYCc8Wms1DyCIdDRNqD\n\
+            5ZRQmtg+Y12ko/D23tPJjwwK+QbgNT1ahnAbpNca2RHPYVY7Nh6yNRr+\n\
+            824= )\n";
+    // Signed as *.example., so with one label fewer than the owner
+    const WILDCARD: &str = "\
+        anything.example. 3600 IN TXT \"hello\"\n\
+        anything.example. 3600 IN RRSIG TXT 8 1 3600 (\n\
+            20700101000000 20200101000000 50837 example.\n\
+            b9Jka3yCVIvEPv4WXmKXhhTj9pA7I6Q90FIliiT9sdtq3Xu2xNuhrcsj\n\
+            iCnTugNXUYHL/3JO8db4O9Ji2WWnFp6Fg/uFlY83NDCZ3wRKX7hCiY9i\n\
+            kDnjBmIXtJ0hkcz2eG29VaQcwj7HzPOB6za4sb5res1NNAcD7AmC68sZ\n\
+            spI= )\n";
+
+    fn records(text: &str) -> Vec<ResourceRecord> {
+        load_records(text, &[]).unwrap()
+    }
+
+    fn validate(validator: &Validator, name: &str, answer: &[ResourceRecord]) -> Validation {
+        validator.validate_chain(
+            &parse_name(name),
+            answer,
+            &records(DELEGATIONS),
+            &records(ANCHOR),
+        )
+    }
+
+    #[test]
+    fn answers_signed_down_the_chain_are_secure() {
+        let validator = Validator::default();
+        assert_eq!(
+            validate(&validator, "www.example", &records(ANSWER)),
+            Validation::Secure
+        );
+        assert_eq!(
+            validate(&validator, "anything.example", &records(WILDCARD)),
+            Validation::Secure
+        );
+
+        // Names are signed lowercased, so the case they arrive in doesn't matter
+        let mut answer = records(ANSWER);
+        for record in &mut answer {
+            record.name = parse_name("WWW.Example");
+        }
+        assert_eq!(
+            validate(&validator, "www.example", &answer),
+            Validation::Secure
+        );
+    }
+
+    #[test]
+    fn dnskey_records_can_be_trust_anchors() {
+        let anchors: Vec<ResourceRecord> = records(DELEGATIONS)
+            .into_iter()
+            .filter(|record| record.rtype == QType::Dnskey && record.name.len() == 1)
+            .collect();
+        let result = Validator::default().validate_chain(
+            &parse_name("www.example"),
+            &records(ANSWER),
+            &records(DELEGATIONS),
+            &anchors,
+        );
+        assert_eq!(result, Validation::Secure);
+    }
+
+    #[test]
+    fn broken_links_are_bogus() {
+        let validator = Validator::default();
+        let answer = records(ANSWER);
+
+        // The root's DS for example. no longer matches its key
+        let mut delegations = records(DELEGATIONS);
+        for record in &mut delegations {
+            if let Rdata::Ds { digest, .. } = &mut record.rdata {
+                digest[0] ^= 1;
+            }
+        }
+        let result = validator.validate_chain(
+            &parse_name("www.example"),
+            &answer,
+            &delegations,
+            &records(ANCHOR),
+        );
+        assert_eq!(result, Validation::Bogus);
+
+        // An address changed after signing
+        let mut tampered = answer.clone();
+        tampered[0].rdata = Rdata::A("192.0.2.3".parse().unwrap());
+        assert_eq!(
+            validate(&validator, "www.example", &tampered),
+            Validation::Bogus
+        );
+
+        // The signature stripped
+        let unsigned: Vec<ResourceRecord> = answer
+            .iter()
+            .filter(|record| record.rtype != QType::Rrsig)
+            .cloned()
+            .collect();
+        assert_eq!(
+            validate(&validator, "www.example", &unsigned),
+            Validation::Bogus
+        );
+
+        // Nothing at the name asked about
+        assert_eq!(
+            validate(&validator, "ftp.example", &answer),
+            Validation::Bogus
+        );
+    }
+
+    #[test]
+    fn signatures_outside_their_validity_period_are_bogus() {
+        let answer = records(ANSWER);
+        // 2019 and 2071
+        for seconds in [1_550_000_000, 3_170_000_000] {
+            let validator =
+                Validator::default().with_time(UNIX_EPOCH + Duration::from_secs(seconds));
+            assert_eq!(
+                validate(&validator, "www.example", &answer),
+                Validation::Bogus
+            );
+        }
+    }
+
+    #[test]
+    fn names_no_anchor_covers_are_insecure() {
+        let result = Validator::default().validate_chain(
+            &parse_name("www.example"),
+            &records(ANSWER),
+            &records(DELEGATIONS),
+            &[],
+        );
+        assert_eq!(result, Validation::Insecure);
+    }
+
+    // The answer with its signature marked as made with `algorithm`, which we can't check
+    fn signed_with(algorithm: u8) -> Vec<ResourceRecord> {
+        let mut answer = records(ANSWER);
+        for record in &mut answer {
+            if let Rdata::Rrsig { algorithm: a, .. } = &mut record.rdata {
+                *a = algorithm;
+            }
+        }
+        answer
+    }
+
+    #[test]
+    fn answers_only_signed_with_unknown_algorithms_are_insecure_unless_required() {
+        let lenient = Validator::default();
+        let strict = Validator::default().with_require_known_algorithms(true);
+        // Ed448 and a private algorithm
+        for algorithm in [16, 253] {
+            let answer = signed_with(algorithm);
+            assert_eq!(
+                validate(&lenient, "www.example", &answer),
+                Validation::Insecure
+            );
+            assert_eq!(validate(&strict, "www.example", &answer), Validation::Bogus);
+        }
+
+        // A signature we can check still decides, alongside one we can't
+        let mut answer = records(ANSWER);
+        answer.extend(
+            signed_with(253)
+                .into_iter()
+                .filter(|record| record.rtype == QType::Rrsig),
+        );
+        assert_eq!(
+            validate(&strict, "www.example", &answer),
+            Validation::Secure
+        );
+        answer[0].rdata = Rdata::A("192.0.2.3".parse().unwrap());
+        assert_eq!(
+            validate(&lenient, "www.example", &answer),
+            Validation::Bogus
+        );
+    }
+
+    #[test]
+    fn zones_only_vouched_for_with_unknown_digests_are_insecure_unless_required() {
+        // A SHA-384 digest of the root's key
+        let mut anchors = records(ANCHOR);
+        if let Rdata::Ds { digest_type, .. } = &mut anchors[0].rdata {
+            *digest_type = 4;
+        }
+        for (validator, expected) in [
+            (Validator::default(), Validation::Insecure),
+            (
+                Validator::default().with_require_known_algorithms(true),
+                Validation::Bogus,
+            ),
+        ] {
+            let result = validator.validate_chain(
+                &parse_name("www.example"),
+                &records(ANSWER),
+                &records(DELEGATIONS),
+                &anchors,
+            );
+            assert_eq!(result, expected);
+        }
+    }
+}
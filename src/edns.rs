@@ -0,0 +1,177 @@
+use std::time::Duration;
+
+use crate::error::ParseError;
+use crate::message::DNSMessage;
+use crate::record::{QClass, QType, Rdata, ResourceRecord};
+
+// The UDP payload size we advertise in our own OPT records unless configured otherwise, small
+// enough that messages of this size aren't fragmented on any common path
+// https://www.dnsflagday.net/2020/
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+// The smallest payload size that may be advertised, the limit for UDP messages without EDNS
+// https://www.rfc-editor.org/rfc/rfc6891#section-6.2.5
+pub const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+
+// The DO flag in an OPT record's TTL, set by clients that want DNSSEC records in answers
+// https://www.rfc-editor.org/rfc/rfc3225#section-3
+pub const DNSSEC_OK: u32 = 0x8000;
+
+// The flags in the low 16 bits of an OPT record's TTL other than DO, which are reserved and
+// should be zero, but may be given meanings in future
+// https://www.rfc-editor.org/rfc/rfc6891#section-6.1.4
+pub const RESERVED_FLAGS: u32 = 0x7FFF;
+
+// Option codes
+// https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-11
+pub const CLIENT_SUBNET: u16 = 8;
+pub const COOKIE: u16 = 10;
+pub const TCP_KEEPALIVE: u16 = 11;
+
+// EDNS(0) extends DNS with a single OPT pseudo-record in the additional section. Its CLASS
+// holds the sender's UDP payload size, its TTL the extended RCODE, version and flags, and its
+// RDATA a list of options each made of a 2 byte code, 2 byte length and the option data.
+// https://www.rfc-editor.org/rfc/rfc6891#section-6.1.2
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl EdnsOption {
+    pub(crate) fn parse_all(bytes: &[u8]) -> Result<Vec<Self>, ParseError> {
+        let mut options = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                return Err(ParseError::UnexpectedEnd);
+            }
+            let code = u16::from_be_bytes([rest[0], rest[1]]);
+            let len = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            let data = rest.get(4..4 + len).ok_or(ParseError::UnexpectedEnd)?;
+            options.push(EdnsOption {
+                code,
+                data: data.to_vec(),
+            });
+            rest = &rest[4 + len..];
+        }
+        Ok(options)
+    }
+
+    pub(crate) fn serialize_all(options: &[Self]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for option in options {
+            bytes.extend_from_slice(&option.code.to_be_bytes());
+            bytes.extend_from_slice(&(option.data.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&option.data);
+        }
+        bytes
+    }
+}
+
+/// Builds the OPT record we attach to responses for clients that sent one, advertising that we
+/// accept UDP messages of up to `payload_size` bytes.
+pub fn opt_record(payload_size: u16, options: Vec<EdnsOption>) -> ResourceRecord {
+    ResourceRecord {
+        name: Vec::new(),
+        rtype: QType::Opt,
+        class: QClass::Unknown(payload_size),
+        ttl: 0,
+        rdata: Rdata::Opt(options),
+    }
+}
+
+/// Whether the message has an OPT record holding an option with the given code.
+pub fn has_option(message: &DNSMessage, code: u16) -> bool {
+    message.edns().is_some_and(|opt| match &opt.rdata {
+        Rdata::Opt(options) => options.iter().any(|option| option.code == code),
+        _ => false,
+    })
+}
+
+/// Adds `option` to the message's OPT record, if it has one.
+pub fn add_option(message: &mut DNSMessage, option: EdnsOption) {
+    for record in &mut message.additional {
+        if let Rdata::Opt(options) = &mut record.rdata {
+            options.push(option);
+            return;
+        }
+    }
+}
+
+/// Builds a keepalive option advertising an idle timeout, which is given in units of 100ms.
+/// https://www.rfc-editor.org/rfc/rfc7828#section-3.1
+pub fn keepalive_option(timeout: Duration) -> EdnsOption {
+    let units = (timeout.as_millis() / 100).min(u16::MAX as u128) as u16;
+    EdnsOption {
+        code: TCP_KEEPALIVE,
+        data: units.to_be_bytes().to_vec(),
+    }
+}
+
+/// Removes any options with one of the given codes from the message's OPT record, leaving the
+/// rest of the record (payload size, DO bit and so on) untouched.
+pub fn strip_options(message: &mut DNSMessage, codes: &[u16]) {
+    for record in &mut message.additional {
+        if let Rdata::Opt(options) = &mut record.rdata {
+            options.retain(|option| !codes.contains(&option.code));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message_with_options(codes: &[u16]) -> DNSMessage {
+        let mut message = DNSMessage::from_bytes(&[0; 12]).unwrap();
+        let options = codes
+            .iter()
+            .map(|&code| EdnsOption {
+                code,
+                data: vec![0; 2],
+            })
+            .collect();
+        let mut opt = opt_record(4096, options);
+        // DO set
+        opt.ttl = 0x8000;
+        message.additional.push(opt);
+        message
+    }
+
+    #[test]
+    fn strip_options_only_removes_those_given() {
+        let mut message = message_with_options(&[CLIENT_SUBNET, COOKIE, CLIENT_SUBNET]);
+        strip_options(&mut message, &[CLIENT_SUBNET]);
+        let opt = message.edns().unwrap();
+        let Rdata::Opt(options) = &opt.rdata else {
+            panic!("not OPT: {:?}", opt.rdata);
+        };
+        let codes: Vec<u16> = options.iter().map(|option| option.code).collect();
+        assert_eq!(codes, [COOKIE]);
+        // The rest of the OPT record is left alone
+        assert_eq!(u16::from(opt.class), 4096);
+        assert_eq!(opt.ttl, 0x8000);
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let options = vec![
+            EdnsOption {
+                code: COOKIE,
+                data: vec![1; 8],
+            },
+            EdnsOption {
+                code: 65001,
+                data: Vec::new(),
+            },
+        ];
+        let bytes = EdnsOption::serialize_all(&options);
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(EdnsOption::parse_all(&bytes).unwrap(), options);
+        assert_eq!(
+            EdnsOption::parse_all(&bytes[..15]),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use thiserror::Error;
+
+use crate::header::ResponseCode;
+use crate::transport::TransportError;
+
+#[derive(Debug, Eq, PartialEq, Clone, Error)]
+pub enum ParseError {
+    #[error("unexpected end of data")]
+    UnexpectedEnd,
+    #[error("invalid label length byte {0:#04x}")]
+    InvalidLabel(u8),
+    #[error("compression pointer to offset {0} does not point backwards")]
+    InvalidPointer(usize),
+    #[error("name exceeds 255 bytes")]
+    NameTooLong,
+    #[error("RDATA length doesn't match the data for type {0}")]
+    RdataLength(u16),
+    #[error("message contains more than one OPT record")]
+    MultipleOpt,
+    #[error("OPT record outside the additional section")]
+    MisplacedOpt,
+    #[error("{0} bytes left over after the message")]
+    TrailingBytes(usize),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Error)]
+pub enum WriteError {
+    #[error("label of {0} bytes exceeds the 63 byte limit")]
+    LabelTooLong(usize),
+    #[error("character-string of {0} bytes exceeds the 255 byte limit")]
+    CharacterStringTooLong(usize),
+}
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("I/O error talking to upstream: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse upstream response: {0}")]
+    Parse(#[from] ParseError),
+    #[error("failed to serialize query: {0}")]
+    Write(#[from] WriteError),
+    #[error("timed out waiting for upstream response")]
+    Timeout,
+    #[error("upstream response has ID {0}, not that of our query")]
+    UnexpectedResponse(u16),
+    #[error("CNAME chain longer than {0} records")]
+    CnameChainTooLong(usize),
+    #[error("upstream refused the zone transfer with {0:?}")]
+    TransferRefused(ResponseCode),
+    #[error("zone transfer didn't start with the zone's SOA record")]
+    TransferMissingSoa,
+}
+
+impl From<TransportError> for ResolveError {
+    fn from(error: TransportError) -> Self {
+        match error {
+            TransportError::Io(e) => ResolveError::Io(e),
+            TransportError::Timeout => ResolveError::Timeout,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Error)]
+pub enum ZoneError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("zone has no SOA record at its origin")]
+    MissingSoa,
+}
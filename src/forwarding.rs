@@ -0,0 +1,39 @@
+use crate::name::is_subdomain;
+use crate::resolver::StubResolver;
+
+// Which upstream each query is forwarded to. Queries for names under one of the rules' suffixes
+// go to that rule's upstream, e.g. corp.internal to an internal resolver, and everything else to
+// the default upstream if there is one.
+#[derive(Default)]
+pub struct ForwardingRules {
+    default: Option<StubResolver>,
+    rules: Vec<(Vec<Vec<u8>>, StubResolver)>,
+}
+
+impl ForwardingRules {
+    /// Sets the upstream for names no rule matches, replacing any previous default.
+    pub fn set_default(&mut self, resolver: StubResolver) {
+        self.default = Some(resolver);
+    }
+
+    /// Forwards queries for `suffix` and every name below it to `resolver`.
+    pub fn add_rule(&mut self, suffix: Vec<Vec<u8>>, resolver: StubResolver) {
+        self.rules.push((suffix, resolver));
+    }
+
+    /// Whether any upstream at all is configured.
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.rules.is_empty()
+    }
+
+    /// The upstream to forward a query for `name` to: that of the most specific rule matching
+    /// it, otherwise the default.
+    pub fn resolver_for(&self, name: &[Vec<u8>]) -> Option<&StubResolver> {
+        self.rules
+            .iter()
+            .filter(|(suffix, _)| is_subdomain(name, suffix))
+            .max_by_key(|(suffix, _)| suffix.len())
+            .map(|(_, resolver)| resolver)
+            .or(self.default.as_ref())
+    }
+}
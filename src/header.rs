@@ -0,0 +1,337 @@
+use std::fmt;
+
+use crate::error::ParseError;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum OpCode {
+    Query,
+    IQuery,
+    Status,
+    // Any other opcode, such as NOTIFY or UPDATE, kept so the query can still be answered
+    // NOTIMP rather than dropped as unparseable
+    Unknown(u8),
+}
+
+impl From<u8> for OpCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => OpCode::Query,
+            1 => OpCode::IQuery,
+            2 => OpCode::Status,
+            other => OpCode::Unknown(other),
+        }
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(value: OpCode) -> Self {
+        match value {
+            OpCode::Query => 0,
+            OpCode::IQuery => 1,
+            OpCode::Status => 2,
+            OpCode::Unknown(other) => other,
+        }
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpCode::Query => write!(f, "QUERY"),
+            OpCode::IQuery => write!(f, "IQUERY"),
+            OpCode::Status => write!(f, "STATUS"),
+            OpCode::Unknown(other) => write!(f, "OPCODE{}", other),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ResponseCode {
+    NoError,
+    FormError,
+    ServFail,
+    NxDomain,
+    // The server doesn't support this kind of query, i.e. its opcode
+    NotImp,
+    // The server won't answer this query, e.g. it would have to recurse and the client asked it
+    // not to
+    Refused,
+    // A name that should not exist does, e.g. one synthesized from a DNAME is too long
+    YxDomain,
+    // Any other code, kept so that it can be passed on as the upstream sent it
+    Unknown(u8),
+}
+
+impl From<u8> for ResponseCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormError,
+            2 => ResponseCode::ServFail,
+            3 => ResponseCode::NxDomain,
+            4 => ResponseCode::NotImp,
+            5 => ResponseCode::Refused,
+            6 => ResponseCode::YxDomain,
+            other => ResponseCode::Unknown(other),
+        }
+    }
+}
+
+impl From<ResponseCode> for u8 {
+    fn from(value: ResponseCode) -> Self {
+        match value {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormError => 1,
+            ResponseCode::ServFail => 2,
+            ResponseCode::NxDomain => 3,
+            ResponseCode::NotImp => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::YxDomain => 6,
+            ResponseCode::Unknown(other) => other,
+        }
+    }
+}
+
+// Shown by their mnemonics, as dig does
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResponseCode::NoError => write!(f, "NOERROR"),
+            ResponseCode::FormError => write!(f, "FORMERR"),
+            ResponseCode::ServFail => write!(f, "SERVFAIL"),
+            ResponseCode::NxDomain => write!(f, "NXDOMAIN"),
+            ResponseCode::NotImp => write!(f, "NOTIMP"),
+            ResponseCode::Refused => write!(f, "REFUSED"),
+            ResponseCode::YxDomain => write!(f, "YXDOMAIN"),
+            ResponseCode::Unknown(other) => write!(f, "RCODE{}", other),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum QueryOrReply {
+    Query = 0,
+    Reply = 1,
+}
+
+impl TryFrom<u8> for QueryOrReply {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(QueryOrReply::Query),
+            1 => Ok(QueryOrReply::Reply),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Header {
+    // id 2 bytes
+    pub id: u16,
+    // Flags section - 2 bytes
+    // Indicates if the message is a query (0) or a reply (1)
+    pub header_flags: HeaderFlags,
+    // each count 2 bytes
+    pub question_count: u16,
+    pub answer_record_count: u16,
+    pub authority_record_count: u16,
+    pub additional_record_count: u16,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct HeaderFlags {
+    pub qr: QueryOrReply,
+    pub op_code: OpCode,
+    pub authoritative_answer: bool,
+    pub truncation: bool,
+    pub recursion_desired: bool,
+    pub recursion_available: bool,
+    pub response_code: ResponseCode,
+}
+
+// The flags that are set, by their lowercase names in header order, e.g. "qr aa rd ra" as on
+// dig's flags line
+impl fmt::Display for HeaderFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [
+            ("qr", self.qr == QueryOrReply::Reply),
+            ("aa", self.authoritative_answer),
+            ("tc", self.truncation),
+            ("rd", self.recursion_desired),
+            ("ra", self.recursion_available),
+        ];
+        let set: Vec<&str> = flags
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| *name)
+            .collect();
+        write!(f, "{}", set.join(" "))
+    }
+}
+
+impl Header {
+    fn extract_flags(bytes: &[u8]) -> Result<HeaderFlags, ParseError> {
+        // The Flags section is a 2 byte long section consisting of bools from single bits
+        // and 2 0.5 byte op codes
+
+        let flags1 = bytes[2];
+        let qr = (flags1 >> 7) & 0b1;
+        let op_code = (flags1 & 0b0111_1000) >> 3;
+        let authoritative_answer = (flags1 & 0b0000_0100) != 0;
+        let truncation = (flags1 & 0b0000_0010) != 0;
+        let recursion_desired = (flags1 & 0b0000_0001) != 0;
+
+        // Fourth byte contains remaining flags
+        let flags2 = bytes[3];
+        let recursion_available = (flags2 & 0b1000_0000) != 0;
+        // Reserved / unused - assume 0 on serialize
+        let _ = (flags2 & 0b0111_0000) >> 4;
+        let response_code = flags2 & 0b0000_1111;
+
+        let op_code = OpCode::from(op_code);
+        let response_code = ResponseCode::from(response_code);
+        let qr = QueryOrReply::try_from(qr).unwrap();
+
+        Ok(HeaderFlags {
+            qr,
+            op_code,
+            authoritative_answer,
+            truncation,
+            recursion_desired,
+            recursion_available,
+            response_code,
+        })
+    }
+
+    pub fn new(bytes: &[u8]) -> Result<Self, ParseError> {
+        debug_assert!(bytes.len() == 12);
+        let id = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let header_flags = Self::extract_flags(bytes)?;
+        let question_count = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let answer_record_count = u16::from_be_bytes([bytes[6], bytes[7]]);
+        let authority_record_count = u16::from_be_bytes([bytes[8], bytes[9]]);
+        let additional_record_count = u16::from_be_bytes([bytes[10], bytes[11]]);
+        Ok(Header {
+            id,
+            header_flags,
+            question_count,
+            answer_record_count,
+            authority_record_count,
+            additional_record_count,
+        })
+    }
+
+    /// Builds the header for a reply to this query.
+    ///
+    /// Only the fields a reply inherits from the query (ID, opcode, RD and the counts) are
+    /// copied. AA must only be set when the answer comes from a zone we are authoritative
+    /// for, so it is passed in by the caller rather than echoed from the query; referrals,
+    /// forwarded answers and cache hits must all pass `false`.
+    pub fn reply(&self, authoritative: bool) -> Header {
+        Header {
+            id: self.id,
+            header_flags: HeaderFlags {
+                qr: QueryOrReply::Reply,
+                op_code: self.header_flags.op_code.clone(),
+                authoritative_answer: authoritative,
+                truncation: false,
+                recursion_desired: self.header_flags.recursion_desired,
+                recursion_available: false,
+                response_code: ResponseCode::NoError,
+            },
+            question_count: self.question_count,
+            answer_record_count: self.answer_record_count,
+            authority_record_count: self.authority_record_count,
+            additional_record_count: self.additional_record_count,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+
+        // Serialize ID (16bits)
+        bytes[0..2].copy_from_slice(&self.id.to_be_bytes());
+
+        // Serialize flags (16bits)
+        let mut flags: u16 = 0;
+        flags |= (self.header_flags.qr.clone() as u16) << 15; // bit 15
+        flags |= (u8::from(self.header_flags.op_code.clone()) as u16 & 0xF) << 11; // bit 14-11
+        flags |= (self.header_flags.authoritative_answer as u16) << 10; // bit 10
+        flags |= (self.header_flags.truncation as u16) << 9; // bit 9
+        flags |= (self.header_flags.recursion_desired as u16) << 8; // bit 8
+        flags |= (self.header_flags.recursion_available as u16) << 7; // bit 7 (bit 6-4 are reserved and left as 0)
+        flags |= u8::from(self.header_flags.response_code.clone()) as u16 & 0xF; // bit 3-0 (ensure only the lowest 4bits assigned is used)
+        bytes[2..4].copy_from_slice(&flags.to_be_bytes());
+
+        // Serialize counts
+        bytes[4..6].copy_from_slice(&self.question_count.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.answer_record_count.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.authority_record_count.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.additional_record_count.to_be_bytes());
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_header() -> Header {
+        Header {
+            id: 0x1234,
+            header_flags: HeaderFlags {
+                qr: QueryOrReply::Query,
+                op_code: OpCode::Query,
+                authoritative_answer: true,
+                truncation: true,
+                recursion_desired: true,
+                recursion_available: true,
+                response_code: ResponseCode::NoError,
+            },
+            question_count: 1,
+            answer_record_count: 0,
+            authority_record_count: 0,
+            additional_record_count: 0,
+        }
+    }
+
+    #[test]
+    fn reply_sets_aa_only_when_authoritative() {
+        let query = query_header();
+        let reply = query.reply(false);
+        assert_eq!(reply.id, 0x1234);
+        assert_eq!(reply.header_flags.qr, QueryOrReply::Reply);
+        assert!(reply.header_flags.recursion_desired);
+        // Nothing the client set is echoed back but RD
+        assert!(!reply.header_flags.authoritative_answer);
+        assert!(!reply.header_flags.truncation);
+        assert!(!reply.header_flags.recursion_available);
+
+        assert!(query.reply(true).header_flags.authoritative_answer);
+    }
+
+    #[test]
+    fn reply_serializes_aa_bit() {
+        let query = query_header();
+        assert_eq!(query.reply(false).to_bytes()[2] & 0b0000_0100, 0);
+        assert_eq!(query.reply(true).to_bytes()[2] & 0b0000_0100, 0b0000_0100);
+    }
+
+    #[test]
+    fn unknown_opcodes_and_rcodes_are_kept() {
+        let mut header = query_header();
+        // NOTIFY, and BADVERS' low bits
+        header.header_flags.op_code = OpCode::Unknown(4);
+        header.header_flags.response_code = ResponseCode::Unknown(9);
+        let parsed = Header::new(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.header_flags.op_code, OpCode::Unknown(4));
+        assert_eq!(parsed.header_flags.response_code, ResponseCode::Unknown(9));
+
+        assert_eq!(OpCode::from(5), OpCode::Unknown(5));
+        assert_eq!(ResponseCode::from(4), ResponseCode::NotImp);
+        assert_eq!(u8::from(ResponseCode::NotImp), 4);
+    }
+}
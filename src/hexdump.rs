@@ -0,0 +1,54 @@
+use std::fmt::Write;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Formats `bytes` for debugging wire format problems: one line per 16 bytes, each with its
+/// offset, the bytes in hex split into two groups of 8, and the bytes as ASCII with anything
+/// unprintable shown as `.`.
+///
+/// ```text
+/// 0000  12 34 01 00 00 01 00 00  00 00 00 00 01 78 01 79  .4...........x.y
+/// ```
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        write!(dump, "{:04x} ", line * BYTES_PER_LINE).unwrap();
+        for i in 0..BYTES_PER_LINE {
+            if i == BYTES_PER_LINE / 2 {
+                dump.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => write!(dump, " {:02x}", byte).unwrap(),
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  ");
+        dump.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push('\n');
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_show_offset_hex_and_ascii() {
+        let mut bytes = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00];
+        bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x01, b'x', 0x01, b'y']);
+        bytes.extend_from_slice(b"\x03com\x00");
+        let expected = "\
+0000  12 34 01 00 00 01 00 00  00 00 00 00 01 78 01 79  .4...........x.y
+0010  03 63 6f 6d 00                                    .com.
+";
+        assert_eq!(hexdump(&bytes), expected);
+        assert_eq!(hexdump(&[]), "");
+    }
+}
@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU16, Ordering};
+
+// Source of the IDs put on outgoing queries
+pub trait IdGenerator: Send + Sync {
+    fn next_id(&self) -> u16;
+}
+
+// Unpredictable IDs, so an off-path attacker has to guess them to spoof a response
+#[derive(Debug, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_id(&self) -> u16 {
+        rand::random()
+    }
+}
+
+// IDs counting up from a starting value, for tests that need to know the bytes that go on the
+// wire. Never use this outside of tests, as the IDs are trivially guessable.
+#[derive(Debug)]
+pub struct SequentialIds {
+    next: AtomicU16,
+}
+
+impl SequentialIds {
+    pub fn new(start: u16) -> Self {
+        SequentialIds {
+            next: AtomicU16::new(start),
+        }
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&self) -> u16 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential_ids_count_up_and_wrap() {
+        let ids = SequentialIds::new(u16::MAX - 1);
+        assert_eq!(ids.next_id(), u16::MAX - 1);
+        assert_eq!(ids.next_id(), u16::MAX);
+        assert_eq!(ids.next_id(), 0);
+    }
+}
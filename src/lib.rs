@@ -0,0 +1,31 @@
+// DNS wire format types and query handling used by the server binary.
+mod base32hex;
+mod base64;
+pub mod cache;
+mod chaos;
+pub mod config;
+pub mod dnssec;
+pub mod edns;
+pub mod error;
+pub mod forwarding;
+pub mod header;
+pub mod hexdump;
+pub mod id;
+pub mod message;
+pub mod metrics;
+pub mod name;
+mod pktinfo;
+pub mod question;
+mod reader;
+pub mod record;
+pub mod resolver;
+mod rsa;
+pub mod selftest;
+pub mod server;
+mod sha256;
+pub mod tcp;
+pub mod transport;
+pub mod udp;
+mod writer;
+pub mod zone;
+pub mod zone_file;
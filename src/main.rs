@@ -1,205 +1,444 @@
 // Uncomment this block to pass the first stage
-use std::net::UdpSocket;
-
-// All communications in the DNS protocol are carried in a single format called a "message".
-// Each message consists of 5 sections: header, question, answer, authority, and an additional space.
-// https://en.wikipedia.org/wiki/Domain_Name_System#DNS_message_format
-struct DNSMessage {
-    header: Header,
-    // question: Question,
-    // answer: Answer,
-    // authority: Authority,
-    // additional: &str
-}
-
-#[derive(Debug, Eq, PartialEq, Clone)]
-enum OpCode {
-    Query = 0,
-    IQuery = 1,
-    Status = 2,
-}
-
-impl TryFrom<u8> for OpCode {
-    type Error = ();
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(OpCode::Query),
-            1 => Ok(OpCode::IQuery),
-            2 => Ok(OpCode::Status),
-            _ => Err(()),
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns_starter_rust::config::{AnyResponse, Config, DebugDelay, ForwardZone};
+use dns_starter_rust::edns;
+use dns_starter_rust::metrics;
+use dns_starter_rust::name;
+use dns_starter_rust::selftest;
+use dns_starter_rust::server::Server;
+use dns_starter_rust::tcp;
+use dns_starter_rust::udp;
+use dns_starter_rust::zone::Zone;
+use dns_starter_rust::zone_file;
+
+// How a single query is read from stdin, and its reply written to stdout
+enum StdinFormat {
+    // The message bytes as-is
+    Raw,
+    // The message bytes as hex digits, ignoring any whitespace
+    Hex,
+}
+
+struct Args {
+    config: Config,
+    // Addresses to answer queries on, over both UDP and TCP
+    listen: Vec<SocketAddr>,
+    // Where to serve Prometheus metrics over HTTP, if anywhere
+    metrics: Option<SocketAddr>,
+    // Zones to serve, as (origin, path to master file)
+    zones: Vec<(String, String)>,
+    // Answer one query from stdin instead of listening on a socket
+    stdin: Option<StdinFormat>,
+}
+
+fn parse_args() -> Args {
+    let mut config = Config::default();
+    let mut stdin = None;
+    let mut zones = Vec::new();
+    let mut listen = Vec::new();
+    let mut metrics = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--identity" => match args.next() {
+                Some(identity) => config.identity = identity,
+                None => usage_error("--identity requires a value"),
+            },
+            "--resolver" => match args.next().map(|addr| addr.parse()) {
+                Some(Ok(addr)) => config.resolver = Some(addr),
+                Some(Err(_)) => usage_error("--resolver must be an <ip>:<port> address"),
+                None => usage_error("--resolver requires a value"),
+            },
+            "--cache-min-ttl" => match args.next().map(|ttl| ttl.parse()) {
+                Some(Ok(ttl)) => config.cache_min_ttl = ttl,
+                _ => usage_error("--cache-min-ttl must be a number of seconds"),
+            },
+            "--cache-max-ttl" => match args.next().map(|ttl| ttl.parse()) {
+                Some(Ok(ttl)) => config.cache_max_ttl = ttl,
+                _ => usage_error("--cache-max-ttl must be a number of seconds"),
+            },
+            "--cache-negative-max-ttl" => match args.next().map(|ttl| ttl.parse()) {
+                Some(Ok(ttl)) => config.cache_negative_max_ttl = ttl,
+                _ => usage_error("--cache-negative-max-ttl must be a number of seconds"),
+            },
+            "--cache-max-entries" => match args.next().map(|max| max.parse()) {
+                Some(Ok(max)) if max > 0 => config.cache_max_entries = max,
+                _ => usage_error("--cache-max-entries must be a positive number of responses"),
+            },
+            "--max-cname-chain" => match args.next().map(|max| max.parse()) {
+                Some(Ok(max)) => config.max_cname_chain = max,
+                _ => usage_error("--max-cname-chain must be a number of CNAMEs"),
+            },
+            "--forward" => match args.next().as_deref().and_then(parse_forward_zone) {
+                Some(zone) => config.forward_zones.push(zone),
+                None => usage_error("--forward must be given as <domain>=<ip:port>"),
+            },
+            "--strip-edns-option" => match args.next().as_deref() {
+                Some("ecs") => config.strip_edns_options.push(edns::CLIENT_SUBNET),
+                Some("cookie") => config.strip_edns_options.push(edns::COOKIE),
+                Some(code) => match code.parse() {
+                    Ok(code) => config.strip_edns_options.push(code),
+                    Err(_) => usage_error("--strip-edns-option must be ecs, cookie or a code"),
+                },
+                None => usage_error("--strip-edns-option requires a value"),
+            },
+            "--zone" => match args.next().as_deref().and_then(|zone| zone.split_once('=')) {
+                Some((origin, path)) => zones.push((origin.to_string(), path.to_string())),
+                None => usage_error("--zone must be given as <origin>=<path>"),
+            },
+            "--static-response" => match args.next() {
+                Some(path) => config.static_response = Some(load_static_response(&path)),
+                None => usage_error("--static-response requires a path"),
+            },
+            "--debug-delay" => match args.next().as_deref().and_then(parse_debug_delay) {
+                Some(delay) => config.debug_delays.push(delay),
+                None => usage_error("--debug-delay must be given as [<name>=]<milliseconds>"),
+            },
+            "--load-shed-threshold" => match args.next().map(|threshold| threshold.parse()) {
+                Some(Ok(threshold)) => config.load_shed_threshold = Some(threshold),
+                _ => usage_error("--load-shed-threshold must be a number of queries"),
+            },
+            "--no-compression" => config.disable_compression = true,
+            "--forward-without-rd" => config.forward_without_rd = true,
+            "--hexdump" => config.hexdump = true,
+            "--sort-by-preference" => config.sort_by_preference = true,
+            "--tcp-idle-timeout" => match args.next().map(|secs| secs.parse()) {
+                Some(Ok(secs)) if secs > 0 => config.tcp_idle_timeout = Duration::from_secs(secs),
+                _ => usage_error("--tcp-idle-timeout must be a positive number of seconds"),
+            },
+            "--tcp-max-session" => match args.next().map(|secs| secs.parse()) {
+                Some(Ok(secs)) if secs > 0 => config.tcp_max_session = Duration::from_secs(secs),
+                _ => usage_error("--tcp-max-session must be a positive number of seconds"),
+            },
+            "--tcp-max-connections" => match args.next().map(|max| max.parse()) {
+                Some(Ok(max)) if max > 0 => config.tcp_max_connections = max,
+                _ => usage_error("--tcp-max-connections must be a positive number"),
+            },
+            "--tcp-max-in-flight" => match args.next().map(|max| max.parse()) {
+                Some(Ok(max)) if max > 0 => config.tcp_max_in_flight = max,
+                _ => usage_error("--tcp-max-in-flight must be a positive number of queries"),
+            },
+            "--udp-max-in-flight" => match args.next().map(|max| max.parse()) {
+                Some(Ok(max)) if max > 0 => config.udp_max_in_flight = max,
+                _ => usage_error("--udp-max-in-flight must be a positive number of queries"),
+            },
+            "--udp-payload-size" => match args.next().map(|size| size.parse()) {
+                Some(Ok(size)) if size >= edns::MIN_UDP_PAYLOAD_SIZE => {
+                    config.udp_payload_size = size
+                }
+                _ => usage_error("--udp-payload-size must be a number of bytes from 512 to 65535"),
+            },
+            "--minimal-any" => match args.next().as_deref() {
+                Some("hinfo") => config.any_response = AnyResponse::Hinfo,
+                Some("record") => config.any_response = AnyResponse::SingleRecord,
+                _ => usage_error("--minimal-any must be either hinfo or record"),
+            },
+            "--amplification-threshold" => match args.next().map(|factor| factor.parse()) {
+                Some(Ok(factor)) => config.amplification_threshold = factor,
+                _ => usage_error("--amplification-threshold must be a number"),
+            },
+            "--listen" => match args.next().map(|addr| addr.parse::<SocketAddr>()) {
+                // Replies must leave from the address the query came in on, which a wildcard
+                // socket can only promise where it can learn that address; see udp::serve
+                Some(Ok(addr)) if addr.ip().is_unspecified() && !cfg!(target_os = "linux") => {
+                    usage_error("--listen needs a specific address, not 0.0.0.0 or [::]")
+                }
+                Some(Ok(addr)) => listen.push(addr),
+                Some(Err(_)) => usage_error("--listen must be an <ip>:<port> address"),
+                None => usage_error("--listen requires a value"),
+            },
+            "--metrics" => match args.next().map(|addr| addr.parse()) {
+                Some(Ok(addr)) => metrics = Some(addr),
+                Some(Err(_)) => usage_error("--metrics must be an <ip>:<port> address"),
+                None => usage_error("--metrics requires a value"),
+            },
+            "--stdin" => match args.next().as_deref() {
+                Some("raw") => stdin = Some(StdinFormat::Raw),
+                Some("hex") => stdin = Some(StdinFormat::Hex),
+                _ => usage_error("--stdin must be either raw or hex"),
+            },
+            "--selftest" => run_selftest(),
+            other => usage_error(&format!("Unknown argument {}", other)),
         }
     }
+    if listen.is_empty() {
+        listen.push(SocketAddr::from(([127, 0, 0, 1], 2053)));
+    }
+    Args {
+        config,
+        listen,
+        metrics,
+        zones,
+        stdin,
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-enum ResponseCode {
-    NoError = 0,
-    FormError = 1,
-    ServFail = 2,
-    NxDomain = 3,
+fn parse_forward_zone(arg: &str) -> Option<ForwardZone> {
+    let (suffix, upstream) = arg.split_once('=')?;
+    Some(ForwardZone {
+        suffix: name::parse_name(suffix),
+        upstream: upstream.parse().ok()?,
+    })
 }
 
-impl TryFrom<u8> for ResponseCode {
-    type Error = ();
+// Either a delay in milliseconds for every query, or <name>=<milliseconds> for just one name
+fn parse_debug_delay(arg: &str) -> Option<DebugDelay> {
+    let (name, millis) = match arg.split_once('=') {
+        Some((name, millis)) => (Some(name::parse_name(name)), millis),
+        None => (None, arg),
+    };
+    let delay = Duration::from_millis(millis.parse().ok()?);
+    Some(DebugDelay { name, delay })
+}
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(ResponseCode::NoError),
-            1 => Ok(ResponseCode::FormError),
-            2 => Ok(ResponseCode::ServFail),
-            3 => Ok(ResponseCode::NxDomain),
-            _ => Err(()),
-        }
+fn load_static_response(path: &str) -> Vec<u8> {
+    let response = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read static response {}: {}", path, e);
+        process::exit(1);
+    });
+    if response.len() < 12 {
+        eprintln!("Static response {} is too short to hold a header", path);
+        process::exit(1);
     }
+    response
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-enum QueryOrReply {
-    Query = 0,
-    Reply = 1,
+// Checks the built in vectors parse and serialize correctly, exiting nonzero if any don't
+fn run_selftest() -> ! {
+    let passed = check_vectors(selftest::VECTORS);
+    process::exit(if passed { 0 } else { 1 });
 }
 
-impl TryFrom<u8> for QueryOrReply {
-    type Error = ();
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(QueryOrReply::Query),
-            1 => Ok(QueryOrReply::Reply),
-            _ => Err(()),
+// Checks each of `vectors`, reporting on each, and returns whether they all passed
+fn check_vectors(vectors: &[selftest::Vector]) -> bool {
+    let mut passed = true;
+    for vector in vectors {
+        match selftest::check(vector) {
+            Ok(()) => println!("ok   {}", vector.name),
+            Err(e) => {
+                println!("FAIL {}: {}", vector.name, e);
+                passed = false;
+            }
         }
     }
+    passed
 }
 
-#[derive(Debug, Eq, PartialEq)]
-struct Header {
-    // id 2 bytes
-    id: u16,
-    // Flags section - 2 bytes
-    // Indicates if the message is a query (0) or a reply (1)
-    header_flags: HeaderFlags,
-    // each count 2 bytes
-    question_count: u16,
-    answer_record_count: u16,
-    authority_record_count: u16,
-    additional_record_count: u16,
-}
-
-#[derive(Debug, Eq, PartialEq)]
-struct HeaderFlags {
-    qr: QueryOrReply,
-    op_code: OpCode,
-    authoritative_answer: bool,
-    truncation: bool,
-    recursion_desired: bool,
-    recursion_available: bool,
-    response_code: ResponseCode,
-}
-
-impl Header {
-    fn extract_flags(bytes: &[u8]) -> HeaderFlags {
-        // The Flags section is a 2 byte long section consisting of bools from single bits
-        // and 2 0.5 byte op codes
-
-        let flags1 = bytes[2];
-        let qr = (flags1 >> 7) & 0b1;
-        let op_code = (flags1 & 0b0111_1000) >> 3;
-        let authoritative_answer = (flags1 & 0b0000_0100) != 0;
-        let truncation = (flags1 & 0b0000_0010) != 0;
-        let recursion_desired = (flags1 & 0b0000_0001) != 0;
-
-        // Fourth byte contains remaining flags
-        let flags2 = bytes[3];
-        let recursion_available = (flags2 & 0b1000_0000) != 0;
-        // Reserved / unused - assume 0 on serialize
-        let _ = (flags2 & 0b0111_0000) >> 4;
-        let response_code = flags2 & 0b0000_1111;
-
-        let op_code = OpCode::try_from(op_code).unwrap();
-        let response_code = ResponseCode::try_from(response_code).unwrap();
-        let qr = QueryOrReply::try_from(qr).unwrap();
-
-        HeaderFlags {
-            qr,
-            op_code,
-            authoritative_answer,
-            truncation,
-            recursion_desired,
-            recursion_available,
-            response_code,
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!(
+        "Usage: dns-starter-rust [--identity <name>] [--resolver <ip:port>] \
+         [--cache-min-ttl <seconds>] [--cache-max-ttl <seconds>] \
+         [--cache-negative-max-ttl <seconds>] [--cache-max-entries <n>] \
+         [--load-shed-threshold <n>] \
+         [--forward <domain>=<ip:port>]... [--strip-edns-option ecs|cookie|<code>]... \
+         [--zone <origin>=<path>]... [--debug-delay [<name>=]<milliseconds>]... \
+         [--static-response <path>] [--no-compression] [--minimal-any hinfo|record] \
+         [--forward-without-rd] [--listen <ip:port>]... [--metrics <ip:port>] \
+         [--amplification-threshold <factor>] [--stdin raw|hex] [--hexdump] \
+         [--udp-payload-size <bytes>] [--udp-max-in-flight <n>] [--tcp-idle-timeout <seconds>] \
+         [--tcp-max-session <seconds>] [--tcp-max-connections <n>] \
+         [--tcp-max-in-flight <n>] [--sort-by-preference] [--max-cname-chain <n>] [--selftest]"
+    );
+    process::exit(2);
+}
+
+fn main() {
+    let args = parse_args();
+    let mut server = Server::new(args.config);
+    for (origin, path) in &args.zones {
+        server = server.with_zone(load_zone_file(origin, path));
+    }
+    let server = Arc::new(server);
+    match args.stdin {
+        Some(format) => serve_stdin(&server, format),
+        None => {
+            if let Some(addr) = args.metrics {
+                let metrics_listener =
+                    TcpListener::bind(addr).expect("Failed to bind metrics listener");
+                let metrics_server = server.clone();
+                thread::spawn(move || metrics::serve(metrics_listener, metrics_server));
+            }
+            serve_sockets(&server, &args.listen);
         }
     }
+}
 
-    fn new(bytes: &[u8]) -> Self {
-        debug_assert!(bytes.len() == 12);
-        let id = u16::from_be_bytes([bytes[0], bytes[1]]);
-        let header_flags = Self::extract_flags(bytes);
-        let question_count = u16::from_be_bytes([bytes[4], bytes[5]]);
-        let answer_record_count = u16::from_be_bytes([bytes[6], bytes[7]]);
-        let authority_record_count = u16::from_be_bytes([bytes[8], bytes[9]]);
-        let additional_record_count = u16::from_be_bytes([bytes[10], bytes[11]]);
-        Header {
-            id,
-            header_flags,
-            question_count,
-            answer_record_count,
-            authority_record_count,
-            additional_record_count,
-        }
+fn load_zone_file(origin: &str, path: &str) -> Zone {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read zone file {}: {}", path, e);
+        process::exit(1);
+    });
+    zone_file::load_zone(&text, &name::parse_name(origin)).unwrap_or_else(|e| {
+        eprintln!("Failed to load zone {} from {}: {}", origin, path, e);
+        process::exit(1);
+    })
+}
+
+// Answers queries on every address that can be bound, over UDP and TCP. Startup only fails if
+// nothing at all could be bound.
+fn serve_sockets(server: &Arc<Server>, addrs: &[SocketAddr]) {
+    let (udp_sockets, tcp_listeners) = bind_all(addrs);
+    if udp_sockets.is_empty() && tcp_listeners.is_empty() {
+        eprintln!("Failed to bind any address to listen on");
+        process::exit(1);
+    }
+    let mut threads = Vec::new();
+    for udp_socket in udp_sockets {
+        let udp_server = server.clone();
+        threads.push(thread::spawn(move || udp::serve(udp_socket, udp_server)));
+    }
+    for tcp_listener in tcp_listeners {
+        let tcp_server = server.clone();
+        threads.push(thread::spawn(move || tcp::serve(tcp_listener, tcp_server)));
+    }
+    for thread in threads {
+        thread.join().expect("Listener thread panicked");
     }
+}
 
-    fn to_bytes(&self) -> [u8; 12] {
-        let mut bytes = [0u8; 12];
+// Binds a UDP socket and a TCP listener on each of `addrs`, logging each that is bound and each
+// that can't be
+fn bind_all(addrs: &[SocketAddr]) -> (Vec<UdpSocket>, Vec<TcpListener>) {
+    let mut udp_sockets = Vec::new();
+    let mut tcp_listeners = Vec::new();
+    for &addr in addrs {
+        // Replies must leave from the address their query arrived on, or clients drop them. A
+        // socket bound to a specific address always sends from it; one bound to a wildcard
+        // address is only allowed where udp::serve can send from the query's address.
+        match UdpSocket::bind(addr) {
+            Ok(udp_socket) => {
+                eprintln!("Listening for UDP queries on {}", addr);
+                udp_sockets.push(udp_socket);
+            }
+            Err(e) => eprintln!("Failed to bind UDP socket on {}: {}", addr, e),
+        }
+        match TcpListener::bind(addr) {
+            Ok(tcp_listener) => {
+                eprintln!("Listening for TCP queries on {}", addr);
+                tcp_listeners.push(tcp_listener);
+            }
+            Err(e) => eprintln!("Failed to bind TCP listener on {}: {}", addr, e),
+        }
+    }
+    (udp_sockets, tcp_listeners)
+}
 
-        // Serialize ID (16bits)
-        bytes[0..2].copy_from_slice(&self.id.to_be_bytes());
+// Answers a single query read from stdin, writing the reply to stdout so the server can be
+// scripted without any sockets. Logging goes to stderr so stdout only ever holds the reply.
+fn serve_stdin(server: &Server, format: StdinFormat) {
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .expect("Failed to read stdin");
+    let query = match format {
+        StdinFormat::Raw => input,
+        StdinFormat::Hex => match decode_hex(&input) {
+            Some(query) => query,
+            None => {
+                eprintln!("stdin is not valid hex");
+                process::exit(2);
+            }
+        },
+    };
 
-        // Serialize flags (16bits)
-        let mut flags: u16 = 0;
-        flags |= (self.header_flags.qr.clone() as u16) << 15; // bit 15
-        flags |= (self.header_flags.op_code.clone() as u16) << 11; // bit 14-11
-        flags |= (self.header_flags.authoritative_answer as u16) << 10; // bit 10
-        flags |= (self.header_flags.truncation as u16) << 9; // bit 9
-        flags |= (self.header_flags.recursion_desired as u16) << 8; // bit 8
-        flags |= (self.header_flags.recursion_available as u16) << 7; // bit 7
-        flags |= ((0_u16) & 0x7) << 4; // bit 6-4 (ensure only the lowest 3bits assigned is used)
-        flags |= (self.header_flags.response_code.clone() as u16) & 0xF; // bit 3-0 (ensure only the lowest 4bits assigned is used)
-        bytes[2..4].copy_from_slice(&flags.to_be_bytes());
+    let Some(response) = server.handle(&query) else {
+        eprintln!("Query was dropped without a reply");
+        process::exit(1);
+    };
+    let output = match format {
+        StdinFormat::Raw => response,
+        StdinFormat::Hex => encode_hex(&response),
+    };
+    io::stdout()
+        .write_all(&output)
+        .expect("Failed to write stdout");
+}
 
-        // Serialize counts
-        bytes[4..6].copy_from_slice(&self.question_count.to_be_bytes());
-        bytes[6..8].copy_from_slice(&self.answer_record_count.to_be_bytes());
-        bytes[8..10].copy_from_slice(&self.authority_record_count.to_be_bytes());
-        bytes[10..12].copy_from_slice(&self.additional_record_count.to_be_bytes());
+// Lowercase hex digits for `bytes`, ending in a newline
+fn encode_hex(bytes: &[u8]) -> Vec<u8> {
+    let mut hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.push('\n');
+    hex.into_bytes()
+}
 
-        bytes
+fn decode_hex(input: &[u8]) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
     }
+    pairs
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
 }
 
-fn main() {
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    let mut buf = [0; 512];
-
-    loop {
-        match udp_socket.recv_from(&mut buf) {
-            Ok((size, source)) => {
-                println!("Received {} bytes from {}", size, source);
-                let filled_buf = &mut buf[..size];
-                let (raw_header, _rest) = filled_buf.split_at(12);
-                let mut header = Header::new(raw_header);
-                println!("Received header:{:?}", header);
-                header.header_flags.qr = QueryOrReply::Reply;
-                let _message = DNSMessage { header };
-                println!("Response header:{:?}", _message.header);
-                udp_socket
-                    .send_to(&_message.header.to_bytes(), source)
-                    .expect("Failed to send response");
-            }
-            Err(e) => {
-                eprintln!("Error receiving data: {}", e);
-                break;
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_query_gets_hex_reply() {
+        let config = Config {
+            identity: "ns1".to_string(),
+            ..Config::default()
+        };
+        let server = Server::new(config);
+        // id.server CH TXT, with RD set, split over lines as it might be piped in
+        let input = b"abcd01000001000000000000\n026964067365727665720000100003\n";
+
+        let query = decode_hex(input).unwrap();
+        let output = encode_hex(&server.handle(&query).unwrap());
+        let expected = "abcd85000001000100000000\
+                        026964067365727665720000100003\
+                        c00c00100003000000000004036e7331\n";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn odd_or_non_hex_input_is_rejected() {
+        assert_eq!(decode_hex(b"abc"), None);
+        assert_eq!(decode_hex(b"zz"), None);
+        assert_eq!(decode_hex(b" 0a ff\n"), Some(vec![0x0a, 0xff]));
+    }
+
+    #[test]
+    fn built_in_vectors_pass_and_corrupted_ones_fail() {
+        assert!(check_vectors(selftest::VECTORS));
+
+        // The plain query with its question count raised to 2
+        let corrupted = selftest::Vector {
+            name: "corrupted",
+            hex: "1234010000020000000000000c636f6465637261667465727302696f0000010001",
+        };
+        let plain = selftest::Vector {
+            name: selftest::VECTORS[0].name,
+            hex: selftest::VECTORS[0].hex,
+        };
+        assert!(!check_vectors(&[plain, corrupted]));
+    }
+
+    #[test]
+    fn addresses_that_cannot_be_bound_are_skipped() {
+        // 192.0.2.1 is a documentation address, so not one of ours
+        let addrs = [
+            "127.0.0.1:0".parse().unwrap(),
+            "192.0.2.1:53".parse().unwrap(),
+        ];
+        let (udp_sockets, tcp_listeners) = bind_all(&addrs);
+        assert_eq!(udp_sockets.len(), 1);
+        assert_eq!(tcp_listeners.len(), 1);
+        assert!(udp_sockets[0].local_addr().unwrap().ip().is_loopback());
     }
 }
@@ -0,0 +1,751 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+
+use crate::edns::{self, EdnsOption};
+use crate::error::{ParseError, WriteError};
+use crate::header::Header;
+use crate::question::Question;
+use crate::reader::Reader;
+use crate::record::{QClass, QType, Rdata, ResourceRecord};
+use crate::writer::Writer;
+
+// All communications in the DNS protocol are carried in a single format called a "message".
+// Each message consists of 5 sections: header, question, answer, authority, and an additional space.
+// https://en.wikipedia.org/wiki/Domain_Name_System#DNS_message_format
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DNSMessage {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<ResourceRecord>,
+    pub authority: Vec<ResourceRecord>,
+    pub additional: Vec<ResourceRecord>,
+}
+
+impl DNSMessage {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        Self::read(bytes).map(|(message, _)| message)
+    }
+
+    /// Like `from_bytes`, but the message must take up all of `bytes` rather than ignoring
+    /// anything after it.
+    pub fn from_bytes_exact(bytes: &[u8]) -> Result<Self, ParseError> {
+        let (message, len) = Self::read(bytes)?;
+        if len != bytes.len() {
+            return Err(ParseError::TrailingBytes(bytes.len() - len));
+        }
+        Ok(message)
+    }
+
+    // Parses the message at the start of `bytes`, also returning how many bytes it took up
+    fn read(bytes: &[u8]) -> Result<(Self, usize), ParseError> {
+        let raw_header = bytes.get(..12).ok_or(ParseError::UnexpectedEnd)?;
+        let mut header = Header::new(raw_header)?;
+        let mut reader = Reader::new(bytes, 12);
+
+        // A message cut short after its last whole question is read as holding the questions
+        // that are there, with the count clamped to match, rather than trusting the header. One
+        // cut off partway through a question is still an error.
+        let mut questions = Vec::new();
+        for _ in 0..header.question_count {
+            if reader.position() == bytes.len() {
+                eprintln!(
+                    "Header claims {} questions but the message holds {}, clamping the count",
+                    header.question_count,
+                    questions.len()
+                );
+                header.question_count = questions.len() as u16;
+                break;
+            }
+            questions.push(Question::read(&mut reader)?);
+        }
+        let answers = ResourceRecord::read_section(&mut reader, header.answer_record_count)?;
+        let authority = ResourceRecord::read_section(&mut reader, header.authority_record_count)?;
+        let additional = ResourceRecord::read_section(&mut reader, header.additional_record_count)?;
+
+        // There may be at most one OPT record and it must be in the additional section
+        // https://www.rfc-editor.org/rfc/rfc6891#section-6.1.1
+        let is_opt = |record: &ResourceRecord| record.rtype == QType::Opt;
+        if answers.iter().chain(&authority).any(is_opt) {
+            return Err(ParseError::MisplacedOpt);
+        }
+        if additional.iter().filter(|record| is_opt(record)).count() > 1 {
+            return Err(ParseError::MultipleOpt);
+        }
+
+        let message = DNSMessage {
+            header,
+            questions,
+            answers,
+            authority,
+            additional,
+        };
+        Ok((message, reader.position()))
+    }
+
+    /// Serializes the message, compressing names. The header counts are taken from the
+    /// sections themselves rather than from `header`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WriteError> {
+        self.write(Writer::new())
+    }
+
+    /// Like `to_bytes`, but with every name written out in full. Only for working around
+    /// clients that mishandle compression, as the message can be a lot larger.
+    pub fn to_bytes_uncompressed(&self) -> Result<Vec<u8>, WriteError> {
+        self.write(Writer::uncompressed())
+    }
+
+    fn write(&self, mut writer: Writer) -> Result<Vec<u8>, WriteError> {
+        let mut header = self.header.clone();
+        header.question_count = self.questions.len() as u16;
+        header.answer_record_count = self.answers.len() as u16;
+        header.authority_record_count = self.authority.len() as u16;
+        header.additional_record_count = self.additional.len() as u16;
+
+        writer.write_bytes(&header.to_bytes());
+        for question in &self.questions {
+            question.write(&mut writer);
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            record.write(&mut writer);
+        }
+        writer.finish()
+    }
+
+    /// Sorts the MX records in the answer section by preference, and the SRV records by
+    /// priority then by weight, heaviest first. Other records stay where they are.
+    pub fn sort_by_preference(&mut self) {
+        sort_in_place(&mut self.answers, |rdata| match rdata {
+            Rdata::Mx { preference, .. } => Some(*preference),
+            _ => None,
+        });
+        sort_in_place(&mut self.answers, |rdata| match rdata {
+            Rdata::Srv {
+                priority, weight, ..
+            } => Some((*priority, Reverse(*weight))),
+            _ => None,
+        });
+    }
+
+    /// The name asked about by the first question, if there is one.
+    pub fn question_name(&self) -> Option<&[Vec<u8>]> {
+        self.questions
+            .first()
+            .map(|question| question.name.as_slice())
+    }
+
+    /// The type asked about by the first question, if there is one.
+    pub fn question_type(&self) -> Option<QType> {
+        self.questions.first().map(|question| question.qtype)
+    }
+
+    /// The class asked about by the first question, if there is one.
+    pub fn question_class(&self) -> Option<QClass> {
+        self.questions.first().map(|question| question.qclass)
+    }
+
+    /// The message's OPT record, if it has one.
+    pub fn edns(&self) -> Option<&ResourceRecord> {
+        self.additional
+            .iter()
+            .find(|record| record.rtype == QType::Opt)
+    }
+
+    /// Reorders the answer section so each CNAME comes before the records at its target, as
+    /// some clients only follow a chain forwards. Unrelated records keep their relative order.
+    pub fn order_answers(&mut self) {
+        let lowercase = |name: &[Vec<u8>]| -> Vec<Vec<u8>> {
+            name.iter()
+                .map(|label| label.to_ascii_lowercase())
+                .collect()
+        };
+        let names: Vec<_> = self.answers.iter().map(|r| lowercase(&r.name)).collect();
+        // How many CNAMEs not yet placed lead to each name, and the records at each name
+        let mut led_to: HashMap<Vec<Vec<u8>>, usize> = HashMap::new();
+        let mut at_name: HashMap<&[Vec<u8>], Vec<usize>> = HashMap::new();
+        for (i, record) in self.answers.iter().enumerate() {
+            if let Rdata::Cname(target) = &record.rdata {
+                *led_to.entry(lowercase(target)).or_default() += 1;
+            }
+            at_name.entry(&names[i]).or_default().push(i);
+        }
+
+        // Records are placed earliest first among those no unplaced CNAME leads to. With a
+        // CNAME loop every record is led to, so the loop is left in the order it came.
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..names.len())
+            .filter(|&i| !led_to.contains_key(&names[i]))
+            .map(Reverse)
+            .collect();
+        let mut placed = vec![false; names.len()];
+        let mut order = Vec::with_capacity(names.len());
+        let mut earliest_unplaced = 0;
+        while order.len() < names.len() {
+            let next = match ready.pop() {
+                Some(Reverse(i)) if placed[i] => continue,
+                Some(Reverse(i)) => i,
+                None => {
+                    while placed[earliest_unplaced] {
+                        earliest_unplaced += 1;
+                    }
+                    earliest_unplaced
+                }
+            };
+            placed[next] = true;
+            order.push(next);
+            if let Rdata::Cname(target) = &self.answers[next].rdata {
+                let target = lowercase(target);
+                let count = led_to
+                    .get_mut(&target)
+                    .expect("every CNAME target is counted");
+                *count -= 1;
+                if *count == 0 {
+                    let records = at_name.get(target.as_slice()).into_iter().flatten();
+                    ready.extend(records.map(|&i| Reverse(i)));
+                }
+            }
+        }
+
+        let mut answers: Vec<_> = std::mem::take(&mut self.answers)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.answers = order
+            .into_iter()
+            .filter_map(|i| answers[i].take())
+            .collect();
+    }
+
+    /// Whether the message has an OPT record with the DO flag set, asking for DNSSEC records.
+    pub fn dnssec_ok(&self) -> bool {
+        self.edns()
+            .is_some_and(|opt| opt.ttl & edns::DNSSEC_OK != 0)
+    }
+
+    /// The smallest TTL across the answer section, or `None` if there are no answers.
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.answers.iter().map(|record| record.ttl).min()
+    }
+}
+
+// Shown in the same layout as dig's output: the header, then each non-empty section with a
+// record per line. The OPT record is summarised in its own pseudo-section rather than shown as
+// a record.
+impl fmt::Display for DNSMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = &self.header.header_flags;
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            flags.op_code, flags.response_code, self.header.id
+        )?;
+        writeln!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            flags,
+            self.questions.len(),
+            self.answers.len(),
+            self.authority.len(),
+            self.additional.len()
+        )?;
+
+        if let Some(opt) = self.edns() {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            let dnssec_ok = if self.dnssec_ok() { " do" } else { "" };
+            let payload_size: u16 = opt.class.into();
+            writeln!(
+                f,
+                "; EDNS: version: {}, flags:{}; udp: {}",
+                (opt.ttl >> 16) & 0xFF,
+                dnssec_ok,
+                payload_size
+            )?;
+        }
+
+        writeln!(f, "\n;; QUESTION SECTION:")?;
+        for question in &self.questions {
+            writeln!(f, ";{}", question)?;
+        }
+        let additional: Vec<&ResourceRecord> = self
+            .additional
+            .iter()
+            .filter(|record| record.rtype != QType::Opt)
+            .collect();
+        let sections = [
+            ("ANSWER", self.answers.iter().collect::<Vec<_>>()),
+            ("AUTHORITY", self.authority.iter().collect()),
+            ("ADDITIONAL", additional),
+        ];
+        for (title, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {} SECTION:", title)?;
+            for record in records {
+                writeln!(f, "{}", record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Stably sorts the records `key` gives a key for, moving them only between the positions they
+// already held
+fn sort_in_place<K: Ord>(records: &mut [ResourceRecord], key: impl Fn(&Rdata) -> Option<K>) {
+    let positions: Vec<usize> = (0..records.len())
+        .filter(|&i| key(&records[i].rdata).is_some())
+        .collect();
+    let mut sorted: Vec<ResourceRecord> = positions.iter().map(|&i| records[i].clone()).collect();
+    sorted.sort_by_key(|record| key(&record.rdata));
+    for (position, record) in positions.into_iter().zip(sorted) {
+        records[position] = record;
+    }
+}
+
+// Assembles a message section by section, keeping the header counts in step with the sections
+// so the built message looks the same as one parsed off the wire.
+pub struct MessageBuilder {
+    message: DNSMessage,
+}
+
+impl MessageBuilder {
+    pub fn new(header: Header) -> Self {
+        MessageBuilder {
+            message: DNSMessage {
+                header,
+                questions: Vec::new(),
+                answers: Vec::new(),
+                authority: Vec::new(),
+                additional: Vec::new(),
+            },
+        }
+    }
+
+    pub fn question(mut self, question: Question) -> Self {
+        self.message.questions.push(question);
+        self
+    }
+
+    pub fn answer(mut self, record: ResourceRecord) -> Self {
+        self.message.answers.push(record);
+        self
+    }
+
+    pub fn authority(mut self, record: ResourceRecord) -> Self {
+        self.message.authority.push(record);
+        self
+    }
+
+    pub fn additional(mut self, record: ResourceRecord) -> Self {
+        self.message.additional.push(record);
+        self
+    }
+
+    /// Adds an OPT record with our usual payload size and `options`, replacing any earlier one.
+    pub fn edns(mut self, options: Vec<EdnsOption>) -> Self {
+        self.message
+            .additional
+            .retain(|record| record.rtype != QType::Opt);
+        self.message
+            .additional
+            .push(edns::opt_record(edns::DEFAULT_UDP_PAYLOAD_SIZE, options));
+        self
+    }
+
+    pub fn build(self) -> DNSMessage {
+        let mut message = self.message;
+        message.header.question_count = message.questions.len() as u16;
+        message.header.answer_record_count = message.answers.len() as u16;
+        message.header.authority_record_count = message.authority.len() as u16;
+        message.header.additional_record_count = message.additional.len() as u16;
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::edns;
+    use crate::error::WriteError;
+    use crate::header::{HeaderFlags, OpCode, QueryOrReply, ResponseCode};
+    use crate::name::parse_name;
+    use crate::record::{QClass, QType, Rdata};
+
+    fn header() -> Header {
+        Header {
+            id: 0x1234,
+            header_flags: HeaderFlags {
+                qr: QueryOrReply::Reply,
+                op_code: OpCode::Query,
+                authoritative_answer: false,
+                truncation: false,
+                recursion_desired: true,
+                recursion_available: true,
+                response_code: ResponseCode::NoError,
+            },
+            question_count: 0,
+            answer_record_count: 0,
+            authority_record_count: 0,
+            additional_record_count: 0,
+        }
+    }
+
+    fn question(name: &str, qtype: QType) -> Question {
+        Question {
+            name: parse_name(name),
+            qtype,
+            qclass: QClass::In,
+        }
+    }
+
+    fn record(name: &str, ttl: u32, rdata: Rdata) -> ResourceRecord {
+        let rtype = match rdata {
+            Rdata::A(_) => QType::A,
+            Rdata::Cname(_) => QType::Cname,
+            Rdata::Mx { .. } => QType::Mx,
+            Rdata::Srv { .. } => QType::Srv,
+            Rdata::Txt(_) => QType::Txt,
+            _ => panic!("no type for {:?}", rdata),
+        };
+        ResourceRecord {
+            name: parse_name(name),
+            rtype,
+            class: QClass::In,
+            ttl,
+            rdata,
+        }
+    }
+
+    fn a(name: &str, ttl: u32) -> ResourceRecord {
+        record(name, ttl, Rdata::A(Ipv4Addr::new(192, 0, 2, 1)))
+    }
+
+    // A reply to example.com A with an answer for each of `ttls`
+    fn reply(ttls: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
+        bytes[7] = ttls.len() as u8;
+        bytes.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        for ttl in ttls {
+            bytes.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+            bytes.extend_from_slice(&ttl.to_be_bytes());
+            bytes.extend_from_slice(&[0, 4, 192, 0, 2, 1]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_every_section() {
+        let message = DNSMessage::from_bytes(&reply(&[300])).unwrap();
+        assert_eq!(message.header.id, 0x1234);
+        assert_eq!(message.questions.len(), 1);
+        assert_eq!(message.questions[0].name, parse_name("example.com"));
+        assert_eq!(message.questions[0].qtype, QType::A);
+        assert_eq!(message.questions[0].qclass, QClass::In);
+        let [answer] = &message.answers[..] else {
+            panic!("expected one answer: {:?}", message.answers);
+        };
+        assert_eq!(answer.name, parse_name("example.com"));
+        assert_eq!(answer.ttl, 300);
+        assert_eq!(answer.rdata, Rdata::A(Ipv4Addr::new(192, 0, 2, 1)));
+        assert!(message.authority.is_empty());
+        assert!(message.additional.is_empty());
+    }
+
+    #[test]
+    fn min_ttl_is_the_smallest_answer_ttl() {
+        let message = DNSMessage::from_bytes(&reply(&[300, 60, 3600])).unwrap();
+        assert_eq!(message.min_ttl(), Some(60));
+        let message = DNSMessage::from_bytes(&reply(&[])).unwrap();
+        assert_eq!(message.min_ttl(), None);
+    }
+
+    #[test]
+    fn labels_that_are_not_utf8_round_trip() {
+        let name = vec![vec![0xff, b'.', 0x00], b"example".to_vec()];
+        let message = MessageBuilder::new(header())
+            .question(Question {
+                name: name.clone(),
+                qtype: QType::A,
+                qclass: QClass::In,
+            })
+            .build();
+        let parsed = DNSMessage::from_bytes(&message.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.questions[0].name, name);
+        assert_eq!(
+            parsed.questions[0].to_string(),
+            "\\255\\.\\000.example. IN A"
+        );
+    }
+
+    #[test]
+    fn labels_over_63_bytes_are_not_written() {
+        let message = MessageBuilder::new(header())
+            .question(question(&format!("{}.example", "a".repeat(64)), QType::A))
+            .build();
+        assert_eq!(message.to_bytes(), Err(WriteError::LabelTooLong(64)));
+
+        let message = MessageBuilder::new(header())
+            .question(question(&format!("{}.example", "a".repeat(63)), QType::A))
+            .build();
+        assert!(message.to_bytes().is_ok());
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let bytes = reply(&[300]);
+        assert_eq!(
+            DNSMessage::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            DNSMessage::from_bytes(&bytes[..11]),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn round_trips_through_to_bytes() {
+        let message = DNSMessage::from_bytes(&reply(&[300, 60])).unwrap();
+        let bytes = message.to_bytes().unwrap();
+        // Names after the question are compressed back to pointers
+        assert_eq!(bytes, reply(&[300, 60]));
+        assert_eq!(DNSMessage::from_bytes(&bytes).unwrap(), message);
+    }
+
+    #[test]
+    fn two_opt_records_are_rejected() {
+        let mut message = DNSMessage::from_bytes(&reply(&[300])).unwrap();
+        message.additional.push(edns::opt_record(4096, Vec::new()));
+        assert!(DNSMessage::from_bytes(&message.to_bytes().unwrap()).is_ok());
+        message.additional.push(edns::opt_record(4096, Vec::new()));
+        assert_eq!(
+            DNSMessage::from_bytes(&message.to_bytes().unwrap()),
+            Err(ParseError::MultipleOpt)
+        );
+    }
+
+    #[test]
+    fn trailing_bytes_are_only_rejected_when_exact() {
+        let mut bytes = reply(&[300]);
+        bytes.extend_from_slice(&[0, 0]);
+        assert!(DNSMessage::from_bytes(&bytes).is_ok());
+        assert_eq!(
+            DNSMessage::from_bytes_exact(&bytes),
+            Err(ParseError::TrailingBytes(2))
+        );
+        assert!(DNSMessage::from_bytes_exact(&reply(&[300])).is_ok());
+    }
+
+    #[test]
+    fn builder_keeps_section_counts() {
+        let message = MessageBuilder::new(header())
+            .question(question("example.com", QType::A))
+            .answer(a("example.com", 300))
+            .answer(a("example.com", 300))
+            .authority(record(
+                "example.com",
+                300,
+                Rdata::Cname(parse_name("ns1.example.com")),
+            ))
+            .edns(Vec::new())
+            .edns(Vec::new())
+            .build();
+        assert_eq!(message.header.question_count, 1);
+        assert_eq!(message.header.answer_record_count, 2);
+        assert_eq!(message.header.authority_record_count, 1);
+        assert_eq!(message.header.additional_record_count, 1);
+        let parsed = DNSMessage::from_bytes(&message.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.header, message.header);
+    }
+
+    #[test]
+    fn uncompressed_messages_have_no_pointers() {
+        let target = Rdata::Cname(parse_name("www.example.com"));
+        let message = MessageBuilder::new(header())
+            .question(question("alias.example.com", QType::Cname))
+            .answer(record("alias.example.com", 300, target.clone()))
+            .answer(record("other.example.com", 300, target))
+            .build();
+        // Nothing in this message has its top two bits set but a compression pointer
+        let has_pointer = |bytes: &[u8]| bytes.iter().any(|&b| b & 0xc0 == 0xc0);
+        let compressed = message.to_bytes().unwrap();
+        assert!(has_pointer(&compressed));
+
+        let uncompressed = message.to_bytes_uncompressed().unwrap();
+        assert!(!has_pointer(&uncompressed));
+        assert!(uncompressed.len() > compressed.len());
+        assert_eq!(DNSMessage::from_bytes(&uncompressed).unwrap(), message);
+    }
+
+    #[test]
+    fn question_count_is_clamped_to_the_questions_present() {
+        let message = MessageBuilder::new(header())
+            .question(question("example.com", QType::A))
+            .build();
+        let mut bytes = message.to_bytes().unwrap();
+        bytes[5] = 2;
+        let parsed = DNSMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.questions, message.questions);
+        assert_eq!(parsed.header.question_count, 1);
+
+        // One cut off partway is still an error
+        bytes.truncate(bytes.len() - 2);
+        assert_eq!(
+            DNSMessage::from_bytes(&bytes),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    fn cname(name: &str, target: &str) -> ResourceRecord {
+        record(name, 300, Rdata::Cname(parse_name(target)))
+    }
+
+    #[test]
+    fn cnames_are_ordered_before_their_targets() {
+        let mut message = MessageBuilder::new(header())
+            .answer(a("c.example.com", 300))
+            .answer(cname("b.example.com", "C.example.com"))
+            .answer(a("other.example.com", 300))
+            .answer(cname("a.example.com", "b.example.com"))
+            .build();
+        // Building leaves the answers as they were given
+        assert_eq!(message.answers[0], a("c.example.com", 300));
+
+        message.order_answers();
+        assert_eq!(
+            message.answers,
+            [
+                a("other.example.com", 300),
+                cname("a.example.com", "b.example.com"),
+                cname("b.example.com", "C.example.com"),
+                a("c.example.com", 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn cname_loops_are_kept_whole() {
+        let mut message = MessageBuilder::new(header())
+            .answer(cname("b.example.com", "a.example.com"))
+            .answer(cname("a.example.com", "b.example.com"))
+            .answer(a("c.example.com", 300))
+            .build();
+        let mut expected = message.answers.clone();
+        message.order_answers();
+        expected.sort_by_key(|record| record.to_string());
+        message.answers.sort_by_key(|record| record.to_string());
+        assert_eq!(message.answers, expected);
+    }
+
+    #[test]
+    fn question_accessors_read_the_first_question() {
+        let mut question = question("example.com", QType::Mx);
+        question.qclass = QClass::Ch;
+        let message = MessageBuilder::new(header()).question(question).build();
+        assert_eq!(
+            message.question_name(),
+            Some(parse_name("example.com").as_slice())
+        );
+        assert_eq!(message.question_type(), Some(QType::Mx));
+        assert_eq!(message.question_class(), Some(QClass::Ch));
+
+        let empty = MessageBuilder::new(header()).build();
+        assert_eq!(empty.question_name(), None);
+        assert_eq!(empty.question_type(), None);
+        assert_eq!(empty.question_class(), None);
+    }
+
+    fn mx(preference: u16) -> ResourceRecord {
+        let exchange = parse_name(&format!("mx{}.example.com", preference));
+        record(
+            "example.com",
+            300,
+            Rdata::Mx {
+                preference,
+                exchange,
+            },
+        )
+    }
+
+    fn srv(priority: u16, weight: u16) -> ResourceRecord {
+        let rdata = Rdata::Srv {
+            priority,
+            weight,
+            port: 443,
+            target: parse_name("example.com"),
+        };
+        record("_https._tcp.example.com", 300, rdata)
+    }
+
+    #[test]
+    fn mx_and_srv_sort_into_the_order_to_try_them() {
+        let mut message = MessageBuilder::new(header())
+            .answer(mx(30))
+            .answer(srv(10, 5))
+            .answer(mx(10))
+            .answer(a("example.com", 300))
+            .answer(srv(10, 50))
+            .answer(mx(20))
+            .answer(srv(0, 1))
+            .build();
+        message.sort_by_preference();
+        assert_eq!(
+            message.answers,
+            [
+                mx(10),
+                srv(0, 1),
+                mx(20),
+                a("example.com", 300),
+                srv(10, 50),
+                mx(30),
+                srv(10, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn display_looks_like_dig() {
+        let mut header = header();
+        header.header_flags.authoritative_answer = true;
+        let mut opt = edns::opt_record(1232, Vec::new());
+        opt.ttl |= edns::DNSSEC_OK;
+        let message = MessageBuilder::new(header)
+            .question(question("www.example.com", QType::A))
+            .answer(cname("www.example.com", "web.example.com"))
+            .answer(a("web.example.com", 60))
+            .authority(ResourceRecord {
+                name: parse_name("example.com"),
+                rtype: QType::Ns,
+                class: QClass::In,
+                ttl: 3600,
+                rdata: Rdata::Ns(parse_name("ns1.example.com")),
+            })
+            .additional(opt)
+            .build();
+        let parsed = DNSMessage::from_bytes(&message.to_bytes().unwrap()).unwrap();
+        let expected = "\
+;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4660
+;; flags: qr aa rd ra; QUERY: 1, ANSWER: 2, AUTHORITY: 1, ADDITIONAL: 1
+
+;; OPT PSEUDOSECTION:
+; EDNS: version: 0, flags: do; udp: 1232
+
+;; QUESTION SECTION:
+;www.example.com. IN A
+
+;; ANSWER SECTION:
+www.example.com. 300 IN CNAME web.example.com.
+web.example.com. 60 IN A 192.0.2.1
+
+;; AUTHORITY SECTION:
+example.com. 3600 IN NS ns1.example.com.
+";
+        assert_eq!(parsed.to_string(), expected);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::record::QType;
+use crate::server::Server;
+
+// Counters describing the traffic the server has handled
+#[derive(Debug, Default)]
+pub struct Metrics {
+    queries_by_type: Mutex<HashMap<QType, u64>>,
+    amplified_responses: AtomicU64,
+    in_flight: AtomicU64,
+    shed_queries: AtomicU64,
+}
+
+// Counts a query as in flight for as long as it is held
+pub struct InFlight<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Counts a question of the given type.
+    pub fn record_query(&self, qtype: QType) {
+        *self
+            .queries_by_type
+            .lock()
+            .unwrap()
+            .entry(qtype)
+            .or_default() += 1;
+    }
+
+    /// How many questions of each type have been received so far.
+    pub fn queries_by_type(&self) -> HashMap<QType, u64> {
+        self.queries_by_type.lock().unwrap().clone()
+    }
+
+    /// Counts a UDP response over the amplification threshold.
+    pub fn record_amplified_response(&self) {
+        self.amplified_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many UDP responses have been over the amplification threshold.
+    pub fn amplified_responses(&self) -> u64 {
+        self.amplified_responses.load(Ordering::Relaxed)
+    }
+
+    /// Counts a query as in flight until the returned guard is dropped.
+    pub fn begin_query(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight { metrics: self }
+    }
+
+    /// How many queries are being handled right now.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Counts a query refused to shed load.
+    pub fn record_shed_query(&self) {
+        self.shed_queries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many queries have been refused to shed load.
+    pub fn shed_queries(&self) -> u64 {
+        self.shed_queries.load(Ordering::Relaxed)
+    }
+
+    /// Renders the counters in the Prometheus text exposition format.
+    /// https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+    pub fn render_prometheus(&self) -> String {
+        let mut by_type: Vec<_> = self.queries_by_type().into_iter().collect();
+        by_type.sort_by_key(|(qtype, _)| u16::from(*qtype));
+
+        let mut output = String::new();
+        output.push_str("# HELP dns_queries_total Questions received, by type.\n");
+        output.push_str("# TYPE dns_queries_total counter\n");
+        for (qtype, count) in by_type {
+            let _ = writeln!(output, "dns_queries_total{{qtype=\"{}\"}} {}", qtype, count);
+        }
+        output.push_str(
+            "# HELP dns_amplified_responses_total UDP responses over the amplification threshold.\n",
+        );
+        output.push_str("# TYPE dns_amplified_responses_total counter\n");
+        let _ = writeln!(
+            output,
+            "dns_amplified_responses_total {}",
+            self.amplified_responses()
+        );
+        output.push_str("# HELP dns_queries_in_flight Queries being handled right now.\n");
+        output.push_str("# TYPE dns_queries_in_flight gauge\n");
+        let _ = writeln!(output, "dns_queries_in_flight {}", self.in_flight());
+        output.push_str("# HELP dns_shed_queries_total Queries refused to shed load.\n");
+        output.push_str("# TYPE dns_shed_queries_total counter\n");
+        let _ = writeln!(output, "dns_shed_queries_total {}", self.shed_queries());
+        output
+    }
+}
+
+// Serves the server's metrics over HTTP for Prometheus to scrape. Every request gets the
+// metrics, whatever its path.
+pub fn serve(listener: TcpListener, server: Arc<Server>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let server = server.clone();
+                thread::spawn(move || {
+                    if let Err(e) = respond(stream, &server) {
+                        eprintln!("Failed to serve metrics: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Error accepting metrics connection: {}", e),
+        }
+    }
+}
+
+fn respond(stream: TcpStream, server: &Server) -> std::io::Result<()> {
+    // Read up to the end of the request headers so the client isn't cut off mid-request
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line != "\r\n" && line != "\n" {
+        line.clear();
+    }
+
+    let body = server.metrics().render_prometheus();
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_by_type_are_rendered_in_type_order() {
+        let metrics = Metrics::default();
+        metrics.record_query(QType::Aaaa);
+        metrics.record_query(QType::A);
+        metrics.record_query(QType::Aaaa);
+        let output = metrics.render_prometheus();
+        let a = output.find("dns_queries_total{qtype=\"A\"} 1\n").unwrap();
+        let aaaa = output
+            .find("dns_queries_total{qtype=\"AAAA\"} 2\n")
+            .unwrap();
+        assert!(a < aaaa);
+    }
+}
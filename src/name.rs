@@ -0,0 +1,121 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// Names are limited to 255 bytes on the wire, including the length bytes
+pub(crate) const MAX_NAME_LENGTH: usize = 255;
+
+// Labels are limited to 63 bytes, the top two bits of the length byte marking pointers
+pub(crate) const MAX_LABEL_LENGTH: usize = 63;
+
+// Formats a name held as labels in the usual dotted, fully qualified form, e.g. "example.com."
+// and "." for the root. Labels are arbitrary bytes, so dots and backslashes within one are
+// escaped with a backslash, and anything unprintable written as \DDD.
+// https://www.rfc-editor.org/rfc/rfc4343#section-2.1
+pub struct DisplayName<'a>(pub &'a [Vec<u8>]);
+
+impl fmt::Display for DisplayName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, ".");
+        }
+        for label in self.0 {
+            for &b in label {
+                match b {
+                    b'.' | b'\\' => write!(f, "\\{}", b as char)?,
+                    0x21..=0x7e => write!(f, "{}", b as char)?,
+                    _ => write!(f, "\\{:03}", b)?,
+                }
+            }
+            write!(f, ".")?;
+        }
+        Ok(())
+    }
+}
+
+// Names compare case-insensitively
+// https://www.rfc-editor.org/rfc/rfc4343
+pub fn same_name(a: &[Vec<u8>], b: &[Vec<u8>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b))
+}
+
+// Orders names canonically for DNSSEC: by their labels from the root down, each compared as
+// unsigned bytes once lowercased, with a missing label or byte sorting first. So the root comes
+// before everything, and "example.com" before "*.example.com" before "a.example.com".
+// https://www.rfc-editor.org/rfc/rfc4034#section-6.1
+pub fn canonical_name_cmp(a: &[Vec<u8>], b: &[Vec<u8>]) -> Ordering {
+    let labels = |name: &'_ [Vec<u8>]| {
+        name.iter()
+            .rev()
+            .map(|label| label.to_ascii_lowercase())
+            .collect::<Vec<_>>()
+    };
+    labels(a).cmp(&labels(b))
+}
+
+// Whether `name` is `ancestor` or any name below it
+pub fn is_subdomain(name: &[Vec<u8>], ancestor: &[Vec<u8>]) -> bool {
+    name.len() >= ancestor.len() && same_name(&name[name.len() - ancestor.len()..], ancestor)
+}
+
+// Parses a dotted name like "example.com" or "example.com." into labels
+pub fn parse_name(name: &str) -> Vec<Vec<u8>> {
+    name.split('.')
+        .filter(|label| !label.is_empty())
+        .map(|label| label.as_bytes().to_vec())
+        .collect()
+}
+
+// The length of a name on the wire, uncompressed: each label with its length byte, then the
+// root's zero byte
+pub(crate) fn wire_length(name: &[Vec<u8>]) -> usize {
+    name.iter().map(|label| label.len() + 1).sum::<usize>() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_display_fully_qualified() {
+        assert_eq!(
+            DisplayName(&parse_name("example.com")).to_string(),
+            "example.com."
+        );
+        assert_eq!(DisplayName(&[]).to_string(), ".");
+        let odd = vec![b"a.b\\c d".to_vec()];
+        assert_eq!(DisplayName(&odd).to_string(), "a\\.b\\\\c\\032d.");
+    }
+
+    #[test]
+    fn canonical_order_matches_rfc_4034() {
+        // The example from https://www.rfc-editor.org/rfc/rfc4034#section-6.1, with the root
+        // added, shuffled
+        let sorted: Vec<Vec<Vec<u8>>> = vec![
+            Vec::new(),
+            parse_name("example"),
+            parse_name("a.example"),
+            parse_name("yljkjljk.a.example"),
+            parse_name("Z.a.example"),
+            vec![b"zABC".to_vec(), b"a".to_vec(), b"EXAMPLE".to_vec()],
+            parse_name("z.example"),
+            vec![vec![0x01], b"z".to_vec(), b"example".to_vec()],
+            parse_name("*.z.example"),
+            vec![vec![0xc8], b"z".to_vec(), b"example".to_vec()],
+        ];
+        let mut names = sorted.clone();
+        names.reverse();
+        names.swap(2, 7);
+        names.sort_by(|a, b| canonical_name_cmp(a, b));
+        assert_eq!(names, sorted);
+
+        let wildcard = parse_name("*.example.com");
+        assert_eq!(
+            canonical_name_cmp(&wildcard, &parse_name("a.example.com")),
+            Ordering::Less
+        );
+        assert_eq!(
+            canonical_name_cmp(&parse_name("EXAMPLE.com"), &parse_name("example.COM")),
+            Ordering::Equal
+        );
+    }
+}
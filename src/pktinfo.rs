@@ -0,0 +1,385 @@
+// Lets a UDP socket bound to a wildcard address reply from the address each query was sent to.
+// The kernel would otherwise pick the reply's source by route, and on a multi-address host that
+// can differ from the address the client queried, so the client drops the reply. On Linux the
+// destination of each datagram is read with IP_PKTINFO and given back as the reply's source;
+// elsewhere wildcard sockets aren't supported.
+// https://man7.org/linux/man-pages/man7/ip.7.html
+// https://www.rfc-editor.org/rfc/rfc3542#section-6
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+// The local address a datagram arrived on, to reply from, and the interface it arrived on
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Destination {
+    ip: IpAddr,
+    interface: u32,
+}
+
+// Asks the kernel to report the destination of each datagram `socket` receives
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(socket: &UdpSocket) -> io::Result<()> {
+    linux::enable(socket)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(_socket: &UdpSocket) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// Receives a datagram like `UdpSocket::recv_from`, along with its destination if the socket was
+// enabled and the kernel reported one
+#[cfg(target_os = "linux")]
+pub(crate) fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<Destination>)> {
+    linux::recv_from(socket, buf)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<Destination>)> {
+    socket
+        .recv_from(buf)
+        .map(|(size, source)| (size, source, None))
+}
+
+// Sends `buf` to `target` like `UdpSocket::send_to`, from the address in `from` if given
+pub(crate) fn send_to(
+    socket: &UdpSocket,
+    buf: &[u8],
+    target: SocketAddr,
+    from: Option<Destination>,
+) -> io::Result<usize> {
+    match from {
+        #[cfg(target_os = "linux")]
+        Some(from) => linux::send_to(socket, buf, target, from),
+        _ => socket.send_to(buf, target),
+    }
+}
+
+// The C structures and calls involved, as laid out by glibc and musl on Linux
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    use super::Destination;
+
+    const AF_INET: u16 = 2;
+    const AF_INET6: u16 = 10;
+    const SOL_IP: c_int = 0;
+    const SOL_IPV6: c_int = 41;
+    const IP_PKTINFO: c_int = 8;
+    const IPV6_RECVPKTINFO: c_int = 49;
+    const IPV6_PKTINFO: c_int = 50;
+
+    #[repr(C)]
+    struct IoVec {
+        base: *mut c_void,
+        len: usize,
+    }
+
+    #[repr(C)]
+    struct MsgHdr {
+        name: *mut c_void,
+        name_len: u32,
+        iov: *mut IoVec,
+        iov_len: usize,
+        control: *mut c_void,
+        control_len: usize,
+        flags: c_int,
+    }
+
+    #[repr(C)]
+    struct CmsgHdr {
+        len: usize,
+        level: c_int,
+        kind: c_int,
+    }
+
+    #[repr(C)]
+    struct InPktinfo {
+        interface: c_int,
+        spec_dst: [u8; 4],
+        addr: [u8; 4],
+    }
+
+    #[repr(C)]
+    struct In6Pktinfo {
+        addr: [u8; 16],
+        interface: u32,
+    }
+
+    #[repr(C)]
+    struct SockaddrIn {
+        family: u16,
+        port: [u8; 2],
+        addr: [u8; 4],
+        zero: [u8; 8],
+    }
+
+    #[repr(C)]
+    struct SockaddrIn6 {
+        family: u16,
+        port: [u8; 2],
+        flow_info: u32,
+        addr: [u8; 16],
+        scope_id: u32,
+    }
+
+    // Room for either kind of socket address, aligned as the kernel expects to write them
+    #[repr(C, align(8))]
+    struct SockaddrStorage([u8; 128]);
+
+    // Room for a control message holding either kind of pktinfo
+    #[repr(C, align(8))]
+    struct Control([u8; 64]);
+
+    extern "C" {
+        fn setsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+        fn recvmsg(fd: c_int, msg: *mut MsgHdr, flags: c_int) -> isize;
+        fn sendmsg(fd: c_int, msg: *const MsgHdr, flags: c_int) -> isize;
+    }
+
+    // Control message headers and data are padded out to the alignment of a size_t
+    fn cmsg_align(len: usize) -> usize {
+        (len + mem::size_of::<usize>() - 1) & !(mem::size_of::<usize>() - 1)
+    }
+
+    pub(super) fn enable(socket: &UdpSocket) -> io::Result<()> {
+        let (level, name) = match socket.local_addr()? {
+            SocketAddr::V4(_) => (SOL_IP, IP_PKTINFO),
+            SocketAddr::V6(_) => (SOL_IPV6, IPV6_RECVPKTINFO),
+        };
+        let on: c_int = 1;
+        // SAFETY: `on` outlives the call and its size is passed alongside it
+        let result = unsafe {
+            setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &on as *const c_int as *const c_void,
+                mem::size_of::<c_int>() as u32,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub(super) fn recv_from(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<Destination>)> {
+        let mut source = SockaddrStorage([0; 128]);
+        let mut control = Control([0; 64]);
+        let mut iov = IoVec {
+            base: buf.as_mut_ptr() as *mut c_void,
+            len: buf.len(),
+        };
+        let mut msg = MsgHdr {
+            name: &mut source as *mut SockaddrStorage as *mut c_void,
+            name_len: mem::size_of::<SockaddrStorage>() as u32,
+            iov: &mut iov,
+            iov_len: 1,
+            control: &mut control as *mut Control as *mut c_void,
+            control_len: mem::size_of::<Control>(),
+            flags: 0,
+        };
+        // SAFETY: every buffer `msg` points to outlives the call and is given with its size
+        let size = unsafe { recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let source = read_sockaddr(&source)?;
+        let destination = read_destination(&control.0[..msg.control_len.min(control.0.len())]);
+        Ok((size as usize, source, destination))
+    }
+
+    pub(super) fn send_to(
+        socket: &UdpSocket,
+        buf: &[u8],
+        target: SocketAddr,
+        from: Destination,
+    ) -> io::Result<usize> {
+        let mut name = SockaddrStorage([0; 128]);
+        let name_len = write_sockaddr(&mut name, target);
+        let mut control = Control([0; 64]);
+        let control_len = write_destination(&mut control, from);
+        let mut iov = IoVec {
+            base: buf.as_ptr() as *mut c_void,
+            len: buf.len(),
+        };
+        let msg = MsgHdr {
+            name: &mut name as *mut SockaddrStorage as *mut c_void,
+            name_len,
+            iov: &mut iov,
+            iov_len: 1,
+            control: &mut control as *mut Control as *mut c_void,
+            control_len,
+            flags: 0,
+        };
+        // SAFETY: every buffer `msg` points to outlives the call and is given with its size, and
+        // sendmsg only reads through them
+        let size = unsafe { sendmsg(socket.as_raw_fd(), &msg, 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size as usize)
+    }
+
+    fn read_sockaddr(storage: &SockaddrStorage) -> io::Result<SocketAddr> {
+        let family = u16::from_ne_bytes([storage.0[0], storage.0[1]]);
+        let port = u16::from_be_bytes([storage.0[2], storage.0[3]]);
+        match family {
+            AF_INET => {
+                // SAFETY: the kernel wrote a sockaddr_in, which fits in the storage
+                let addr = unsafe { ptr::read(storage as *const _ as *const SockaddrIn) };
+                Ok(SocketAddr::from((Ipv4Addr::from(addr.addr), port)))
+            }
+            AF_INET6 => {
+                // SAFETY: the kernel wrote a sockaddr_in6, which fits in the storage
+                let addr = unsafe { ptr::read(storage as *const _ as *const SockaddrIn6) };
+                Ok(SocketAddr::V6(std::net::SocketAddrV6::new(
+                    Ipv6Addr::from(addr.addr),
+                    port,
+                    u32::from_be(addr.flow_info),
+                    addr.scope_id,
+                )))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("datagram from an address of family {}", family),
+            )),
+        }
+    }
+
+    // Returns the length of the address written
+    fn write_sockaddr(storage: &mut SockaddrStorage, addr: SocketAddr) -> u32 {
+        match addr {
+            SocketAddr::V4(addr) => {
+                let sockaddr = SockaddrIn {
+                    family: AF_INET,
+                    port: addr.port().to_be_bytes(),
+                    addr: addr.ip().octets(),
+                    zero: [0; 8],
+                };
+                // SAFETY: a sockaddr_in fits in the storage, which is aligned for it
+                unsafe { ptr::write(storage as *mut _ as *mut SockaddrIn, sockaddr) };
+                mem::size_of::<SockaddrIn>() as u32
+            }
+            SocketAddr::V6(addr) => {
+                let sockaddr = SockaddrIn6 {
+                    family: AF_INET6,
+                    port: addr.port().to_be_bytes(),
+                    flow_info: addr.flowinfo().to_be(),
+                    addr: addr.ip().octets(),
+                    scope_id: addr.scope_id(),
+                };
+                // SAFETY: a sockaddr_in6 fits in the storage, which is aligned for it
+                unsafe { ptr::write(storage as *mut _ as *mut SockaddrIn6, sockaddr) };
+                mem::size_of::<SockaddrIn6>() as u32
+            }
+        }
+    }
+
+    // Finds the pktinfo among the control messages received, if there is one
+    fn read_destination(control: &[u8]) -> Option<Destination> {
+        let header_len = cmsg_align(mem::size_of::<CmsgHdr>());
+        let mut offset = 0;
+        while offset + header_len <= control.len() {
+            // SAFETY: the header lies within `control`, and is read unaligned
+            let header =
+                unsafe { ptr::read_unaligned(control[offset..].as_ptr() as *const CmsgHdr) };
+            if header.len < header_len || offset + header.len > control.len() {
+                return None;
+            }
+            let data = &control[offset + header_len..offset + header.len];
+            match (header.level, header.kind) {
+                (SOL_IP, IP_PKTINFO) if data.len() >= mem::size_of::<InPktinfo>() => {
+                    // SAFETY: `data` holds a whole in_pktinfo, read unaligned
+                    let info = unsafe { ptr::read_unaligned(data.as_ptr() as *const InPktinfo) };
+                    // The local address is what to reply from, but not every kernel fills it
+                    // in, and the header's destination is the same for unicast queries
+                    let ip = match info.spec_dst {
+                        [0, 0, 0, 0] => info.addr,
+                        spec_dst => spec_dst,
+                    };
+                    return Some(Destination {
+                        ip: IpAddr::from(ip),
+                        interface: info.interface as u32,
+                    });
+                }
+                (SOL_IPV6, IPV6_PKTINFO) if data.len() >= mem::size_of::<In6Pktinfo>() => {
+                    // SAFETY: `data` holds a whole in6_pktinfo, read unaligned
+                    let info = unsafe { ptr::read_unaligned(data.as_ptr() as *const In6Pktinfo) };
+                    return Some(Destination {
+                        ip: IpAddr::from(info.addr),
+                        interface: info.interface,
+                    });
+                }
+                _ => offset += cmsg_align(header.len),
+            }
+        }
+        None
+    }
+
+    // Writes a control message setting the source of what's sent to `from`, returning its length.
+    // IPv4 destinations of a v6 socket arrive as mapped addresses, so are given back as such.
+    fn write_destination(control: &mut Control, from: Destination) -> usize {
+        let header_len = cmsg_align(mem::size_of::<CmsgHdr>());
+        let (level, kind, data_len) = match from.ip {
+            IpAddr::V4(ip) => {
+                let info = InPktinfo {
+                    interface: from.interface as c_int,
+                    spec_dst: ip.octets(),
+                    addr: [0; 4],
+                };
+                // SAFETY: the header and in_pktinfo fit in the control buffer
+                unsafe {
+                    ptr::write_unaligned(
+                        control.0[header_len..].as_mut_ptr() as *mut InPktinfo,
+                        info,
+                    )
+                };
+                (SOL_IP, IP_PKTINFO, mem::size_of::<InPktinfo>())
+            }
+            IpAddr::V6(ip) => {
+                let info = In6Pktinfo {
+                    addr: ip.octets(),
+                    interface: from.interface,
+                };
+                // SAFETY: the header and in6_pktinfo fit in the control buffer
+                unsafe {
+                    ptr::write_unaligned(
+                        control.0[header_len..].as_mut_ptr() as *mut In6Pktinfo,
+                        info,
+                    )
+                };
+                (SOL_IPV6, IPV6_PKTINFO, mem::size_of::<In6Pktinfo>())
+            }
+        };
+        let header = CmsgHdr {
+            len: header_len + data_len,
+            level,
+            kind,
+        };
+        // SAFETY: the control buffer starts with room for the header, and is aligned for it
+        unsafe { ptr::write(control as *mut Control as *mut CmsgHdr, header) };
+        header_len + cmsg_align(data_len)
+    }
+}
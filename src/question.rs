@@ -0,0 +1,49 @@
+use std::fmt;
+
+use crate::error::ParseError;
+use crate::name::DisplayName;
+use crate::reader::Reader;
+use crate::record::{QClass, QType};
+use crate::writer::Writer;
+
+// The question section holds the name being asked about, followed by 2 bytes each of type
+// and class.
+// https://www.rfc-editor.org/rfc/rfc1035#section-4.1.2
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Question {
+    pub name: Vec<Vec<u8>>,
+    pub qtype: QType,
+    pub qclass: QClass,
+}
+
+// Shown like a record without a TTL or RDATA: name, class and type
+impl fmt::Display for Question {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            DisplayName(&self.name),
+            self.qclass,
+            self.qtype
+        )
+    }
+}
+
+impl Question {
+    pub(crate) fn read(reader: &mut Reader) -> Result<Self, ParseError> {
+        let name = reader.read_name()?;
+        let qtype = QType::from(reader.read_u16()?);
+        let qclass = QClass::from(reader.read_u16()?);
+        Ok(Question {
+            name,
+            qtype,
+            qclass,
+        })
+    }
+
+    pub(crate) fn write(&self, writer: &mut Writer) {
+        writer.write_name(&self.name);
+        writer.write_u16(self.qtype.into());
+        writer.write_u16(self.qclass.into());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::error::ParseError;
+use crate::name::MAX_NAME_LENGTH;
+
+// Cursor over a complete DNS message. Compressed names point back into earlier parts of the
+// message so the reader always holds the whole message rather than the remaining bytes.
+pub(crate) struct Reader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(message: &'a [u8], position: usize) -> Self {
+        Reader { message, position }
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        let bytes = self
+            .message
+            .get(self.position..self.position + len)
+            .ok_or(ParseError::UnexpectedEnd)?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, ParseError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, ParseError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Reads a sequence of labels terminated by the root label or a compression pointer.
+    // A pointer is 2 bytes with the top two bits set, the remaining 14 bits being the offset
+    // of the rest of the name. Pointers must point strictly backwards so a loop can't occur.
+    // https://www.rfc-editor.org/rfc/rfc1035#section-4.1.4
+    pub(crate) fn read_name(&mut self) -> Result<Vec<Vec<u8>>, ParseError> {
+        let mut labels = Vec::new();
+        let mut wire_length = 1;
+        // Where to continue reading once the name is done, set by the first pointer followed
+        let mut resume_at = None;
+        let mut label_start = self.position;
+
+        loop {
+            let len = self.read_u8()?;
+            match len & 0b1100_0000 {
+                0b1100_0000 => {
+                    let offset = (((len & 0b0011_1111) as usize) << 8) | self.read_u8()? as usize;
+                    if offset >= label_start {
+                        return Err(ParseError::InvalidPointer(offset));
+                    }
+                    resume_at.get_or_insert(self.position);
+                    self.position = offset;
+                    label_start = offset;
+                }
+                0b0000_0000 => {
+                    if len == 0 {
+                        break;
+                    }
+                    wire_length += len as usize + 1;
+                    if wire_length > MAX_NAME_LENGTH {
+                        return Err(ParseError::NameTooLong);
+                    }
+                    let label = self.read_bytes(len as usize)?;
+                    labels.push(label.to_vec());
+                }
+                _ => return Err(ParseError::InvalidLabel(len)),
+            }
+        }
+
+        if let Some(position) = resume_at {
+            self.position = position;
+        }
+        Ok(labels)
+    }
+}
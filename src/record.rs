@@ -0,0 +1,1361 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::base32hex;
+use crate::base64;
+use crate::edns::EdnsOption;
+use crate::error::{ParseError, WriteError};
+use crate::name::DisplayName;
+use crate::reader::Reader;
+use crate::writer::Writer;
+
+// The TYPE of a resource record. Questions use the same values for their QTYPE.
+// https://www.rfc-editor.org/rfc/rfc1035#section-3.2.2
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum QType {
+    A,
+    Ns,
+    Cname,
+    Soa,
+    Hinfo,
+    Mx,
+    Txt,
+    Aaaa,
+    Srv,
+    Cert,
+    Dname,
+    Opt,
+    Apl,
+    Ds,
+    Rrsig,
+    Nsec,
+    Dnskey,
+    Nsec3,
+    Nsec3Param,
+    // Deprecated in favour of TXT records, but still published by some old zones
+    // https://www.rfc-editor.org/rfc/rfc7208#section-3.1
+    Spf,
+    // Only valid in questions, asking for a transfer of the whole zone
+    Axfr,
+    // Only valid in questions, asking for records of every type
+    Any,
+    // Any type we don't model, kept as the raw type number
+    Unknown(u16),
+}
+
+impl From<u16> for QType {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => QType::A,
+            2 => QType::Ns,
+            5 => QType::Cname,
+            6 => QType::Soa,
+            13 => QType::Hinfo,
+            15 => QType::Mx,
+            16 => QType::Txt,
+            28 => QType::Aaaa,
+            33 => QType::Srv,
+            37 => QType::Cert,
+            39 => QType::Dname,
+            41 => QType::Opt,
+            42 => QType::Apl,
+            43 => QType::Ds,
+            46 => QType::Rrsig,
+            47 => QType::Nsec,
+            48 => QType::Dnskey,
+            50 => QType::Nsec3,
+            51 => QType::Nsec3Param,
+            99 => QType::Spf,
+            252 => QType::Axfr,
+            255 => QType::Any,
+            other => QType::Unknown(other),
+        }
+    }
+}
+
+impl From<QType> for u16 {
+    fn from(value: QType) -> Self {
+        match value {
+            QType::A => 1,
+            QType::Ns => 2,
+            QType::Cname => 5,
+            QType::Soa => 6,
+            QType::Hinfo => 13,
+            QType::Mx => 15,
+            QType::Txt => 16,
+            QType::Aaaa => 28,
+            QType::Srv => 33,
+            QType::Cert => 37,
+            QType::Dname => 39,
+            QType::Opt => 41,
+            QType::Apl => 42,
+            QType::Ds => 43,
+            QType::Rrsig => 46,
+            QType::Nsec => 47,
+            QType::Dnskey => 48,
+            QType::Nsec3 => 50,
+            QType::Nsec3Param => 51,
+            QType::Spf => 99,
+            QType::Axfr => 252,
+            QType::Any => 255,
+            QType::Unknown(other) => other,
+        }
+    }
+}
+
+// Types we don't know are shown in the generic TYPE<n> form
+// https://www.rfc-editor.org/rfc/rfc3597#section-5
+impl fmt::Display for QType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QType::A => write!(f, "A"),
+            QType::Ns => write!(f, "NS"),
+            QType::Cname => write!(f, "CNAME"),
+            QType::Soa => write!(f, "SOA"),
+            QType::Hinfo => write!(f, "HINFO"),
+            QType::Mx => write!(f, "MX"),
+            QType::Txt => write!(f, "TXT"),
+            QType::Aaaa => write!(f, "AAAA"),
+            QType::Srv => write!(f, "SRV"),
+            QType::Cert => write!(f, "CERT"),
+            QType::Dname => write!(f, "DNAME"),
+            QType::Opt => write!(f, "OPT"),
+            QType::Apl => write!(f, "APL"),
+            QType::Ds => write!(f, "DS"),
+            QType::Rrsig => write!(f, "RRSIG"),
+            QType::Nsec => write!(f, "NSEC"),
+            QType::Dnskey => write!(f, "DNSKEY"),
+            QType::Nsec3 => write!(f, "NSEC3"),
+            QType::Nsec3Param => write!(f, "NSEC3PARAM"),
+            QType::Spf => write!(f, "SPF"),
+            QType::Axfr => write!(f, "AXFR"),
+            QType::Any => write!(f, "ANY"),
+            QType::Unknown(other) => write!(f, "TYPE{}", other),
+        }
+    }
+}
+
+// The CLASS of a resource record, or QCLASS of a question
+// https://www.rfc-editor.org/rfc/rfc1035#section-3.2.4
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum QClass {
+    In,
+    Ch,
+    Hs,
+    // Any class we don't model. OPT records also reuse this field for their UDP payload size.
+    Unknown(u16),
+}
+
+impl From<u16> for QClass {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => QClass::In,
+            3 => QClass::Ch,
+            4 => QClass::Hs,
+            other => QClass::Unknown(other),
+        }
+    }
+}
+
+impl From<QClass> for u16 {
+    fn from(value: QClass) -> Self {
+        match value {
+            QClass::In => 1,
+            QClass::Ch => 3,
+            QClass::Hs => 4,
+            QClass::Unknown(other) => other,
+        }
+    }
+}
+
+impl fmt::Display for QClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QClass::In => write!(f, "IN"),
+            QClass::Ch => write!(f, "CH"),
+            QClass::Hs => write!(f, "HS"),
+            QClass::Unknown(other) => write!(f, "CLASS{}", other),
+        }
+    }
+}
+
+// The answer, authority and additional sections all share the same resource record format
+// https://www.rfc-editor.org/rfc/rfc1035#section-4.1.3
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ResourceRecord {
+    pub name: Vec<Vec<u8>>,
+    pub rtype: QType,
+    pub class: QClass,
+    // Seconds the record may be cached for, as received
+    pub ttl: u32,
+    pub rdata: Rdata,
+}
+
+impl ResourceRecord {
+    pub(crate) fn read(reader: &mut Reader) -> Result<Self, ParseError> {
+        let name = reader.read_name()?;
+        let rtype = QType::from(reader.read_u16()?);
+        let class = QClass::from(reader.read_u16()?);
+        let ttl = reader.read_u32()?;
+        let rdlength = reader.read_u16()?;
+        let rdata = Rdata::read(rtype, reader, rdlength as usize)?;
+        Ok(ResourceRecord {
+            name,
+            rtype,
+            class,
+            ttl,
+            rdata,
+        })
+    }
+
+    pub(crate) fn write(&self, writer: &mut Writer) {
+        writer.write_name(&self.name);
+        writer.write_u16(self.rtype.into());
+        writer.write_u16(self.class.into());
+        writer.write_u32(self.ttl);
+        // RDLENGTH isn't known until the RDATA is written, as names in it may be compressed
+        let length_at = writer.len();
+        writer.write_u16(0);
+        self.rdata.write(writer);
+        writer.set_u16(length_at, (writer.len() - length_at - 2) as u16);
+    }
+
+    pub(crate) fn read_section(reader: &mut Reader, count: u16) -> Result<Vec<Self>, ParseError> {
+        (0..count).map(|_| Self::read(reader)).collect()
+    }
+}
+
+// Shown in master file form: name, TTL, class, type and RDATA
+impl fmt::Display for ResourceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            DisplayName(&self.name),
+            self.ttl,
+            self.class,
+            self.rtype,
+            self.rdata
+        )
+    }
+}
+
+// Character-strings are prefixed with a single length byte so can hold at most 255 bytes
+pub(crate) const MAX_CHARACTER_STRING: usize = 255;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Rdata {
+    A(Ipv4Addr),
+    // An authoritative name server for the owner's zone
+    Ns(Vec<Vec<u8>>),
+    // The canonical name this alias points to
+    Cname(Vec<Vec<u8>>),
+    // Start of authority: the zone's primary server and responsible mailbox, followed by the
+    // serial and timers secondaries use. `minimum` is also the TTL for negative answers.
+    // https://www.rfc-editor.org/rfc/rfc1035#section-3.3.13
+    Soa {
+        mname: Vec<Vec<u8>>,
+        rname: Vec<Vec<u8>>,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    // The host's CPU and operating system, each a single character-string
+    // https://www.rfc-editor.org/rfc/rfc1035#section-3.3.2
+    Hinfo {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    // A mail exchange for the owner, lower preferences being tried first
+    Mx {
+        preference: u16,
+        exchange: Vec<Vec<u8>>,
+    },
+    // TXT data is one or more character-strings of at most 255 bytes each. The boundaries between
+    // them are kept as received, as some formats built on TXT give them meaning.
+    Txt(Vec<Vec<u8>>),
+    Aaaa(Ipv6Addr),
+    // A server for a service, lower priorities being tried first and, among equal priorities,
+    // higher weights more often
+    // https://www.rfc-editor.org/rfc/rfc2782
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Vec<Vec<u8>>,
+    },
+    // A certificate or CRL, identified by its type and the tag and algorithm of the key
+    // https://www.rfc-editor.org/rfc/rfc4398#section-2
+    Cert {
+        cert_type: u16,
+        key_tag: u16,
+        algorithm: u8,
+        certificate: Vec<u8>,
+    },
+    // Redirects every name below the owner to the same name below the target
+    // https://www.rfc-editor.org/rfc/rfc6672#section-2.1
+    Dname(Vec<Vec<u8>>),
+    // The EDNS options carried by an OPT pseudo-record
+    Opt(Vec<EdnsOption>),
+    // Address prefix list, e.g. for access control data
+    Apl(Vec<AplItem>),
+    // A digest of one of a child zone's keys, published by the parent to vouch for it
+    // https://www.rfc-editor.org/rfc/rfc4034#section-5.1
+    Ds {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    // A signature over the owner's records of one type, made with the key of the signer's zone
+    // with the given tag, valid from the inception to the expiration time
+    // https://www.rfc-editor.org/rfc/rfc4034#section-3.1
+    Rrsig {
+        type_covered: QType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: Vec<Vec<u8>>,
+        signature: Vec<u8>,
+    },
+    // A public key the owner zone's records are signed with
+    // https://www.rfc-editor.org/rfc/rfc4034#section-2.1
+    Dnskey {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    // Authenticated denial of existence: the hash of the next owner name in the zone, in hash
+    // order, and the types present at this record's hashed owner
+    // https://www.rfc-editor.org/rfc/rfc5155#section-3.2
+    Nsec3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed_owner: Vec<u8>,
+        type_bitmaps: Vec<QType>,
+    },
+    // The hash parameters an authoritative server uses to compute NSEC3 owner names
+    // https://www.rfc-editor.org/rfc/rfc5155#section-4.2
+    Nsec3Param {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+    },
+    // RDATA for a type we don't parse, kept as-is
+    Raw(Vec<u8>),
+}
+
+impl Rdata {
+    /// TXT RDATA holding `text`, split across as many character-strings as it needs, as for
+    /// values longer than 255 bytes such as DKIM keys.
+    pub fn txt(text: &str) -> Self {
+        Rdata::Txt(split_text(text.as_bytes()))
+    }
+
+    /// The data of TXT RDATA with its character-strings joined back together, or `None` for
+    /// other types.
+    pub fn txt_data(&self) -> Option<Vec<u8>> {
+        match self {
+            Rdata::Txt(strings) => Some(strings.concat()),
+            _ => None,
+        }
+    }
+
+    pub fn from_bytes(rtype: QType, bytes: &[u8]) -> Result<Self, ParseError> {
+        match rtype {
+            QType::A => {
+                let octets: [u8; 4] = bytes
+                    .try_into()
+                    .map_err(|_| ParseError::RdataLength(rtype.into()))?;
+                Ok(Rdata::A(Ipv4Addr::from(octets)))
+            }
+            QType::Aaaa => {
+                let octets: [u8; 16] = bytes
+                    .try_into()
+                    .map_err(|_| ParseError::RdataLength(rtype.into()))?;
+                Ok(Rdata::Aaaa(Ipv6Addr::from(octets)))
+            }
+            QType::Ns | QType::Cname | QType::Soa | QType::Mx | QType::Srv | QType::Dname => {
+                Self::read(rtype, &mut Reader::new(bytes, 0), bytes.len())
+            }
+            QType::Hinfo => {
+                let (cpu, rest) = split_character_string(bytes)?;
+                let (os, rest) = split_character_string(rest)?;
+                if !rest.is_empty() {
+                    return Err(ParseError::RdataLength(rtype.into()));
+                }
+                Ok(Rdata::Hinfo {
+                    cpu: cpu.to_vec(),
+                    os: os.to_vec(),
+                })
+            }
+            // SPF RDATA has exactly the same format as TXT
+            QType::Txt | QType::Spf => Ok(Rdata::Txt(parse_character_strings(bytes)?)),
+            QType::Cert => {
+                if bytes.len() < 5 {
+                    return Err(ParseError::UnexpectedEnd);
+                }
+                Ok(Rdata::Cert {
+                    cert_type: u16::from_be_bytes([bytes[0], bytes[1]]),
+                    key_tag: u16::from_be_bytes([bytes[2], bytes[3]]),
+                    algorithm: bytes[4],
+                    certificate: bytes[5..].to_vec(),
+                })
+            }
+            QType::Opt => Ok(Rdata::Opt(EdnsOption::parse_all(bytes)?)),
+            QType::Ds => {
+                let [t1, t2, algorithm, digest_type, digest @ ..] = bytes else {
+                    return Err(ParseError::UnexpectedEnd);
+                };
+                Ok(Rdata::Ds {
+                    key_tag: u16::from_be_bytes([*t1, *t2]),
+                    algorithm: *algorithm,
+                    digest_type: *digest_type,
+                    digest: digest.to_vec(),
+                })
+            }
+            // The signer's name is never compressed, so can be read from the RDATA alone
+            QType::Rrsig => {
+                let mut reader = Reader::new(bytes, 0);
+                Ok(Rdata::Rrsig {
+                    type_covered: QType::from(reader.read_u16()?),
+                    algorithm: reader.read_u8()?,
+                    labels: reader.read_u8()?,
+                    original_ttl: reader.read_u32()?,
+                    expiration: reader.read_u32()?,
+                    inception: reader.read_u32()?,
+                    key_tag: reader.read_u16()?,
+                    signer_name: reader.read_name()?,
+                    signature: bytes[reader.position()..].to_vec(),
+                })
+            }
+            QType::Dnskey => {
+                let [f1, f2, protocol, algorithm, public_key @ ..] = bytes else {
+                    return Err(ParseError::UnexpectedEnd);
+                };
+                Ok(Rdata::Dnskey {
+                    flags: u16::from_be_bytes([*f1, *f2]),
+                    protocol: *protocol,
+                    algorithm: *algorithm,
+                    public_key: public_key.to_vec(),
+                })
+            }
+            QType::Apl => Ok(Rdata::Apl(AplItem::parse_all(bytes)?)),
+            QType::Nsec3 | QType::Nsec3Param => {
+                let [hash_algorithm, flags, i1, i2, rest @ ..] = bytes else {
+                    return Err(ParseError::UnexpectedEnd);
+                };
+                let iterations = u16::from_be_bytes([*i1, *i2]);
+                let (salt, rest) = split_character_string(rest)?;
+                if rtype == QType::Nsec3Param {
+                    if !rest.is_empty() {
+                        return Err(ParseError::RdataLength(rtype.into()));
+                    }
+                    return Ok(Rdata::Nsec3Param {
+                        hash_algorithm: *hash_algorithm,
+                        flags: *flags,
+                        iterations,
+                        salt: salt.to_vec(),
+                    });
+                }
+                // The next hashed owner has the same length byte prefix as a character-string
+                let (next_hashed_owner, rest) = split_character_string(rest)?;
+                Ok(Rdata::Nsec3 {
+                    hash_algorithm: *hash_algorithm,
+                    flags: *flags,
+                    iterations,
+                    salt: salt.to_vec(),
+                    next_hashed_owner: next_hashed_owner.to_vec(),
+                    type_bitmaps: parse_type_bitmaps(rest)?,
+                })
+            }
+            _ => Ok(Rdata::Raw(bytes.to_vec())),
+        }
+    }
+
+    // Reads `len` bytes of RDATA from within a message. Names in the RDATA of the well known
+    // types may be compressed against the rest of the message, so can only be read from here.
+    pub(crate) fn read(rtype: QType, reader: &mut Reader, len: usize) -> Result<Self, ParseError> {
+        let end = reader.position() + len;
+        let rdata = match rtype {
+            QType::Ns => Rdata::Ns(reader.read_name()?),
+            QType::Cname => Rdata::Cname(reader.read_name()?),
+            QType::Dname => Rdata::Dname(reader.read_name()?),
+            QType::Soa => Rdata::Soa {
+                mname: reader.read_name()?,
+                rname: reader.read_name()?,
+                serial: reader.read_u32()?,
+                refresh: reader.read_u32()?,
+                retry: reader.read_u32()?,
+                expire: reader.read_u32()?,
+                minimum: reader.read_u32()?,
+            },
+            QType::Mx => Rdata::Mx {
+                preference: reader.read_u16()?,
+                exchange: reader.read_name()?,
+            },
+            QType::Srv => Rdata::Srv {
+                priority: reader.read_u16()?,
+                weight: reader.read_u16()?,
+                port: reader.read_u16()?,
+                target: reader.read_name()?,
+            },
+            _ => return Self::from_bytes(rtype, reader.read_bytes(len)?),
+        };
+        if reader.position() != end {
+            return Err(ParseError::RdataLength(rtype.into()));
+        }
+        Ok(rdata)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WriteError> {
+        let mut writer = Writer::new();
+        self.write(&mut writer);
+        writer.finish()
+    }
+
+    pub(crate) fn write(&self, writer: &mut Writer) {
+        match self {
+            Rdata::A(address) => writer.write_bytes(&address.octets()),
+            Rdata::Ns(name) | Rdata::Cname(name) => writer.write_name(name),
+            // Servers that don't know DNAME couldn't follow a pointer in its RDATA
+            Rdata::Dname(target) => writer.write_name_uncompressed(target),
+            Rdata::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                writer.write_name(mname);
+                writer.write_name(rname);
+                for value in [serial, refresh, retry, expire, minimum] {
+                    writer.write_u32(*value);
+                }
+            }
+            Rdata::Mx {
+                preference,
+                exchange,
+            } => {
+                writer.write_u16(*preference);
+                writer.write_name(exchange);
+            }
+            Rdata::Hinfo { cpu, os } => {
+                writer.write_character_string(cpu);
+                writer.write_character_string(os);
+            }
+            Rdata::Txt(strings) => write_character_strings(writer, strings),
+            Rdata::Aaaa(address) => writer.write_bytes(&address.octets()),
+            // The target mustn't be compressed, though we accept it compressed when reading
+            // https://www.rfc-editor.org/rfc/rfc2782
+            Rdata::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                writer.write_u16(*priority);
+                writer.write_u16(*weight);
+                writer.write_u16(*port);
+                writer.write_name_uncompressed(target);
+            }
+            Rdata::Cert {
+                cert_type,
+                key_tag,
+                algorithm,
+                certificate,
+            } => {
+                writer.write_u16(*cert_type);
+                writer.write_u16(*key_tag);
+                writer.write_u8(*algorithm);
+                writer.write_bytes(certificate);
+            }
+            Rdata::Opt(options) => writer.write_bytes(&EdnsOption::serialize_all(options)),
+            Rdata::Apl(items) => writer.write_bytes(&AplItem::serialize_all(items)),
+            Rdata::Ds {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                writer.write_u16(*key_tag);
+                writer.write_u8(*algorithm);
+                writer.write_u8(*digest_type);
+                writer.write_bytes(digest);
+            }
+            Rdata::Rrsig {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                writer.write_u16((*type_covered).into());
+                writer.write_u8(*algorithm);
+                writer.write_u8(*labels);
+                writer.write_u32(*original_ttl);
+                writer.write_u32(*expiration);
+                writer.write_u32(*inception);
+                writer.write_u16(*key_tag);
+                writer.write_name_uncompressed(signer_name);
+                writer.write_bytes(signature);
+            }
+            Rdata::Dnskey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                writer.write_u16(*flags);
+                writer.write_u8(*protocol);
+                writer.write_u8(*algorithm);
+                writer.write_bytes(public_key);
+            }
+            Rdata::Nsec3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                type_bitmaps,
+            } => {
+                writer.write_u8(*hash_algorithm);
+                writer.write_u8(*flags);
+                writer.write_u16(*iterations);
+                writer.write_character_string(salt);
+                writer.write_character_string(next_hashed_owner);
+                writer.write_bytes(&serialize_type_bitmaps(type_bitmaps));
+            }
+            Rdata::Nsec3Param {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => {
+                writer.write_u8(*hash_algorithm);
+                writer.write_u8(*flags);
+                writer.write_u16(*iterations);
+                writer.write_character_string(salt);
+            }
+            Rdata::Raw(bytes) => writer.write_bytes(bytes),
+        }
+    }
+}
+
+// RDATA is shown in its type's presentation format where we have one, and otherwise in the
+// generic form of `\#`, the length and the data as hex
+// https://www.rfc-editor.org/rfc/rfc3597#section-5
+impl fmt::Display for Rdata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rdata::A(address) => write!(f, "{}", address),
+            Rdata::Ns(name) | Rdata::Cname(name) | Rdata::Dname(name) => {
+                write!(f, "{}", DisplayName(name))
+            }
+            Rdata::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                DisplayName(mname),
+                DisplayName(rname),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            Rdata::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, DisplayName(exchange)),
+            Rdata::Hinfo { cpu, os } => {
+                fmt_character_string(f, cpu)?;
+                write!(f, " ")?;
+                fmt_character_string(f, os)
+            }
+            Rdata::Txt(strings) => {
+                for (i, string) in strings.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    fmt_character_string(f, string)?;
+                }
+                Ok(())
+            }
+            Rdata::Aaaa(address) => write!(f, "{}", address),
+            Rdata::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                priority,
+                weight,
+                port,
+                DisplayName(target)
+            ),
+            Rdata::Apl(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Rdata::Nsec3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                type_bitmaps,
+            } => {
+                write!(f, "{} {} {} ", hash_algorithm, flags, iterations)?;
+                fmt_salt(f, salt)?;
+                write!(f, " {}", base32hex::encode(next_hashed_owner))?;
+                for rtype in type_bitmaps {
+                    write!(f, " {}", rtype)?;
+                }
+                Ok(())
+            }
+            Rdata::Nsec3Param {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+            } => {
+                write!(f, "{} {} {} ", hash_algorithm, flags, iterations)?;
+                fmt_salt(f, salt)
+            }
+            Rdata::Ds {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                write!(f, "{} {} {} ", key_tag, algorithm, digest_type)?;
+                for b in digest {
+                    write!(f, "{:02X}", b)?;
+                }
+                Ok(())
+            }
+            Rdata::Rrsig {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                write!(
+                    f,
+                    "{} {} {} {} ",
+                    type_covered, algorithm, labels, original_ttl
+                )?;
+                fmt_timestamp(f, *expiration)?;
+                write!(f, " ")?;
+                fmt_timestamp(f, *inception)?;
+                write!(
+                    f,
+                    " {} {} {}",
+                    key_tag,
+                    DisplayName(signer_name),
+                    base64::encode(signature)
+                )
+            }
+            Rdata::Dnskey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => write!(
+                f,
+                "{} {} {} {}",
+                flags,
+                protocol,
+                algorithm,
+                base64::encode(public_key)
+            ),
+            Rdata::Cert { .. } | Rdata::Opt(_) | Rdata::Raw(_) => {
+                let bytes = self.to_bytes().map_err(|_| fmt::Error)?;
+                write!(f, "\\# {}", bytes.len())?;
+                if !bytes.is_empty() {
+                    write!(f, " ")?;
+                    for b in bytes {
+                        write!(f, "{:02x}", b)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// A single prefix in an APL record. Each item is a 2 byte address family (1 for IPv4, 2 for
+// IPv6), the prefix length, then a byte holding the negation flag in its top bit and the length
+// of the address data in the other 7, then the address data with trailing zero bytes removed.
+// https://www.rfc-editor.org/rfc/rfc3123#section-4
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AplItem {
+    pub family: u16,
+    pub prefix: u8,
+    pub negation: bool,
+    pub address: Vec<u8>,
+}
+
+impl AplItem {
+    fn parse_all(bytes: &[u8]) -> Result<Vec<Self>, ParseError> {
+        let mut items = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                return Err(ParseError::UnexpectedEnd);
+            }
+            let family = u16::from_be_bytes([rest[0], rest[1]]);
+            let prefix = rest[2];
+            let negation = rest[3] & 0b1000_0000 != 0;
+            let len = (rest[3] & 0b0111_1111) as usize;
+            let address = rest.get(4..4 + len).ok_or(ParseError::UnexpectedEnd)?;
+            items.push(AplItem {
+                family,
+                prefix,
+                negation,
+                address: address.to_vec(),
+            });
+            rest = &rest[4 + len..];
+        }
+        Ok(items)
+    }
+
+    fn serialize_all(items: &[Self]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for item in items {
+            let len = item
+                .address
+                .iter()
+                .rposition(|&b| b != 0)
+                .map_or(0, |last| last + 1);
+            bytes.extend_from_slice(&item.family.to_be_bytes());
+            bytes.push(item.prefix);
+            bytes.push(((item.negation as u8) << 7) | len as u8);
+            bytes.extend_from_slice(&item.address[..len]);
+        }
+        bytes
+    }
+}
+
+// e.g. "1:192.168.32.0/21" or "!2:2001:db8::/32"
+impl fmt::Display for AplItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negation {
+            write!(f, "!")?;
+        }
+        write!(f, "{}:", self.family)?;
+        match self.family {
+            1 => {
+                let mut octets = [0; 4];
+                let len = self.address.len().min(4);
+                octets[..len].copy_from_slice(&self.address[..len]);
+                write!(f, "{}", Ipv4Addr::from(octets))?;
+            }
+            2 => {
+                let mut octets = [0; 16];
+                let len = self.address.len().min(16);
+                octets[..len].copy_from_slice(&self.address[..len]);
+                write!(f, "{}", Ipv6Addr::from(octets))?;
+            }
+            _ => {
+                for b in &self.address {
+                    write!(f, "{:02x}", b)?;
+                }
+            }
+        }
+        write!(f, "/{}", self.prefix)
+    }
+}
+
+// The types present at a name, as a bitmap per "window" of 256 type numbers. Each window is its
+// number, the length of its bitmap (trailing zero bytes left off) and the bitmap, with the most
+// significant bit of the first byte standing for the window's lowest type.
+// https://www.rfc-editor.org/rfc/rfc4034#section-4.1.2
+fn parse_type_bitmaps(bytes: &[u8]) -> Result<Vec<QType>, ParseError> {
+    let mut types = Vec::new();
+    let mut rest = bytes;
+    while let [window, len, tail @ ..] = rest {
+        let len = *len as usize;
+        if len == 0 || len > 32 {
+            return Err(ParseError::RdataLength(QType::Nsec3.into()));
+        }
+        let bitmap = tail.get(..len).ok_or(ParseError::UnexpectedEnd)?;
+        for (i, &byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    let number = (*window as u16) << 8 | (i * 8 + bit) as u16;
+                    types.push(QType::from(number));
+                }
+            }
+        }
+        rest = &tail[len..];
+    }
+    if !rest.is_empty() {
+        return Err(ParseError::UnexpectedEnd);
+    }
+    Ok(types)
+}
+
+fn serialize_type_bitmaps(types: &[QType]) -> Vec<u8> {
+    let mut numbers: Vec<u16> = types.iter().map(|&rtype| rtype.into()).collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+
+    let mut bytes = Vec::new();
+    let mut rest = numbers.as_slice();
+    while let Some(&first) = rest.first() {
+        let window = first >> 8;
+        let count = rest
+            .iter()
+            .take_while(|&&number| number >> 8 == window)
+            .count();
+        let (numbers, tail) = rest.split_at(count);
+        let mut bitmap = [0_u8; 32];
+        for &number in numbers {
+            let low = (number & 0xff) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+        }
+        let len = bitmap
+            .iter()
+            .rposition(|&b| b != 0)
+            .map_or(0, |last| last + 1);
+        bytes.push(window as u8);
+        bytes.push(len as u8);
+        bytes.extend_from_slice(&bitmap[..len]);
+        rest = tail;
+    }
+    bytes
+}
+
+// An NSEC3 salt is shown as hex, or "-" if there isn't one
+fn fmt_salt(f: &mut fmt::Formatter, salt: &[u8]) -> fmt::Result {
+    if salt.is_empty() {
+        return write!(f, "-");
+    }
+    for b in salt {
+        write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+// Writes an RRSIG time, seconds since 1970 in serial number arithmetic, as YYYYMMDDHHmmSS in UTC.
+// Converting the days to a date follows http://howardhinnant.github.io/date_algorithms.html
+// https://www.rfc-editor.org/rfc/rfc4034#section-3.2
+fn fmt_timestamp(f: &mut fmt::Formatter, seconds: u32) -> fmt::Result {
+    let days = seconds / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so that the leap day comes last
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + u32::from(month <= 2);
+    let time = seconds % 86400;
+    write!(
+        f,
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+// Splits a single character-string off the front of `bytes`, returning its contents and the
+// bytes after it
+fn split_character_string(bytes: &[u8]) -> Result<(&[u8], &[u8]), ParseError> {
+    let (&len, tail) = bytes.split_first().ok_or(ParseError::UnexpectedEnd)?;
+    let len = len as usize;
+    if tail.len() < len {
+        return Err(ParseError::UnexpectedEnd);
+    }
+    Ok(tail.split_at(len))
+}
+
+// Reads consecutive character-strings, keeping each separate
+fn parse_character_strings(bytes: &[u8]) -> Result<Vec<Vec<u8>>, ParseError> {
+    let mut strings = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let (string, tail) = split_character_string(rest)?;
+        strings.push(string.to_vec());
+        rest = tail;
+    }
+    Ok(strings)
+}
+
+// Splits `text` into as many full character-strings as needed. Empty text is still one zero
+// length string, as TXT RDATA must hold at least one.
+pub(crate) fn split_text(text: &[u8]) -> Vec<Vec<u8>> {
+    if text.is_empty() {
+        return vec![Vec::new()];
+    }
+    text.chunks(MAX_CHARACTER_STRING)
+        .map(|chunk| chunk.to_vec())
+        .collect()
+}
+
+// Writes each string with its length byte. No strings at all is written as a single zero
+// length one, as the RDATA must hold at least one.
+fn write_character_strings(writer: &mut Writer, strings: &[Vec<u8>]) {
+    if strings.is_empty() {
+        writer.write_u8(0);
+    }
+    for string in strings {
+        writer.write_character_string(string);
+    }
+}
+
+// Writes a quoted character-string, escaping quotes and backslashes, and anything unprintable
+// as \DDD
+fn fmt_character_string(f: &mut fmt::Formatter, string: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    for &b in string {
+        match b {
+            b'"' | b'\\' => write!(f, "\\{}", b as char)?,
+            0x20..=0x7e => write!(f, "{}", b as char)?,
+            _ => write!(f, "\\{:03}", b)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::name::parse_name;
+
+    #[test]
+    fn long_txt_splits_across_character_strings() {
+        let text = "k".repeat(300);
+        let rdata = Rdata::txt(&text);
+        let Rdata::Txt(strings) = &rdata else {
+            panic!("not TXT: {:?}", rdata);
+        };
+        assert_eq!(strings.len(), 2);
+        assert_eq!(strings[0].len(), 255);
+        assert_eq!(strings[1].len(), 45);
+
+        let bytes = rdata.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 302);
+        assert_eq!(bytes[0], 255);
+        assert_eq!(bytes[256], 45);
+        assert_eq!(Rdata::from_bytes(QType::Txt, &bytes).unwrap(), rdata);
+    }
+
+    #[test]
+    fn long_txt_data_round_trips() {
+        let text = [b"v=DKIM1; k=rsa; p=".as_slice(), &[b'A'; 282]].concat();
+        assert_eq!(text.len(), 300);
+        let rdata = Rdata::txt(std::str::from_utf8(&text).unwrap());
+        let bytes = rdata.to_bytes().unwrap();
+        let parsed = Rdata::from_bytes(QType::Txt, &bytes).unwrap();
+        assert_eq!(parsed.txt_data(), Some(text));
+        assert_eq!(Rdata::A(Ipv4Addr::LOCALHOST).txt_data(), None);
+    }
+
+    #[test]
+    fn character_strings_over_255_bytes_are_not_written() {
+        let long = vec![b'x'; 300];
+        let too_long = Err(WriteError::CharacterStringTooLong(300));
+        assert_eq!(Rdata::Txt(vec![long.clone()]).to_bytes(), too_long);
+        let hinfo = Rdata::Hinfo {
+            cpu: long.clone(),
+            os: b"Linux".to_vec(),
+        };
+        assert_eq!(hinfo.to_bytes(), too_long);
+        let nsec3 = Rdata::Nsec3 {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 0,
+            salt: Vec::new(),
+            next_hashed_owner: long.clone(),
+            type_bitmaps: vec![QType::A],
+        };
+        assert_eq!(nsec3.to_bytes(), too_long);
+        let nsec3param = Rdata::Nsec3Param {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 0,
+            salt: long,
+        };
+        assert_eq!(nsec3param.to_bytes(), too_long);
+    }
+
+    #[test]
+    fn txt_keeps_string_boundaries() {
+        let rdata = Rdata::from_bytes(QType::Txt, b"\x01a\x01b").unwrap();
+        assert_eq!(rdata, Rdata::Txt(vec![b"a".to_vec(), b"b".to_vec()]));
+        assert_eq!(rdata.to_string(), "\"a\" \"b\"");
+        assert_eq!(rdata.to_bytes().unwrap(), b"\x01a\x01b");
+    }
+
+    #[test]
+    fn empty_txt_is_one_empty_string() {
+        assert_eq!(Rdata::txt(""), Rdata::Txt(vec![Vec::new()]));
+        assert_eq!(Rdata::txt("").to_bytes().unwrap(), [0]);
+    }
+
+    #[test]
+    fn truncated_txt_is_rejected() {
+        assert_eq!(
+            Rdata::from_bytes(QType::Txt, b"\x05ab"),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn apl_with_ipv4_and_negated_ipv6_prefixes() {
+        // 1:192.168.32.0/21 and !2:2001:db8::/32, with trailing zero bytes of each address left
+        // off as RFC 3123 requires
+        let bytes = [
+            0x00, 0x01, 21, 0x03, 192, 168, 32, //
+            0x00, 0x02, 32, 0x84, 0x20, 0x01, 0x0d, 0xb8,
+        ];
+        let rdata = Rdata::from_bytes(QType::Apl, &bytes).unwrap();
+        let Rdata::Apl(items) = &rdata else {
+            panic!("not APL: {:?}", rdata);
+        };
+        assert_eq!(
+            items[0],
+            AplItem {
+                family: 1,
+                prefix: 21,
+                negation: false,
+                address: vec![192, 168, 32],
+            }
+        );
+        assert_eq!(
+            items[1],
+            AplItem {
+                family: 2,
+                prefix: 32,
+                negation: true,
+                address: vec![0x20, 0x01, 0x0d, 0xb8],
+            }
+        );
+        assert_eq!(rdata.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn apl_trailing_zero_bytes_are_trimmed() {
+        let rdata = Rdata::Apl(vec![AplItem {
+            family: 1,
+            prefix: 8,
+            negation: false,
+            address: vec![10, 0, 0, 0],
+        }]);
+        assert_eq!(rdata.to_bytes().unwrap(), [0x00, 0x01, 8, 0x01, 10]);
+    }
+
+    #[test]
+    fn cert_round_trips() {
+        // A PKIX certificate with key tag 12345 and algorithm 8, then the certificate itself
+        let bytes = [0x00, 0x01, 0x30, 0x39, 0x08, 0xde, 0xad, 0xbe, 0xef];
+        let rdata = Rdata::from_bytes(QType::Cert, &bytes).unwrap();
+        assert_eq!(
+            rdata,
+            Rdata::Cert {
+                cert_type: 1,
+                key_tag: 12345,
+                algorithm: 8,
+                certificate: vec![0xde, 0xad, 0xbe, 0xef],
+            }
+        );
+        assert_eq!(rdata.to_bytes().unwrap(), bytes);
+        assert_eq!(
+            Rdata::from_bytes(QType::Cert, &bytes[..4]),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn unknown_records_display_in_generic_form() {
+        let record = ResourceRecord {
+            name: parse_name("host.example"),
+            rtype: QType::Unknown(65280),
+            class: QClass::In,
+            ttl: 3600,
+            rdata: Rdata::from_bytes(QType::Unknown(65280), &[0x0a, 0, 0, 1]).unwrap(),
+        };
+        assert_eq!(record.rdata, Rdata::Raw(vec![0x0a, 0, 0, 1]));
+        assert_eq!(
+            record.to_string(),
+            "host.example. 3600 IN TYPE65280 \\# 4 0a000001"
+        );
+        assert_eq!(Rdata::Raw(Vec::new()).to_string(), "\\# 0");
+        assert_eq!(QClass::Unknown(42).to_string(), "CLASS42");
+    }
+
+    #[test]
+    fn known_rdata_uses_its_presentation_format() {
+        let rdata = Rdata::Apl(vec![AplItem {
+            family: 2,
+            prefix: 32,
+            negation: true,
+            address: vec![0x20, 0x01, 0x0d, 0xb8],
+        }]);
+        assert_eq!(rdata.to_string(), "!2:2001:db8::/32");
+        assert_eq!(Rdata::txt("say \"hi\"").to_string(), "\"say \\\"hi\\\"\"");
+    }
+
+    #[test]
+    fn hinfo_is_two_character_strings() {
+        let bytes = b"\x07RFC8482\x00";
+        let rdata = Rdata::from_bytes(QType::Hinfo, bytes).unwrap();
+        assert_eq!(
+            rdata,
+            Rdata::Hinfo {
+                cpu: b"RFC8482".to_vec(),
+                os: Vec::new(),
+            }
+        );
+        assert_eq!(rdata.to_string(), "\"RFC8482\" \"\"");
+        assert_eq!(
+            Rdata::from_bytes(QType::Hinfo, b"\x01a\x01b\x01c"),
+            Err(ParseError::RdataLength(13))
+        );
+    }
+
+    #[test]
+    fn nsec3_round_trips_salt_and_bitmap() {
+        // From RFC 5155 appendix A: 1 1 12 aabbccdd 2t7b4g4vsa5smi47k61mv5bv1a22bojr MX DNSKEY NS
+        // SOA NSEC3PARAM RRSIG
+        let next_hashed_owner = base32hex::decode("2t7b4g4vsa5smi47k61mv5bv1a22bojr").unwrap();
+        let mut bytes = vec![1, 1, 0, 12, 4, 0xaa, 0xbb, 0xcc, 0xdd, 20];
+        bytes.extend_from_slice(&next_hashed_owner);
+        bytes.extend_from_slice(&[0, 7, 0x22, 0x01, 0, 0, 0, 0x02, 0x90]);
+
+        let rdata = Rdata::from_bytes(QType::Nsec3, &bytes).unwrap();
+        assert_eq!(
+            rdata,
+            Rdata::Nsec3 {
+                hash_algorithm: 1,
+                flags: 1,
+                iterations: 12,
+                salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+                next_hashed_owner,
+                type_bitmaps: vec![
+                    QType::Ns,
+                    QType::Soa,
+                    QType::Mx,
+                    QType::Rrsig,
+                    QType::Dnskey,
+                    QType::Nsec3Param,
+                ],
+            }
+        );
+        assert_eq!(rdata.to_bytes().unwrap(), bytes);
+        assert_eq!(
+            rdata.to_string(),
+            "1 1 12 aabbccdd 2T7B4G4VSA5SMI47K61MV5BV1A22BOJR NS SOA MX RRSIG DNSKEY NSEC3PARAM"
+        );
+    }
+
+    #[test]
+    fn nsec3param_with_no_salt() {
+        let rdata = Rdata::from_bytes(QType::Nsec3Param, &[1, 0, 0, 0, 0]).unwrap();
+        assert_eq!(rdata.to_string(), "1 0 0 -");
+        assert_eq!(rdata.to_bytes().unwrap(), [1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn dnssec_records_round_trip_and_display() {
+        let ds = Rdata::Ds {
+            key_tag: 50837,
+            algorithm: 8,
+            digest_type: 2,
+            digest: vec![0x8e, 0xcb, 0x5d],
+        };
+        let dnskey = Rdata::Dnskey {
+            flags: 257,
+            protocol: 3,
+            algorithm: 8,
+            public_key: vec![3, 1, 0, 1],
+        };
+        let rrsig = Rdata::Rrsig {
+            type_covered: QType::A,
+            algorithm: 8,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 3155760000,
+            inception: 1709210096,
+            key_tag: 50837,
+            signer_name: parse_name("example"),
+            signature: vec![1, 2, 3],
+        };
+        for (rtype, rdata) in [
+            (QType::Ds, &ds),
+            (QType::Dnskey, &dnskey),
+            (QType::Rrsig, &rrsig),
+        ] {
+            let bytes = rdata.to_bytes().unwrap();
+            assert_eq!(&Rdata::from_bytes(rtype, &bytes).unwrap(), rdata);
+        }
+        assert_eq!(ds.to_string(), "50837 8 2 8ECB5D");
+        assert_eq!(dnskey.to_string(), "257 3 8 AwEAAQ==");
+        assert_eq!(
+            rrsig.to_string(),
+            "A 8 2 3600 20700101000000 20240229123456 50837 example. AQID"
+        );
+        // The signer's name is written in full even where it could be compressed
+        assert_eq!(rrsig.to_bytes().unwrap()[18..27], *b"\x07example\x00");
+        assert_eq!(
+            Rdata::from_bytes(QType::Dnskey, &[1, 1, 3]),
+            Err(ParseError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn spf_parses_like_txt() {
+        let bytes = b"\x0fv=spf1 -all ext\x03end";
+        let spf = Rdata::from_bytes(QType::Spf, bytes).unwrap();
+        assert_eq!(spf, Rdata::from_bytes(QType::Txt, bytes).unwrap());
+        assert_eq!(spf.to_string(), "\"v=spf1 -all ext\" \"end\"");
+        assert_eq!(spf.to_bytes().unwrap(), bytes);
+    }
+}
@@ -0,0 +1,586 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::error::ResolveError;
+use crate::header::{Header, HeaderFlags, OpCode, QueryOrReply, ResponseCode};
+use crate::id::{IdGenerator, RandomIds};
+use crate::message::{DNSMessage, MessageBuilder};
+use crate::name::{parse_name, same_name};
+use crate::question::Question;
+use crate::record::{QClass, QType, Rdata, ResourceRecord};
+use crate::tcp;
+use crate::transport::{self, Transport, UdpTransport, DEFAULT_TIMEOUT};
+
+// How many CNAMEs `resolve` will follow before giving up on the chain
+pub const DEFAULT_MAX_CNAME_CHAIN: usize = 8;
+
+// Sends queries to a single upstream server, over UDP unless given another transport
+pub struct StubResolver {
+    upstream: SocketAddr,
+    // How long to wait for each response, whichever transport it comes over, and for each read
+    // during a zone transfer
+    timeout: Duration,
+    max_cname_chain: usize,
+    ids: Box<dyn IdGenerator>,
+    transport: Box<dyn Transport>,
+}
+
+impl StubResolver {
+    pub fn new(upstream: SocketAddr) -> Self {
+        StubResolver {
+            upstream,
+            timeout: DEFAULT_TIMEOUT,
+            max_cname_chain: DEFAULT_MAX_CNAME_CHAIN,
+            ids: Box::new(RandomIds),
+            transport: Box::new(UdpTransport::new(upstream)),
+        }
+    }
+
+    /// Creates a resolver that sends every query over one socket connected to `upstream`.
+    pub fn with_connected_socket(upstream: SocketAddr) -> io::Result<Self> {
+        Ok(StubResolver::new(upstream).with_transport(UdpTransport::connected(upstream)?))
+    }
+
+    /// Sends queries over `transport` instead of UDP, e.g. TCP or a mock in tests.
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Box::new(transport);
+        self
+    }
+
+    /// Sets how long to wait for upstream to answer a query, or to send more of a zone transfer.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_max_cname_chain(mut self, max_cname_chain: usize) -> Self {
+        self.max_cname_chain = max_cname_chain;
+        self
+    }
+
+    /// Replaces the random query IDs, e.g. with `SequentialIds` so tests see stable bytes.
+    pub fn with_id_generator(mut self, ids: impl IdGenerator + 'static) -> Self {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    /// Like `query`, but if the answer is an alias whose target records weren't included the
+    /// target is queried for in turn. The returned answer section holds the whole CNAME chain
+    /// followed by the records it leads to. Chains longer than the configured maximum fail with
+    /// `CnameChainTooLong` so a looping or malicious chain can't keep us querying.
+    pub fn resolve(&self, name: &[Vec<u8>], qtype: QType) -> Result<DNSMessage, ResolveError> {
+        let mut target = name.to_vec();
+        let mut chain = Vec::new();
+        loop {
+            let mut response = self.query(&target, qtype)?;
+            let followed = chain.len();
+            while let Some((record, next)) = cname_for(&response.answers, &target) {
+                if chain.len() == self.max_cname_chain {
+                    return Err(ResolveError::CnameChainTooLong(self.max_cname_chain));
+                }
+                target = next.clone();
+                chain.push(record.clone());
+            }
+
+            let answered = response
+                .answers
+                .iter()
+                .any(|record| record.rtype == qtype && same_name(&record.name, &target));
+            // Only go round again if this response ended on an alias it didn't resolve
+            if chain.len() == followed
+                || answered
+                || qtype == QType::Cname
+                || response.header.header_flags.response_code != ResponseCode::NoError
+            {
+                response
+                    .answers
+                    .retain(|record| record.rtype != QType::Cname);
+                chain.append(&mut response.answers);
+                response.answers = chain;
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Asks the upstream for records of `qtype` at `name`.
+    pub fn query(&self, name: &[Vec<u8>], qtype: QType) -> Result<DNSMessage, ResolveError> {
+        self.exchange(&query_message(name, qtype))
+    }
+
+    /// Transfers the whole of `zone` from the upstream over TCP. Records are yielded as the
+    /// messages carrying them arrive, starting and ending with the zone's SOA record. Any error
+    /// is yielded as the last item.
+    /// https://www.rfc-editor.org/rfc/rfc5936#section-2.2
+    pub fn axfr(&self, zone: &str) -> impl Iterator<Item = Result<ResourceRecord, ResolveError>> {
+        let mut query = query_message(&parse_name(zone), QType::Axfr);
+        query.header.id = self.ids.next_id();
+        query.header.header_flags.recursion_desired = false;
+
+        let connection = query
+            .to_bytes()
+            .map_err(ResolveError::from)
+            .and_then(|bytes| {
+                let mut stream = transport::connect_tcp(self.upstream, self.timeout)?;
+                transport::write_framed(&mut stream, &bytes)?;
+                Ok(stream)
+            });
+        let (stream, error) = match connection {
+            Ok(stream) => (Some(stream), None),
+            Err(e) => (None, Some(e)),
+        };
+        Transfer {
+            stream,
+            id: query.header.id,
+            records: VecDeque::new(),
+            soa_seen: 0,
+            error,
+        }
+    }
+
+    /// Sends `query` upstream under a new ID and waits for the matching response.
+    /// The response is returned with the ID `query` originally had. A response whose answers
+    /// hold a CNAME chain longer than the configured maximum fails with `CnameChainTooLong`.
+    pub fn exchange(&self, query: &DNSMessage) -> Result<DNSMessage, ResolveError> {
+        let mut query = query.clone();
+        let original_id = query.header.id;
+        let id = self.ids.next_id();
+        query.header.id = id;
+        let bytes = query.to_bytes()?;
+
+        let response = DNSMessage::from_bytes(&self.transport.exchange(&bytes, self.timeout)?)?;
+        if response.header.id != id || response.header.header_flags.qr != QueryOrReply::Reply {
+            return Err(ResolveError::UnexpectedResponse(response.header.id));
+        }
+        // The upstream may have followed aliases for us, but a chain longer than we'd follow
+        // ourselves is refused all the same
+        if let Some(question) = query.questions.first() {
+            let mut target = &question.name;
+            let mut followed = 0;
+            while let Some((_, next)) = cname_for(&response.answers, target) {
+                if followed == self.max_cname_chain {
+                    return Err(ResolveError::CnameChainTooLong(self.max_cname_chain));
+                }
+                followed += 1;
+                target = next;
+            }
+        }
+        let mut response = response;
+        response.header.id = original_id;
+        Ok(response)
+    }
+}
+
+// A standard query for `name` and `qtype`, with RD set and an ID of 0 for the caller to replace
+fn query_message(name: &[Vec<u8>], qtype: QType) -> DNSMessage {
+    let header = Header {
+        id: 0,
+        header_flags: HeaderFlags {
+            qr: QueryOrReply::Query,
+            op_code: OpCode::Query,
+            authoritative_answer: false,
+            truncation: false,
+            recursion_desired: true,
+            recursion_available: false,
+            response_code: ResponseCode::NoError,
+        },
+        question_count: 0,
+        answer_record_count: 0,
+        authority_record_count: 0,
+        additional_record_count: 0,
+    };
+    MessageBuilder::new(header)
+        .question(Question {
+            name: name.to_vec(),
+            qtype,
+            qclass: QClass::In,
+        })
+        .build()
+}
+
+// The records of a zone transfer, read from the connection as they are needed. The upstream may
+// send them over any number of messages; the transfer is over once the SOA record it started
+// with comes round again.
+struct Transfer {
+    // None once the transfer is finished or has failed
+    stream: Option<TcpStream>,
+    id: u16,
+    // Records received but not yet yielded
+    records: VecDeque<ResourceRecord>,
+    soa_seen: usize,
+    // An error to yield before stopping
+    error: Option<ResolveError>,
+}
+
+impl Transfer {
+    // Reads messages until one carries some records
+    fn read_records(&mut self) -> Result<(), ResolveError> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(());
+        };
+        while self.records.is_empty() {
+            let bytes = match tcp::read_message(stream) {
+                Ok(Some(bytes)) => bytes,
+                Ok(None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before the closing SOA record",
+                    )
+                    .into())
+                }
+                Err(e) if transport::is_timeout(&e) => return Err(ResolveError::Timeout),
+                Err(e) => return Err(e.into()),
+            };
+            let response = DNSMessage::from_bytes(&bytes)?;
+            if response.header.id != self.id
+                || response.header.header_flags.qr != QueryOrReply::Reply
+            {
+                eprintln!(
+                    "Ignoring response with unexpected ID {}",
+                    response.header.id
+                );
+                continue;
+            }
+            let response_code = response.header.header_flags.response_code;
+            if response_code != ResponseCode::NoError {
+                return Err(ResolveError::TransferRefused(response_code));
+            }
+            self.records.extend(response.answers);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Transfer {
+    type Item = Result<ResourceRecord, ResolveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.read_records() {
+            self.error = Some(e);
+        }
+        if let Some(e) = self.error.take() {
+            self.stream = None;
+            self.records.clear();
+            return Some(Err(e));
+        }
+
+        let record = self.records.pop_front()?;
+        if record.rtype == QType::Soa {
+            self.soa_seen += 1;
+        } else if self.soa_seen == 0 {
+            self.stream = None;
+            self.records.clear();
+            return Some(Err(ResolveError::TransferMissingSoa));
+        }
+        if self.soa_seen == 2 {
+            // Anything after the closing SOA isn't part of the zone
+            self.stream = None;
+            self.records.clear();
+        }
+        Some(Ok(record))
+    }
+}
+
+// Finds the CNAME record for `name` and the name it points to
+fn cname_for<'a>(
+    records: &'a [ResourceRecord],
+    name: &[Vec<u8>],
+) -> Option<(&'a ResourceRecord, &'a Vec<Vec<u8>>)> {
+    records.iter().find_map(|record| match &record.rdata {
+        Rdata::Cname(target) if same_name(&record.name, name) => Some((record, target)),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+    use crate::id::SequentialIds;
+    use crate::transport::{TcpTransport, TransportError};
+    use crate::zone_file::load_zone;
+
+    // Answers `query` with an A record for its question holding `ip`
+    fn answer(query: &DNSMessage, ip: Ipv4Addr) -> Vec<u8> {
+        let mut response = query.clone();
+        response.header = query.header.reply(false);
+        response.answers.push(ResourceRecord {
+            name: query.questions[0].name.clone(),
+            rtype: QType::A,
+            class: QClass::In,
+            ttl: 300,
+            rdata: Rdata::A(ip),
+        });
+        response.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn connected_socket_ignores_other_sources() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spoofer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = StubResolver::with_connected_socket(upstream.local_addr().unwrap()).unwrap();
+
+        let server = thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, client) = upstream.recv_from(&mut buf).unwrap();
+            let query = DNSMessage::from_bytes(&buf[..size]).unwrap();
+            // A forged answer with the right ID, but from the wrong address, gets there first
+            spoofer
+                .send_to(&answer(&query, Ipv4Addr::new(203, 0, 113, 66)), client)
+                .unwrap();
+            thread::sleep(Duration::from_millis(50));
+            upstream
+                .send_to(&answer(&query, Ipv4Addr::new(198, 51, 100, 1)), client)
+                .unwrap();
+        });
+
+        let response = resolver
+            .query(&parse_name("example.com"), QType::A)
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(
+            response.answers[0].rdata,
+            Rdata::A(Ipv4Addr::new(198, 51, 100, 1))
+        );
+    }
+
+    #[test]
+    fn responses_with_the_wrong_id_are_ignored() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = StubResolver::new(upstream.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, client) = upstream.recv_from(&mut buf).unwrap();
+            let mut query = DNSMessage::from_bytes(&buf[..size]).unwrap();
+            let id = query.header.id;
+            query.header.id = id.wrapping_add(1);
+            upstream
+                .send_to(&answer(&query, Ipv4Addr::new(203, 0, 113, 66)), client)
+                .unwrap();
+            query.header.id = id;
+            upstream
+                .send_to(&answer(&query, Ipv4Addr::new(198, 51, 100, 1)), client)
+                .unwrap();
+        });
+
+        let response = resolver
+            .query(&parse_name("example.com"), QType::A)
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(
+            response.answers[0].rdata,
+            Rdata::A(Ipv4Addr::new(198, 51, 100, 1))
+        );
+    }
+
+    #[test]
+    fn tcp_responses_with_the_wrong_id_are_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let resolver = StubResolver::new(addr).with_transport(TcpTransport::new(addr));
+
+        let upstream = thread::spawn(move || {
+            for right_id_after in [true, false] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let query = tcp::read_message(&mut stream).unwrap().unwrap();
+                let mut query = DNSMessage::from_bytes(&query).unwrap();
+                let id = query.header.id;
+                query.header.id = id.wrapping_add(1);
+                let wrong = answer(&query, Ipv4Addr::new(203, 0, 113, 66));
+                transport::write_framed(&mut stream, &wrong).unwrap();
+                if right_id_after {
+                    query.header.id = id;
+                    let right = answer(&query, Ipv4Addr::new(198, 51, 100, 1));
+                    transport::write_framed(&mut stream, &right).unwrap();
+                }
+            }
+        });
+
+        let response = resolver
+            .query(&parse_name("example.com"), QType::A)
+            .unwrap();
+        assert_eq!(
+            response.answers[0].rdata,
+            Rdata::A(Ipv4Addr::new(198, 51, 100, 1))
+        );
+        // With only the wrong ID before the upstream hangs up, there's no answer at all
+        assert!(matches!(
+            resolver.query(&parse_name("example.com"), QType::A),
+            Err(ResolveError::Io(_))
+        ));
+        upstream.join().unwrap();
+    }
+
+    #[test]
+    fn silent_upstreams_time_out() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = StubResolver::new(upstream.local_addr().unwrap())
+            .with_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            resolver.query(&parse_name("example.com"), QType::A),
+            Err(ResolveError::Timeout)
+        ));
+    }
+
+    // Serves every query sent to the returned address with `respond`, until the test exits
+    fn serve(respond: fn(&DNSMessage) -> DNSMessage) -> SocketAddr {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (size, client) = upstream.recv_from(&mut buf).unwrap();
+            let query = DNSMessage::from_bytes(&buf[..size]).unwrap();
+            upstream
+                .send_to(&respond(&query).to_bytes().unwrap(), client)
+                .unwrap();
+        });
+        addr
+    }
+
+    // Answers "<n>.example.com" with a CNAME to "<n+1>.example.com", and "3.example.com" with
+    // an A record
+    fn chain(query: &DNSMessage) -> DNSMessage {
+        let mut response = query.clone();
+        response.header = query.header.reply(false);
+        let question = &query.questions[0];
+        let n: u32 = std::str::from_utf8(&question.name[0])
+            .unwrap()
+            .parse()
+            .unwrap();
+        let (rtype, rdata) = if n == 3 {
+            (QType::A, Rdata::A(Ipv4Addr::new(192, 0, 2, 1)))
+        } else {
+            (
+                QType::Cname,
+                Rdata::Cname(parse_name(&format!("{}.example.com", n + 1))),
+            )
+        };
+        response.answers.push(ResourceRecord {
+            name: question.name.clone(),
+            rtype,
+            class: QClass::In,
+            ttl: 300,
+            rdata,
+        });
+        response
+    }
+
+    #[test]
+    fn resolve_follows_chains_up_to_the_limit() {
+        let resolver = StubResolver::new(serve(chain)).with_max_cname_chain(3);
+        let response = resolver
+            .resolve(&parse_name("0.example.com"), QType::A)
+            .unwrap();
+        let types: Vec<QType> = response.answers.iter().map(|r| r.rtype).collect();
+        assert_eq!(types, [QType::Cname, QType::Cname, QType::Cname, QType::A]);
+    }
+
+    #[test]
+    fn resolve_gives_up_on_chains_over_the_limit() {
+        let resolver = StubResolver::new(serve(chain)).with_max_cname_chain(2);
+        let result = resolver.resolve(&parse_name("0.example.com"), QType::A);
+        assert!(matches!(result, Err(ResolveError::CnameChainTooLong(2))));
+    }
+
+    #[test]
+    fn axfr_yields_records_across_messages_until_the_closing_soa() {
+        let zone = load_zone(
+            "$TTL 300\n@ IN SOA ns1 hostmaster 1 7200 900 1209600 300\n@ IN NS ns1\nns1 IN A 192.0.2.53\nwww IN A 192.0.2.1\n",
+            &parse_name("example.com"),
+        )
+        .unwrap();
+        let [soa, ns, ns1, www] = zone.records.clone().try_into().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let resolver = StubResolver::new(listener.local_addr().unwrap());
+
+        let messages = [
+            vec![soa.clone(), ns.clone()],
+            vec![ns1.clone(), www.clone()],
+            // Anything after the closing SOA isn't part of the transfer
+            vec![soa.clone(), www.clone()],
+        ];
+        let upstream = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = tcp::read_message(&mut stream).unwrap().unwrap();
+            let query = DNSMessage::from_bytes(&query).unwrap();
+            assert_eq!(query.questions[0].qtype, QType::Axfr);
+            for answers in messages {
+                let mut response = query.clone();
+                response.header = query.header.reply(true);
+                response.answers = answers;
+                let bytes = response.to_bytes().unwrap();
+                stream
+                    .write_all(&(bytes.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&bytes).unwrap();
+            }
+        });
+
+        let records: Vec<ResourceRecord> = resolver
+            .axfr("example.com")
+            .collect::<Result<_, _>>()
+            .unwrap();
+        upstream.join().unwrap();
+        assert_eq!(records, [soa.clone(), ns, ns1, www, soa]);
+    }
+
+    // Answers every query with the same bytes, keeping the timeouts it was given
+    struct Canned {
+        response: Vec<u8>,
+        timeouts: Arc<Mutex<Vec<Duration>>>,
+    }
+
+    impl Transport for Canned {
+        fn exchange(&self, _query: &[u8], timeout: Duration) -> Result<Vec<u8>, TransportError> {
+            self.timeouts.lock().unwrap().push(timeout);
+            Ok(self.response.clone())
+        }
+    }
+
+    #[test]
+    fn a_mock_transport_drives_a_query() {
+        let mut query = query_message(&parse_name("example.com"), QType::A);
+        query.header.id = 7;
+        let response = answer(&query, Ipv4Addr::new(198, 51, 100, 1));
+        let timeouts = Arc::new(Mutex::new(Vec::new()));
+        let resolver = |timeout: Option<Duration>| {
+            let resolver = StubResolver::new("192.0.2.53:53".parse().unwrap())
+                .with_transport(Canned {
+                    response: response.clone(),
+                    timeouts: timeouts.clone(),
+                })
+                .with_id_generator(SequentialIds::new(7));
+            match timeout {
+                Some(timeout) => resolver.with_timeout(timeout),
+                None => resolver,
+            }
+        };
+
+        let answered = resolver(None)
+            .query(&parse_name("example.com"), QType::A)
+            .unwrap();
+        let expected = DNSMessage::from_bytes(&response).unwrap();
+        assert_eq!(answered.answers, expected.answers);
+        resolver(Some(Duration::from_millis(250)))
+            .query(&parse_name("example.com"), QType::A)
+            .unwrap();
+        assert_eq!(
+            *timeouts.lock().unwrap(),
+            [DEFAULT_TIMEOUT, Duration::from_millis(250)]
+        );
+
+        // The canned response only matches the first ID handed out
+        let resolver = resolver(None);
+        resolver
+            .query(&parse_name("example.com"), QType::A)
+            .unwrap();
+        let result = resolver.query(&parse_name("example.com"), QType::A);
+        assert!(matches!(result, Err(ResolveError::UnexpectedResponse(7))));
+    }
+}
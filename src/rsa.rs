@@ -0,0 +1,195 @@
+// Verification of RSA signatures as DNSSEC uses them: PKCS #1 v1.5 over a SHA-256 digest, with
+// the public key in the format DNSKEY records carry it in
+// https://www.rfc-editor.org/rfc/rfc5702#section-3
+// https://www.rfc-editor.org/rfc/rfc8017#section-8.2.2
+use crate::sha256;
+
+// The DER encoded DigestInfo naming SHA-256, which the digest follows in a signature
+// https://www.rfc-editor.org/rfc/rfc8017#section-9.2
+const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+// RSASHA256 keys must be from 512 to 4096 bits
+// https://www.rfc-editor.org/rfc/rfc5702#section-2.1
+const MIN_MODULUS_BYTES: usize = 64;
+const MAX_MODULUS_BYTES: usize = 512;
+
+// Public exponents are small in practice, and a huge one would have us spend a long time on
+// every signature made with the key, so longer ones aren't accepted
+const MAX_EXPONENT_BYTES: usize = 4;
+
+// Whether `signature` is a signature of `message` by `public_key`, given as in a DNSKEY record:
+// the exponent's length in one byte, or in two after a zero byte, the exponent, then the modulus
+// https://www.rfc-editor.org/rfc/rfc3110#section-2
+pub(crate) fn verify_sha256(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let (exponent_len, rest) = match public_key {
+        [0, high, low, rest @ ..] => (u16::from_be_bytes([*high, *low]) as usize, rest),
+        [len, rest @ ..] => (*len as usize, rest),
+        [] => return false,
+    };
+    if exponent_len == 0 || exponent_len > rest.len() {
+        return false;
+    }
+    let (exponent, modulus) = rest.split_at(exponent_len);
+    let exponent = strip_zeros(exponent);
+    let modulus = strip_zeros(modulus);
+    if exponent.len() > MAX_EXPONENT_BYTES
+        || !(MIN_MODULUS_BYTES..=MAX_MODULUS_BYTES).contains(&modulus.len())
+        || signature.len() != modulus.len()
+    {
+        return false;
+    }
+
+    // Enough 32 bit limbs for the modulus, the last perhaps partly used
+    let limbs = modulus.chunks(4).len();
+    let n = to_limbs(modulus, limbs);
+    let s = to_limbs(signature, limbs);
+    if !less(&s, &n) {
+        return false;
+    }
+    let encoded = from_limbs(&pow_mod(&s, exponent, &n), modulus.len());
+
+    // 00 01, at least 8 bytes of FF, 00, then the DigestInfo and digest
+    let Some(padding) = modulus.len().checked_sub(3 + SHA256_DIGEST_INFO.len() + 32) else {
+        return false;
+    };
+    let mut expected = vec![0x00, 0x01];
+    expected.resize(2 + padding, 0xff);
+    expected.push(0x00);
+    expected.extend_from_slice(&SHA256_DIGEST_INFO);
+    expected.extend_from_slice(&sha256::digest(message));
+    encoded == expected
+}
+
+fn strip_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+// Numbers are held as `len` 32 bit limbs, least significant first, all the length of the modulus
+fn to_limbs(bytes: &[u8], len: usize) -> Vec<u32> {
+    let mut limbs = vec![0; len];
+    for (i, &b) in bytes.iter().rev().enumerate() {
+        limbs[i / 4] |= (b as u32) << (8 * (i % 4));
+    }
+    limbs
+}
+
+// The lowest `len` bytes of `limbs`, most significant first
+fn from_limbs(limbs: &[u32], len: usize) -> Vec<u8> {
+    (0..len)
+        .rev()
+        .map(|i| (limbs[i / 4] >> (8 * (i % 4))) as u8)
+        .collect()
+}
+
+fn less(a: &[u32], b: &[u32]) -> bool {
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x != y {
+            return x < y;
+        }
+    }
+    false
+}
+
+// a += b, returning whether it carried out of the top limb
+fn add(a: &mut [u32], b: &[u32]) -> bool {
+    let mut carry = 0;
+    for (x, &y) in a.iter_mut().zip(b) {
+        let sum = *x as u64 + y as u64 + carry;
+        *x = sum as u32;
+        carry = sum >> 32;
+    }
+    carry != 0
+}
+
+// a -= b, wrapping round if b is larger
+fn subtract(a: &mut [u32], b: &[u32]) {
+    let mut borrow = 0;
+    for (x, &y) in a.iter_mut().zip(b) {
+        let difference = (*x as u64).wrapping_sub(y as u64 + borrow);
+        *x = difference as u32;
+        borrow = difference >> 63;
+    }
+}
+
+// a *= 2, returning whether a bit was shifted out of the top limb
+fn double(a: &mut [u32]) -> bool {
+    let mut carry = 0;
+    for x in a.iter_mut() {
+        let top = *x >> 31;
+        *x = (*x << 1) | carry;
+        carry = top;
+    }
+    carry != 0
+}
+
+// a * b mod n, for a and b below n, adding in a for each bit of b from the top while doubling.
+// The running total stays below 2n, so one subtraction of n after each step keeps it below n;
+// if a step carried out of the top limb the total was certainly past n, and the subtraction
+// wrapping round gives the right answer.
+fn multiply_mod(a: &[u32], b: &[u32], n: &[u32]) -> Vec<u32> {
+    let mut total = vec![0; n.len()];
+    for bit in (0..n.len() * 32).rev() {
+        if double(&mut total) || !less(&total, n) {
+            subtract(&mut total, n);
+        }
+        if (b[bit / 32] >> (bit % 32)) & 1 == 1 && (add(&mut total, a) || !less(&total, n)) {
+            subtract(&mut total, n);
+        }
+    }
+    total
+}
+
+// base ^ exponent mod n, squaring for each bit of the exponent from the top
+fn pow_mod(base: &[u32], exponent: &[u8], n: &[u32]) -> Vec<u32> {
+    let mut result = vec![0; n.len()];
+    result[0] = 1;
+    for byte in exponent {
+        for shift in (0..8).rev() {
+            result = multiply_mod(&result, &result, n);
+            if (byte >> shift) & 1 == 1 {
+                result = multiply_mod(&result, base, n);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base64;
+
+    // A 1024 bit key with exponent 65537, as it appears in a DNSKEY record, and its signature
+    // over a short message
+    const KEY: &str = "\
+        AwEAAdlVvzVvFCbneiySdkpyC+sCinu/nFk8KWEBn+lBULAjKw3888sWWqZnOAyI\
+        qAfENilQM43jGAQu9ya0jKhAWD1/4yQwk+QIma6LN3UWgE2xlNFIqty5bPzXFMqD\
+        4IK2XeP5H0dkK7wQiMIUoqr675AIqZQXmNyWZrtPuBFRS/A3";
+    const SIGNATURE: &str = "\
+        gJjVBioTQzFutXMoqjWL6luuZjTUOw+GJvP8YFW0I0OXmtPg3tsUz2vd6/oSNhqk\
+        r0MGNfFmzf4SX0+a0hbOK58at7w/7E6z5iMrh8UxoeRdf/bg5VJQTVDRNPQDqYLv\
+        N+NrHEcWYm8ANP36SHF+THPx+NDNfw6wqZQjTI1a2NY=";
+
+    #[test]
+    fn verifies_signatures_made_with_the_key() {
+        let key = base64::decode(KEY).unwrap();
+        let signature = base64::decode(SIGNATURE).unwrap();
+        let message = b"The quick brown fox jumps over the lazy dog";
+        assert!(verify_sha256(&key, message, &signature));
+
+        assert!(!verify_sha256(
+            &key,
+            b"The quick brown fox jumps over the lazy cat",
+            &signature
+        ));
+        let mut tampered = signature.clone();
+        tampered[10] ^= 1;
+        assert!(!verify_sha256(&key, message, &tampered));
+        assert!(!verify_sha256(&key, message, &signature[1..]));
+        assert!(!verify_sha256(&key[..40], message, &signature));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::message::DNSMessage;
+use crate::zone_file::decode_hex;
+
+// A message known to be well formed, as hex. It must parse and serialize back to exactly the
+// same bytes, compression pointers included.
+pub struct Vector {
+    pub name: &'static str,
+    pub hex: &'static str,
+}
+
+// Messages covering the core parsing and serializing paths, checked by --selftest so a build can
+// be sanity checked where it's deployed without needing a network
+pub const VECTORS: &[Vector] = &[
+    // codecrafters.io A, with RD set
+    Vector {
+        name: "plain query",
+        hex: "1234010000010000000000000c636f6465637261667465727302696f0000010001",
+    },
+    // The same with an OPT record advertising a 1232 byte payload
+    Vector {
+        name: "EDNS query",
+        hex: "1234010000010000000000010c636f6465637261667465727302696f0000010001\
+              00002904d0000000000000",
+    },
+    // An answer whose owner name points back at the question
+    Vector {
+        name: "compressed response",
+        hex: "1234818000010001000000000c636f6465637261667465727302696f0000010001\
+              c00c000100010000003c00044c4c1515",
+    },
+    // nope.codecrafters.io doesn't exist, with the zone's SOA in the authority section
+    Vector {
+        name: "NXDOMAIN response",
+        hex: "123481830001000000010000046e6f70650c636f6465637261667465727302696f\
+              0000010001c011000600010000012c0026026e73c0110a686f73746d6173746572\
+              c0110000000100001c2000000384001275000000012c",
+    },
+];
+
+/// Checks that `vector` parses as a whole message and serializes back to the same bytes.
+pub fn check(vector: &Vector) -> Result<(), String> {
+    let bytes = decode_hex(vector.hex).ok_or("not valid hex")?;
+    let message = DNSMessage::from_bytes_exact(&bytes).map_err(|e| e.to_string())?;
+    let serialized = message.to_bytes().map_err(|e| e.to_string())?;
+    if serialized != bytes {
+        return Err(format!(
+            "serialized to {} bytes that differ from the original {}",
+            serialized.len(),
+            bytes.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_must_round_trip_exactly() {
+        for vector in VECTORS {
+            assert_eq!(check(vector), Ok(()), "{}", vector.name);
+        }
+        let not_hex = Vector {
+            name: "not hex",
+            hex: "12340",
+        };
+        assert!(check(&not_hex).is_err());
+        // The compressed response written out without compression
+        let uncompressed = Vector {
+            name: "uncompressed",
+            hex: "1234818000010001000000000c636f6465637261667465727302696f0000010001\
+                  0c636f6465637261667465727302696f00000100010000003c00044c4c1515",
+        };
+        assert!(check(&uncompressed).is_err());
+    }
+}
@@ -0,0 +1,1149 @@
+use crate::chaos;
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::edns;
+use crate::error::ParseError;
+use crate::forwarding::ForwardingRules;
+use crate::header::{Header, OpCode, ResponseCode};
+use crate::hexdump::hexdump;
+use crate::message::{DNSMessage, MessageBuilder};
+use crate::metrics::Metrics;
+use crate::name::{same_name, DisplayName};
+use crate::record::{QClass, QType, ResourceRecord};
+use crate::resolver::StubResolver;
+use crate::zone::{Zone, ZoneAnswer};
+
+// Answers queries: from built in data or the zones we are authoritative for where we can,
+// otherwise by forwarding to the upstream resolver configured for the name.
+pub struct Server {
+    config: Config,
+    forwarding: ForwardingRules,
+    cache: Cache,
+    zones: Vec<Zone>,
+    metrics: Metrics,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Self {
+        let mut forwarding = ForwardingRules::default();
+        if let Some(upstream) = config.resolver {
+            let resolver = StubResolver::new(upstream).with_max_cname_chain(config.max_cname_chain);
+            forwarding.set_default(resolver);
+        }
+        for zone in &config.forward_zones {
+            let resolver =
+                StubResolver::new(zone.upstream).with_max_cname_chain(config.max_cname_chain);
+            forwarding.add_rule(zone.suffix.clone(), resolver);
+        }
+        let cache = Cache::default()
+            .with_min_ttl(config.cache_min_ttl)
+            .with_max_ttl(config.cache_max_ttl)
+            .with_negative_max_ttl(config.cache_negative_max_ttl)
+            .with_max_entries(config.cache_max_entries);
+        Server {
+            config,
+            forwarding,
+            cache,
+            zones: Vec::new(),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Adds a zone to answer authoritatively from.
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.zones.push(zone);
+        self
+    }
+
+    /// Replaces the default resolver built from the config, e.g. with one using fixed query IDs.
+    /// It follows CNAME chains only as far as the config allows.
+    pub fn with_resolver(mut self, resolver: StubResolver) -> Self {
+        let resolver = resolver.with_max_cname_chain(self.config.max_cname_chain);
+        self.forwarding.set_default(resolver);
+        self
+    }
+
+    /// Forwards queries for names at or below `suffix` to `resolver` rather than the default.
+    /// It follows CNAME chains only as far as the config allows.
+    pub fn with_forwarding_rule(mut self, suffix: Vec<Vec<u8>>, resolver: StubResolver) -> Self {
+        let resolver = resolver.with_max_cname_chain(self.config.max_cname_chain);
+        self.forwarding.add_rule(suffix, resolver);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Responses to forwarded queries, kept until they expire.
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    /// Counters for the queries handled so far.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Handles a single query, returning the bytes of the reply to send, if any.
+    pub fn handle(&self, query: &[u8]) -> Option<Vec<u8>> {
+        let (response, delay) = self.handle_from(query, false)?;
+        sleep(delay);
+        Some(response)
+    }
+
+    /// Resolves a query in-process, for programs embedding the server rather than talking to it
+    /// over a socket. The query goes through the same zones, cache and forwarding as one off the
+    /// network, and the reply is the bytes a UDP client would get, or empty if the query would
+    /// have been dropped without one.
+    pub fn query(&self, message: &[u8]) -> Vec<u8> {
+        self.handle(message).unwrap_or_default()
+    }
+
+    /// Like `handle`, for a query that arrived over TCP, whose reply may carry options that only
+    /// make sense on a connection, such as keepalive.
+    pub fn handle_tcp(&self, query: &[u8]) -> Option<Vec<u8>> {
+        let (response, delay) = self.handle_from(query, true)?;
+        sleep(delay);
+        Some(response)
+    }
+
+    fn handle_from(&self, query: &[u8], over_tcp: bool) -> Option<(Vec<u8>, Option<Duration>)> {
+        if self.config.hexdump {
+            eprint!("Received {} bytes:\n{}", query.len(), hexdump(query));
+        }
+        let (response, delay) = self.respond(query, over_tcp)?;
+        if self.config.hexdump {
+            eprint!("Sending {} bytes:\n{}", response.len(), hexdump(&response));
+        }
+        Some((response, delay))
+    }
+
+    // The reply to a query, if there is one to send, and how long to hold it back for. The delay
+    // is left to the caller so that it needn't block whatever called us.
+    fn respond(&self, query: &[u8], over_tcp: bool) -> Option<(Vec<u8>, Option<Duration>)> {
+        // A message with QR set is a response, not a query. Replying to it, even with FORMERR,
+        // could set up a loop between two servers reflecting each other's replies, so drop it
+        // before anything else looks at it.
+        if query.get(2).is_some_and(|flags| flags & 0b1000_0000 != 0) {
+            eprintln!("Dropping message with QR set");
+            return None;
+        }
+
+        if let Some(canned) = &self.config.static_response {
+            // Anything too short for a header isn't a query to answer, even in this mode
+            if query.len() < 12 {
+                return None;
+            }
+            let mut response = canned.clone();
+            response[..2].copy_from_slice(&query[..2]);
+            return Some((response, None));
+        }
+
+        let message = match DNSMessage::from_bytes(query) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("Failed to parse query: {}", e);
+                return form_error(query, e).map(|response| (response, None));
+            }
+        };
+        eprintln!("Received header:{:?}", message.header);
+        for question in &message.questions {
+            self.metrics.record_query(question.qtype);
+        }
+        let _in_flight = self.metrics.begin_query();
+
+        // CHAOS answers describe this server itself, so they are authoritative
+        let answers: Vec<_> = message
+            .questions
+            .iter()
+            .filter_map(|question| chaos::answer(&self.config, question))
+            .collect();
+
+        let mut response = if message.header.header_flags.op_code != OpCode::Query {
+            // Only standard queries are supported; anything else, such as a NOTIFY or UPDATE, is
+            // declined so the sender knows not to wait for it to take effect
+            eprintln!(
+                "Declining query {} with opcode {}",
+                message.header.id, message.header.header_flags.op_code
+            );
+            let mut header = message.header.reply(false);
+            header.header_flags.response_code = ResponseCode::NotImp;
+            DNSMessage {
+                header,
+                questions: message.questions.clone(),
+                answers: Vec::new(),
+                authority: Vec::new(),
+                additional: Vec::new(),
+            }
+        } else if self.should_shed(&message) {
+            eprintln!("Shedding query {} under load", message.header.id);
+            self.metrics.record_shed_query();
+            let mut header = message.header.reply(false);
+            header.header_flags.response_code = ResponseCode::ServFail;
+            DNSMessage {
+                header,
+                questions: message.questions.clone(),
+                answers: Vec::new(),
+                authority: Vec::new(),
+                additional: Vec::new(),
+            }
+        } else if !answers.is_empty() {
+            DNSMessage {
+                header: message.header.reply(true),
+                questions: message.questions.clone(),
+                answers,
+                authority: Vec::new(),
+                additional: Vec::new(),
+            }
+        } else if let Some(answer) = self.answer_from_zone(&message) {
+            // Names in our zones are always answered from them, however much the client wants
+            // recursion, so local data can't be overridden by whatever upstream says
+            let mut header = message.header.reply(answer.authoritative);
+            header.header_flags.response_code = answer.response_code;
+            DNSMessage {
+                header,
+                questions: message.questions.clone(),
+                answers: answer.answers,
+                authority: answer.authority,
+                additional: answer.additional,
+            }
+        } else if !self.forwarding.is_empty() && !self.may_forward(&message) {
+            // A client clearing RD wants an iterative answer, and with no zone of ours holding
+            // the name the closest thing we have is to decline, as an authoritative-only server
+            // would, rather than recursing on its behalf
+            eprintln!("Refusing query {} with RD clear", message.header.id);
+            let mut header = message.header.reply(false);
+            header.header_flags.response_code = ResponseCode::Refused;
+            DNSMessage {
+                header,
+                questions: message.questions.clone(),
+                answers: Vec::new(),
+                authority: Vec::new(),
+                additional: Vec::new(),
+            }
+        } else if !self.forwarding.is_empty() {
+            forward(&self.config, &self.forwarding, &self.cache, &message)
+        } else {
+            // The name isn't in any of our zones, so nothing we could say about it would be
+            // authoritative
+            DNSMessage {
+                header: message.header.reply(false),
+                questions: message.questions.clone(),
+                answers: Vec::new(),
+                authority: Vec::new(),
+                additional: Vec::new(),
+            }
+        };
+
+        // RA says whether we offer recursion at all, so is the same whether or not this
+        // particular answer came from upstream
+        response.header.header_flags.recursion_available = !self.forwarding.is_empty();
+        response.order_answers();
+        if self.config.sort_by_preference {
+            response.sort_by_preference();
+        }
+        if message.edns().is_some() && response.edns().is_none() {
+            response.additional.push(opt_record(&self.config, &message));
+        }
+        // Clients asking over TCP how long they may keep the connection open are told our idle
+        // timeout. Over UDP the option means nothing, so is never sent.
+        // https://www.rfc-editor.org/rfc/rfc7828#section-3.3.2
+        if over_tcp && edns::has_option(&message, edns::TCP_KEEPALIVE) {
+            let keepalive = edns::keepalive_option(self.config.tcp_idle_timeout);
+            edns::add_option(&mut response, keepalive);
+        }
+        eprintln!("Response header:{:?}", response.header);
+        let delay = self.debug_delay(&message);
+        if let Some(delay) = delay {
+            eprintln!("Delaying response by {:?}", delay);
+        }
+        let bytes = if self.config.disable_compression {
+            response.to_bytes_uncompressed()
+        } else {
+            response.to_bytes()
+        };
+        match bytes {
+            Ok(bytes) => Some((bytes, delay)),
+            Err(e) => {
+                // Zone data can hold names that can't go on the wire, which is no fault of the
+                // client's
+                eprintln!(
+                    "Failed to serialize response to {}: {}",
+                    message.header.id, e
+                );
+                let mut header = message.header.reply(false);
+                header.header_flags.response_code = ResponseCode::ServFail;
+                let reply = DNSMessage {
+                    header,
+                    questions: message.questions.clone(),
+                    answers: Vec::new(),
+                    authority: Vec::new(),
+                    additional: Vec::new(),
+                };
+                reply.to_bytes().ok().map(|bytes| (bytes, delay))
+            }
+        }
+    }
+
+    // Whether to refuse a query to shed load. Under load we keep answering CHAOS queries, which
+    // monitoring relies on, and SOA queries for our zones' apexes, which secondaries use to check
+    // for changes; both are answered from memory without any upstream work.
+    fn should_shed(&self, message: &DNSMessage) -> bool {
+        let Some(threshold) = self.config.load_shed_threshold else {
+            return false;
+        };
+        if self.metrics.in_flight() <= threshold {
+            return false;
+        }
+        let priority = message.questions.iter().all(|question| {
+            question.qclass == QClass::Ch
+                || (question.qtype == QType::Soa
+                    && self
+                        .zones
+                        .iter()
+                        .any(|zone| same_name(&zone.origin, &question.name)))
+        });
+        !priority
+    }
+
+    // Whether a query may be forwarded upstream, i.e. the client asked for recursion
+    fn may_forward(&self, message: &DNSMessage) -> bool {
+        message.header.header_flags.recursion_desired || self.config.forward_without_rd
+    }
+
+    // The debug delay for a query: one configured for its name if there is one, otherwise one
+    // configured for every query
+    fn debug_delay(&self, message: &DNSMessage) -> Option<Duration> {
+        let question_name = message.question_name();
+        let for_name = self.config.debug_delays.iter().find(|delay| {
+            delay.name.as_ref().is_some_and(|name| {
+                question_name.is_some_and(|question_name| same_name(question_name, name))
+            })
+        });
+        for_name
+            .or_else(|| {
+                self.config
+                    .debug_delays
+                    .iter()
+                    .find(|delay| delay.name.is_none())
+            })
+            .map(|delay| delay.delay)
+    }
+
+    // Answers from the most specific of our zones containing the question's name. Only single
+    // question queries are answered this way, as a multi-question query could span zones.
+    fn answer_from_zone(&self, message: &DNSMessage) -> Option<ZoneAnswer> {
+        let [question] = message.questions.as_slice() else {
+            return None;
+        };
+        let zone = self
+            .zones
+            .iter()
+            .filter(|zone| zone.contains(&question.name))
+            .max_by_key(|zone| zone.origin.len())?;
+        if question.qtype == QType::Any {
+            return Some(zone.answer_any(
+                &question.name,
+                self.config.any_response,
+                self.config.max_cname_chain,
+            ));
+        }
+        Some(zone.answer(&question.name, question.qtype, self.config.max_cname_chain))
+    }
+}
+
+fn sleep(delay: Option<Duration>) {
+    if let Some(delay) = delay {
+        thread::sleep(delay);
+    }
+}
+
+// Forwards the query upstream, unless the answer is already cached. Upstream servers generally
+// only answer single question queries, so each question is sent separately and the answers
+// merged. Forwarded answers are never authoritative, whatever the upstream said.
+fn forward(
+    config: &Config,
+    forwarding: &ForwardingRules,
+    cache: &Cache,
+    query: &DNSMessage,
+) -> DNSMessage {
+    let mut response = DNSMessage {
+        header: query.header.reply(false),
+        questions: query.questions.clone(),
+        answers: Vec::new(),
+        authority: Vec::new(),
+        additional: Vec::new(),
+    };
+    let mut reserved_flags = 0;
+
+    for question in &query.questions {
+        let upstream = match cache.get(question, query) {
+            Some(cached) => cached,
+            None => {
+                // With no default upstream, names outside every rule have nowhere to go. An
+                // empty answer would read as the name having no such records, so decline it
+                // instead.
+                let Some(resolver) = forwarding.resolver_for(&question.name) else {
+                    eprintln!("No upstream to forward {} to", DisplayName(&question.name));
+                    response.header.header_flags.response_code = ResponseCode::Refused;
+                    response.answers.clear();
+                    response.authority.clear();
+                    response.additional.clear();
+                    return response;
+                };
+                let mut single = query.clone();
+                single.questions = vec![question.clone()];
+                edns::strip_options(&mut single, &config.strip_edns_options);
+                // Keepalive is about the client's connection to us, not ours upstream
+                edns::strip_options(&mut single, &[edns::TCP_KEEPALIVE]);
+                match resolver.exchange(&single) {
+                    Ok(upstream) => {
+                        cache.insert(question, query, &upstream);
+                        upstream
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to forward query {}: {}", query.header.id, e);
+                        response.header.header_flags.response_code = ResponseCode::ServFail;
+                        response.answers.clear();
+                        response.authority.clear();
+                        response.additional.clear();
+                        return response;
+                    }
+                }
+            }
+        };
+
+        // The upstream's OPT record describes its own EDNS support, not ours, so only the
+        // reserved flags are carried over to ours in case they mean something to the client
+        if let Some(opt) = upstream.edns() {
+            reserved_flags |= opt.ttl & edns::RESERVED_FLAGS;
+        }
+        let flags = &upstream.header.header_flags;
+        if flags.response_code != ResponseCode::NoError {
+            response.header.header_flags.response_code = flags.response_code.clone();
+        }
+        response.header.header_flags.truncation |= flags.truncation;
+        response.answers.extend(upstream.answers);
+        response.authority.extend(upstream.authority);
+        response.additional.extend(
+            upstream
+                .additional
+                .into_iter()
+                .filter(|record| record.rtype != QType::Opt),
+        );
+    }
+
+    if query.edns().is_some() {
+        let mut opt = opt_record(config, query);
+        opt.ttl |= reserved_flags;
+        response.additional.push(opt);
+    }
+    response
+}
+
+// Our OPT record for the reply to `query`. DO is echoed back when the query set it, telling the
+// client we understood it wanted DNSSEC records.
+// https://www.rfc-editor.org/rfc/rfc3225#section-3
+fn opt_record(config: &Config, query: &DNSMessage) -> ResourceRecord {
+    let mut opt = edns::opt_record(config.udp_payload_size, Vec::new());
+    if query.dnssec_ok() {
+        opt.ttl |= edns::DNSSEC_OK;
+    }
+    opt
+}
+
+// Replies FORMERR to a query we couldn't parse. If even the header is unreadable there is no
+// ID to reply to, so the query is dropped.
+fn form_error(query: &[u8], error: ParseError) -> Option<Vec<u8>> {
+    let header = Header::new(query.get(..12)?).ok()?;
+    let mut reply = header.reply(false);
+    reply.header_flags.response_code = ResponseCode::FormError;
+    eprintln!("Replying FORMERR to {}: {}", header.id, error);
+    MessageBuilder::new(reply).build().to_bytes().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::config::DebugDelay;
+    use crate::edns::EdnsOption;
+    use crate::id::SequentialIds;
+    use crate::name::parse_name;
+    use crate::record::{QClass, Rdata, ResourceRecord};
+    use crate::zone_file::load_zone;
+
+    // A query for example.com A, with an OPT record for each of `opts`
+    fn query(opts: usize) -> Vec<u8> {
+        let mut bytes = vec![0xbe, 0xef, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, opts as u8];
+        bytes.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        for _ in 0..opts {
+            bytes.extend_from_slice(b"\x00\x00\x29\x04\xd0\x00\x00\x00\x00\x00\x00");
+        }
+        bytes
+    }
+
+    fn ask(server: &Server, query: &[u8]) -> DNSMessage {
+        DNSMessage::from_bytes(&server.handle(query).unwrap()).unwrap()
+    }
+
+    fn opts(message: &DNSMessage) -> usize {
+        let opts = message.additional.iter();
+        opts.filter(|record| record.rtype == QType::Opt).count()
+    }
+
+    #[test]
+    fn two_opt_records_get_formerr() {
+        let response = ask(&Server::new(Config::default()), &query(2));
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::FormError
+        );
+        assert_eq!(response.header.id, 0xbeef);
+        assert_eq!(opts(&response), 0);
+    }
+
+    #[test]
+    fn edns_queries_get_exactly_one_opt() {
+        let server = Server::new(Config::default());
+        let response = ask(&server, &query(1));
+        assert_eq!(opts(&response), 1);
+        assert_eq!(
+            u16::from(response.edns().unwrap().class),
+            edns::DEFAULT_UDP_PAYLOAD_SIZE
+        );
+        assert_eq!(opts(&ask(&server, &query(0))), 0);
+    }
+
+    #[test]
+    fn id_server_is_answered_authoritatively() {
+        let config = Config {
+            identity: "ns1.example.com".to_string(),
+            ..Config::default()
+        };
+        let server = Server::new(config);
+        let mut id_server = vec![0xbe, 0xef, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        id_server.extend_from_slice(b"\x02id\x06server\x00\x00\x10\x00\x03");
+        let response = ask(&server, &id_server);
+        assert!(response.header.header_flags.authoritative_answer);
+        assert_eq!(response.answers[0].rdata, Rdata::txt("ns1.example.com"));
+
+        let response = ask(&server, &query(0));
+        assert!(!response.header.header_flags.authoritative_answer);
+        assert!(response.answers.is_empty());
+    }
+
+    // Answers each question with a single A record, adding an OPT record of upstream's own
+    fn answer_a(query: &DNSMessage) -> DNSMessage {
+        let mut response = query.clone();
+        response.header = query.header.reply(true);
+        for question in &query.questions {
+            response.answers.push(ResourceRecord {
+                name: question.name.clone(),
+                rtype: QType::A,
+                class: QClass::In,
+                ttl: 300,
+                rdata: Rdata::A(Ipv4Addr::new(198, 51, 100, 1)),
+            });
+        }
+        response.additional = vec![edns::opt_record(4096, Vec::new())];
+        response
+    }
+
+    // Answers queries sent to the returned address with `respond`, until the test exits. The
+    // queries it was sent are passed back over the channel.
+    fn upstream_answering(
+        respond: fn(&DNSMessage) -> DNSMessage,
+    ) -> (SocketAddr, mpsc::Receiver<DNSMessage>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (queries, received) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (size, client) = socket.recv_from(&mut buf).unwrap();
+            let query = DNSMessage::from_bytes(&buf[..size]).unwrap();
+            let _ = queries.send(query.clone());
+            socket
+                .send_to(&respond(&query).to_bytes().unwrap(), client)
+                .unwrap();
+        });
+        (addr, received)
+    }
+
+    fn upstream() -> (SocketAddr, mpsc::Receiver<DNSMessage>) {
+        upstream_answering(answer_a)
+    }
+
+    #[test]
+    fn unsupported_opcodes_get_notimp() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        let mut notify = query_for("example.com", QType::Soa);
+        // NOTIFY, with RD still set
+        notify[2] = (4 << 3) | 0x01;
+        let response = ask(&server, &notify);
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::NotImp
+        );
+        assert_eq!(response.header.header_flags.op_code, OpCode::Unknown(4));
+        assert!(response.answers.is_empty());
+        assert!(queries.try_recv().is_err());
+    }
+
+    #[test]
+    fn forwarded_answers_are_not_authoritative() {
+        let (addr, queries) = upstream();
+        let resolver = StubResolver::new(addr).with_id_generator(SequentialIds::new(100));
+        let server = Server::new(Config::default()).with_resolver(resolver);
+
+        let response = ask(&server, &query(1));
+        assert_eq!(response.header.id, 0xbeef);
+        assert!(!response.header.header_flags.authoritative_answer);
+        assert!(response.header.header_flags.recursion_available);
+        assert_eq!(
+            response.answers[0].rdata,
+            Rdata::A(Ipv4Addr::new(198, 51, 100, 1))
+        );
+        // Upstream's OPT record is replaced with ours
+        assert_eq!(opts(&response), 1);
+
+        ask(&server, &query_for("example.net", QType::A));
+        let ids: Vec<u16> = queries.try_iter().map(|query| query.header.id).collect();
+        assert_eq!(ids, [100, 101]);
+    }
+
+    #[test]
+    fn upstream_failures_become_servfail() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver =
+            StubResolver::new(silent.local_addr().unwrap()).with_timeout(Duration::from_millis(50));
+        let server = Server::new(Config::default()).with_resolver(resolver);
+        let response = ask(&server, &query(0));
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::ServFail
+        );
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn messages_with_qr_set_are_dropped() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        let mut response = query(0);
+        response[2] |= 0b1000_0000;
+        assert_eq!(server.handle(&response), None);
+        assert!(queries.try_recv().is_err());
+
+        // Even one too broken to parse, which would otherwise get FORMERR
+        response.truncate(14);
+        assert_eq!(server.handle(&response), None);
+    }
+
+    // The codes of the options in the message's OPT record
+    fn option_codes(message: &DNSMessage) -> Vec<u16> {
+        match &message.edns().unwrap().rdata {
+            Rdata::Opt(options) => options.iter().map(|option| option.code).collect(),
+            rdata => panic!("not OPT: {:?}", rdata),
+        }
+    }
+
+    // A query with an OPT record holding client subnet and cookie options
+    fn query_with_options() -> Vec<u8> {
+        let mut message = DNSMessage::from_bytes(&query(0)).unwrap();
+        let options = vec![
+            EdnsOption {
+                code: edns::CLIENT_SUBNET,
+                data: vec![0, 1, 24, 0, 192, 0, 2],
+            },
+            EdnsOption {
+                code: edns::COOKIE,
+                data: vec![1; 8],
+            },
+        ];
+        message.additional.push(edns::opt_record(4096, options));
+        message.to_bytes().unwrap()
+    }
+
+    #[test]
+    fn configured_options_are_stripped_before_forwarding() {
+        let config = Config {
+            strip_edns_options: vec![edns::CLIENT_SUBNET],
+            ..Config::default()
+        };
+        let (addr, queries) = upstream();
+        let server = Server::new(config).with_resolver(StubResolver::new(addr));
+        ask(&server, &query_with_options());
+        assert_eq!(option_codes(&queries.try_recv().unwrap()), [edns::COOKIE]);
+    }
+
+    #[test]
+    fn options_are_forwarded_unless_configured() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        ask(&server, &query_with_options());
+        assert_eq!(
+            option_codes(&queries.try_recv().unwrap()),
+            [edns::CLIENT_SUBNET, edns::COOKIE]
+        );
+    }
+
+    // A query for `name` of type `qtype`
+    fn query_for(name: &str, qtype: QType) -> Vec<u8> {
+        let mut bytes = vec![0xbe, 0xef, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.push(0);
+        bytes.extend_from_slice(&u16::from(qtype).to_be_bytes());
+        bytes.extend_from_slice(&[0, 1]);
+        bytes
+    }
+
+    const ZONE: &str = "\
+$TTL 300
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.1
+";
+
+    fn zone() -> Zone {
+        load_zone(ZONE, &parse_name("example.com")).unwrap()
+    }
+
+    #[test]
+    fn only_zone_answers_are_authoritative() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default())
+            .with_zone(zone())
+            .with_resolver(StubResolver::new(addr));
+
+        let from_zone = ask(&server, &query_for("www.example.com", QType::A));
+        assert!(from_zone.header.header_flags.authoritative_answer);
+        assert_eq!(
+            from_zone.answers[0].rdata,
+            Rdata::A(Ipv4Addr::new(192, 0, 2, 1))
+        );
+        let missing = ask(&server, &query_for("missing.example.com", QType::A));
+        assert!(missing.header.header_flags.authoritative_answer);
+        assert_eq!(
+            missing.header.header_flags.response_code,
+            ResponseCode::NxDomain
+        );
+        assert!(queries.try_recv().is_err());
+
+        let forwarded = ask(&server, &query_for("example.net", QType::A));
+        assert!(!forwarded.header.header_flags.authoritative_answer);
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn delays_for_a_name_take_precedence() {
+        let delay = |name: Option<&str>, ms| DebugDelay {
+            name: name.map(parse_name),
+            delay: Duration::from_millis(ms),
+        };
+        let config = Config {
+            debug_delays: vec![delay(None, 50), delay(Some("www.example.com"), 200)],
+            ..Config::default()
+        };
+        let server = Server::new(config).with_zone(zone());
+        let www = query_for("WWW.example.com", QType::A);
+        let message = DNSMessage::from_bytes(&www).unwrap();
+        assert_eq!(
+            server.debug_delay(&message),
+            Some(Duration::from_millis(200))
+        );
+        let message = DNSMessage::from_bytes(&query_for("ns1.example.com", QType::A)).unwrap();
+        assert_eq!(
+            server.debug_delay(&message),
+            Some(Duration::from_millis(50))
+        );
+
+        let started = Instant::now();
+        server.handle(&www).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn nothing_is_delayed_by_default() {
+        let server = Server::new(Config::default());
+        let message = DNSMessage::from_bytes(&query(0)).unwrap();
+        assert_eq!(server.debug_delay(&message), None);
+    }
+
+    #[test]
+    fn queries_are_counted_by_type() {
+        let server = Server::new(Config::default()).with_zone(zone());
+        for _ in 0..3 {
+            ask(&server, &query_for("www.example.com", QType::A));
+        }
+        for _ in 0..2 {
+            ask(&server, &query_for("www.example.com", QType::Aaaa));
+        }
+        let counts = server.metrics().queries_by_type();
+        assert_eq!(counts.get(&QType::A), Some(&3));
+        assert_eq!(counts.get(&QType::Aaaa), Some(&2));
+        assert_eq!(counts.get(&QType::Mx), None);
+    }
+
+    #[test]
+    fn queries_go_to_the_longest_matching_rule() {
+        let (default, default_queries) = upstream();
+        let (corp, corp_queries) = upstream();
+        let (dev, dev_queries) = upstream();
+        let server = Server::new(Config::default())
+            .with_resolver(StubResolver::new(default))
+            .with_forwarding_rule(parse_name("corp.internal"), StubResolver::new(corp))
+            .with_forwarding_rule(parse_name("dev.corp.internal"), StubResolver::new(dev));
+
+        ask(&server, &query_for("wiki.corp.internal", QType::A));
+        ask(&server, &query_for("build.dev.corp.internal", QType::A));
+        ask(&server, &query_for("example.net", QType::A));
+
+        let names = |queries: &mpsc::Receiver<DNSMessage>| -> Vec<Vec<Vec<u8>>> {
+            let queries = queries.try_iter();
+            queries.map(|q| q.questions[0].name.clone()).collect()
+        };
+        assert_eq!(names(&corp_queries), [parse_name("wiki.corp.internal")]);
+        assert_eq!(names(&dev_queries), [parse_name("build.dev.corp.internal")]);
+        assert_eq!(names(&default_queries), [parse_name("example.net")]);
+    }
+
+    #[test]
+    fn names_matching_no_rule_are_refused_without_a_default() {
+        let (corp, corp_queries) = upstream();
+        let server = Server::new(Config::default())
+            .with_forwarding_rule(parse_name("corp.internal"), StubResolver::new(corp));
+        let response = ask(&server, &query_for("example.net", QType::A));
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::Refused
+        );
+        assert!(response.answers.is_empty());
+        assert!(corp_queries.try_recv().is_err());
+    }
+
+    // Answers like `answer_a`, but with a TTL of 0
+    fn answer_a_uncacheable(query: &DNSMessage) -> DNSMessage {
+        let mut response = answer_a(query);
+        response.answers[0].ttl = 0;
+        response
+    }
+
+    #[test]
+    fn forwarded_answers_are_cached() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        let first = ask(&server, &query_for("example.net", QType::A));
+        let second = ask(&server, &query_for("EXAMPLE.net", QType::A));
+        assert_eq!(second.answers[0].rdata, first.answers[0].rdata);
+        assert!(!second.header.header_flags.authoritative_answer);
+        assert_eq!(queries.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn ttl_zero_answers_are_passed_on_but_not_cached() {
+        let config = Config {
+            cache_min_ttl: 60,
+            ..Config::default()
+        };
+        let (addr, queries) = upstream_answering(answer_a_uncacheable);
+        let server = Server::new(config).with_resolver(StubResolver::new(addr));
+        let message = query_for("example.net", QType::A);
+        let response = ask(&server, &message);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].ttl, 0);
+        let message = DNSMessage::from_bytes(&message).unwrap();
+        assert!(!server.cache().contains(&message.questions[0], &message));
+
+        ask(&server, &message.to_bytes().unwrap());
+        assert_eq!(queries.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn responses_can_be_sent_uncompressed() {
+        let config = Config {
+            disable_compression: true,
+            ..Config::default()
+        };
+        let server = Server::new(config).with_zone(zone());
+        let bytes = server.handle(&query_for("example.com", QType::Ns)).unwrap();
+        let response = DNSMessage::from_bytes(&bytes).unwrap();
+        assert!(!response.answers.is_empty());
+        assert_eq!(bytes, response.to_bytes_uncompressed().unwrap());
+        assert!(bytes.len() > response.to_bytes().unwrap().len());
+    }
+
+    #[test]
+    fn static_responses_take_the_query_id() {
+        let query = DNSMessage::from_bytes(&query_for("example.net", QType::A)).unwrap();
+        let canned = answer_a(&query).to_bytes().unwrap();
+        let config = Config {
+            static_response: Some(canned.clone()),
+            ..Config::default()
+        };
+        let (addr, queries) = upstream();
+        let server = Server::new(config).with_resolver(StubResolver::new(addr));
+        let mut message = query_for("www.example.com", QType::Mx);
+        message[..2].copy_from_slice(&[0x01, 0x02]);
+        let response = server.handle(&message).unwrap();
+        assert_eq!(response[..2], [0x01, 0x02]);
+        assert_eq!(response[2..], canned[2..]);
+        assert!(queries.try_recv().is_err());
+    }
+
+    // Answers like `answer_a`, with a reserved flag set in the OPT record
+    fn answer_a_with_reserved_flag(query: &DNSMessage) -> DNSMessage {
+        let mut response = answer_a(query);
+        response.additional[0].ttl |= 0x0001;
+        response
+    }
+
+    #[test]
+    fn upstream_reserved_flags_are_passed_on() {
+        let (addr, _) = upstream_answering(answer_a_with_reserved_flag);
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        let response = ask(&server, &query(1));
+        assert_eq!(opts(&response), 1);
+        let opt = response.edns().unwrap();
+        assert_eq!(opt.ttl & edns::RESERVED_FLAGS, 0x0001);
+    }
+
+    #[test]
+    fn only_priority_queries_are_answered_over_the_threshold() {
+        let config = Config {
+            load_shed_threshold: Some(1),
+            ..Config::default()
+        };
+        let server = Server::new(config).with_zone(zone());
+        let code = |message: &[u8]| ask(&server, message).header.header_flags.response_code;
+        let www = query_for("www.example.com", QType::A);
+        assert_eq!(code(&www), ResponseCode::NoError);
+
+        // Another query being handled takes us over
+        let _busy = server.metrics().begin_query();
+        assert_eq!(code(&www), ResponseCode::ServFail);
+        assert_eq!(server.metrics().shed_queries(), 1);
+        assert_eq!(
+            code(&query_for("example.com", QType::Soa)),
+            ResponseCode::NoError
+        );
+        let mut chaos = query_for("id.server", QType::Txt);
+        let class = chaos.len() - 2;
+        chaos[class..].copy_from_slice(&[0, 3]);
+        assert_eq!(code(&chaos), ResponseCode::NoError);
+        assert_eq!(server.metrics().shed_queries(), 1);
+    }
+
+    // Answers with the A record before the CNAME pointing at it
+    fn answer_a_before_cname(query: &DNSMessage) -> DNSMessage {
+        let mut response = answer_a(query);
+        response.answers[0].name = parse_name("target.example.net");
+        response.answers.push(ResourceRecord {
+            name: query.questions[0].name.clone(),
+            rtype: QType::Cname,
+            class: QClass::In,
+            ttl: 300,
+            rdata: Rdata::Cname(parse_name("target.example.net")),
+        });
+        response
+    }
+
+    #[test]
+    fn forwarded_answers_are_put_in_chain_order() {
+        let (addr, _) = upstream_answering(answer_a_before_cname);
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        let response = ask(&server, &query_for("example.net", QType::A));
+        let types: Vec<QType> = response.answers.iter().map(|r| r.rtype).collect();
+        assert_eq!(types, [QType::Cname, QType::A]);
+    }
+
+    // `answer_a`, reached through a chain of three CNAMEs
+    fn answer_a_after_three_cnames(query: &DNSMessage) -> DNSMessage {
+        let mut response = answer_a(query);
+        let mut owner = query.questions[0].name.clone();
+        for i in 1..=3 {
+            let target = parse_name(&format!("alias{}.example.net", i));
+            response.answers.insert(
+                i - 1,
+                ResourceRecord {
+                    name: owner,
+                    rtype: QType::Cname,
+                    class: QClass::In,
+                    ttl: 300,
+                    rdata: Rdata::Cname(target.clone()),
+                },
+            );
+            owner = target;
+        }
+        response.answers[3].name = owner;
+        response
+    }
+
+    #[test]
+    fn forwarded_cname_chains_are_capped() {
+        let (addr, _) = upstream_answering(answer_a_after_three_cnames);
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        let response = ask(&server, &query_for("example.net", QType::A));
+        assert_eq!(response.answers.len(), 4);
+
+        let config = Config {
+            max_cname_chain: 2,
+            ..Config::default()
+        };
+        let server = Server::new(config).with_resolver(StubResolver::new(addr));
+        let response = ask(&server, &query_for("example.net", QType::A));
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::ServFail
+        );
+        assert!(response.answers.is_empty());
+    }
+
+    // `query_for` with the RD bit cleared
+    fn query_without_rd(name: &str, qtype: QType) -> Vec<u8> {
+        let mut query = query_for(name, qtype);
+        query[2] &= !0x01;
+        query
+    }
+
+    #[test]
+    fn rd_clear_queries_are_not_forwarded() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default())
+            .with_resolver(StubResolver::new(addr))
+            .with_zone(zone());
+        let response = ask(&server, &query_without_rd("example.net", QType::A));
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::Refused
+        );
+        assert!(queries.try_recv().is_err());
+
+        // In-zone names are still answered
+        let response = ask(&server, &query_without_rd("www.example.com", QType::A));
+        assert!(response.header.header_flags.authoritative_answer);
+        assert_eq!(response.answers.len(), 1);
+
+        let config = Config {
+            forward_without_rd: true,
+            ..Config::default()
+        };
+        let server = Server::new(config).with_resolver(StubResolver::new(addr));
+        let response = ask(&server, &query_without_rd("example.net", QType::A));
+        assert_eq!(response.answers.len(), 1);
+        assert!(queries.try_recv().is_ok());
+    }
+
+    #[test]
+    fn zone_names_are_never_forwarded() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default())
+            .with_resolver(StubResolver::new(addr))
+            .with_zone(zone());
+
+        let response = ask(&server, &query_for("www.example.com", QType::A));
+        let flags = &response.header.header_flags;
+        assert!(flags.authoritative_answer);
+        assert!(flags.recursion_available);
+        assert_eq!(
+            response.answers[0].rdata,
+            Rdata::A(Ipv4Addr::new(192, 0, 2, 1))
+        );
+
+        // Even for names the zone doesn't have
+        let response = ask(&server, &query_for("missing.example.com", QType::A));
+        assert!(response.header.header_flags.authoritative_answer);
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::NxDomain
+        );
+        assert!(queries.try_recv().is_err());
+    }
+
+    #[test]
+    fn dnssec_ok_queries_are_cached_apart() {
+        let (addr, queries) = upstream();
+        let server = Server::new(Config::default()).with_resolver(StubResolver::new(addr));
+        let plain = query(1);
+        let mut dnssec = query(1);
+        // The top bit of the OPT record's TTL flags
+        let flags = dnssec.len() - 4;
+        dnssec[flags] = 0x80;
+
+        for message in [&dnssec, &plain, &dnssec, &plain] {
+            ask(&server, message);
+        }
+        assert_eq!(queries.try_iter().count(), 2);
+        assert_eq!(server.cache().len(), 2);
+        for message in [&dnssec, &plain] {
+            let message = DNSMessage::from_bytes(message).unwrap();
+            assert!(server.cache().contains(&message.questions[0], &message));
+        }
+    }
+
+    #[test]
+    fn mx_answers_are_sorted_only_when_configured() {
+        let text = format!("{}@ IN MX 30 c\n@ IN MX 10 a\n@ IN MX 20 b\n", ZONE);
+        let zone = load_zone(&text, &parse_name("example.com")).unwrap();
+        let preferences = |server: &Server| -> Vec<u16> {
+            let response = ask(server, &query_for("example.com", QType::Mx));
+            response
+                .answers
+                .iter()
+                .filter_map(|record| match record.rdata {
+                    Rdata::Mx { preference, .. } => Some(preference),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let config = Config {
+            sort_by_preference: true,
+            ..Config::default()
+        };
+        let sorted = Server::new(config).with_zone(zone.clone());
+        assert_eq!(preferences(&sorted), [10, 20, 30]);
+        let unsorted = Server::new(Config::default()).with_zone(zone);
+        assert_eq!(preferences(&unsorted), [30, 10, 20]);
+    }
+
+    // `query_for` with an OPT record, with DO set or not
+    fn query_with_do(name: &str, qtype: QType, dnssec_ok: bool) -> Vec<u8> {
+        let mut bytes = query_for(name, qtype);
+        bytes[11] = 1;
+        let flags = if dnssec_ok { 0x80 } else { 0 };
+        bytes.extend_from_slice(&[0, 0, 0x29, 0x04, 0xd0, 0, 0, flags, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn dnssec_ok_is_echoed() {
+        let (addr, _) = upstream();
+        let server = Server::new(Config::default())
+            .with_resolver(StubResolver::new(addr))
+            .with_zone(zone());
+        for name in ["example.net", "www.example.com"] {
+            let response = ask(&server, &query_with_do(name, QType::A, true));
+            assert_eq!(
+                response.edns().unwrap().ttl & edns::DNSSEC_OK,
+                edns::DNSSEC_OK
+            );
+
+            let response = ask(&server, &query_with_do(name, QType::A, false));
+            assert_eq!(response.edns().unwrap().ttl & edns::DNSSEC_OK, 0);
+        }
+    }
+}
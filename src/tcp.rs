@@ -0,0 +1,533 @@
+use std::io::{self, Read};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::ParseError;
+use crate::message::DNSMessage;
+use crate::server::Server;
+use crate::transport;
+
+// Accepts connections on `listener` until it fails, handling each on its own thread. Beyond the
+// configured number of open connections new ones are closed straight away, so clients can't run
+// us out of threads and sockets by opening more.
+pub fn serve(listener: TcpListener, server: Arc<Server>) {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if open.load(Ordering::SeqCst) >= server.config().tcp_max_connections {
+                    eprintln!(
+                        "Refusing TCP connection from {:?} with too many open",
+                        stream.peer_addr().ok()
+                    );
+                    let _ = stream.shutdown(Shutdown::Both);
+                    continue;
+                }
+                open.fetch_add(1, Ordering::SeqCst);
+                let server = server.clone();
+                let open = open.clone();
+                thread::spawn(move || {
+                    handle_connection(stream, server);
+                    open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(e) => eprintln!("Error accepting TCP connection: {}", e),
+        }
+    }
+}
+
+// Over TCP every message is prefixed by its length as 2 bytes, and a client may pipeline several
+// queries on one connection without waiting for the replies. Each query is answered on its own
+// thread so a slow one (e.g. waiting on upstream) doesn't hold up those behind it. Replies are
+// written as they become ready, so may be out of order; clients match them up by ID. Once the
+// configured number of queries are being worked on we stop reading until one finishes, leaving
+// the rest in the socket buffer, so a client can't have us start any number of threads.
+// https://www.rfc-editor.org/rfc/rfc7766#section-6.2.1.1
+//
+// A connection is closed once it has sat idle for the idle timeout, or has been open for the
+// maximum session length, so clients can't tie up threads and sockets by holding connections
+// open. It only counts as idle while none of its queries are being answered, so a slow upstream
+// can't get a connection closed under a client still waiting on it. Queries still being answered
+// when it closes are waited for, though their replies can't be sent, so that a connection's
+// threads all count against the connection cap until they're done.
+// https://www.rfc-editor.org/rfc/rfc7766#section-6.2.3
+fn handle_connection(stream: TcpStream, server: Arc<Server>) {
+    let peer = stream.peer_addr().ok();
+    let writer = match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(e) => {
+            eprintln!("Failed to set up TCP connection from {:?}: {}", peer, e);
+            return;
+        }
+    };
+
+    let in_flight = Arc::new(InFlight::new());
+    let mut replies = Vec::new();
+    let mut reader = TimedReader {
+        stream: &stream,
+        idle_timeout: server.config().tcp_idle_timeout,
+        session_end: Instant::now() + server.config().tcp_max_session,
+        last_read: Instant::now(),
+        in_flight: in_flight.clone(),
+    };
+    loop {
+        if !in_flight.wait_below(server.config().tcp_max_in_flight, reader.session_end) {
+            eprintln!(
+                "Closing TCP connection {:?} at the maximum session length",
+                peer
+            );
+            let _ = stream.shutdown(Shutdown::Both);
+            break;
+        }
+        let query = match read_message(&mut reader) {
+            Ok(Some(query)) => query,
+            Ok(None) => break,
+            Err(e) if transport::is_timeout(&e) => {
+                if Instant::now() >= reader.session_end {
+                    eprintln!(
+                        "Closing TCP connection {:?} at the maximum session length",
+                        peer
+                    );
+                } else {
+                    eprintln!("Closing idle TCP connection {:?}", peer);
+                }
+                let _ = stream.shutdown(Shutdown::Both);
+                break;
+            }
+            Err(e) => {
+                eprintln!("Error reading from TCP connection {:?}: {}", peer, e);
+                let _ = stream.shutdown(Shutdown::Both);
+                break;
+            }
+        };
+        eprintln!("Received {} bytes over TCP from {:?}", query.len(), peer);
+
+        // A message running past its length prefix, or stopping short of it, means we and the
+        // client disagree about where messages start. Everything after it would be read from the
+        // wrong offset, and could be crafted to smuggle in a query, so give up on the connection.
+        if let Err(e) = check_framing(&query) {
+            eprintln!("Closing TCP connection {:?} on bad framing: {}", peer, e);
+            let _ = stream.shutdown(Shutdown::Both);
+            break;
+        }
+
+        let server = server.clone();
+        let writer = writer.clone();
+        let in_flight = in_flight.clone();
+        in_flight.start();
+        replies.retain(|reply: &thread::JoinHandle<()>| !reply.is_finished());
+        replies.push(thread::spawn(move || {
+            let response = server.handle_tcp(&query);
+            in_flight.finish();
+            let Some(response) = response else {
+                return;
+            };
+            // Write the prefix and message in one go under the lock so replies finishing at the
+            // same time can't interleave
+            if let Err(e) = transport::write_framed(&mut writer.lock().unwrap(), &response) {
+                eprintln!("Failed to send TCP response: {}", e);
+            }
+        }));
+    }
+    for reply in replies {
+        if reply.join().is_err() {
+            eprintln!("Reply thread for TCP connection {:?} panicked", peer);
+        }
+    }
+}
+
+// The queries on a connection still being answered
+struct InFlight {
+    state: Mutex<InFlightState>,
+    finished: Condvar,
+}
+
+struct InFlightState {
+    count: usize,
+    // When the last query finished, or the connection opened before any have, from which the
+    // connection is idle if none are left
+    last_finished: Instant,
+}
+
+impl InFlight {
+    fn new() -> Self {
+        InFlight {
+            state: Mutex::new(InFlightState {
+                count: 0,
+                last_finished: Instant::now(),
+            }),
+            finished: Condvar::new(),
+        }
+    }
+
+    fn start(&self) {
+        self.state.lock().unwrap().count += 1;
+    }
+
+    fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.count -= 1;
+        state.last_finished = Instant::now();
+        self.finished.notify_all();
+    }
+
+    // When the last query finished, or `None` if some are still being answered
+    fn idle_since(&self) -> Option<Instant> {
+        let state = self.state.lock().unwrap();
+        (state.count == 0).then_some(state.last_finished)
+    }
+
+    // Waits until fewer than `max` queries are in flight, returning false if `deadline` passes
+    // first
+    fn wait_below(&self, max: usize, deadline: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.count >= max {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            state = self.finished.wait_timeout(state, remaining).unwrap().0;
+        }
+        true
+    }
+}
+
+// Reads from a connection, timing out once it has been idle for the idle timeout, or once the
+// session has gone on too long. Both apply within a message as well as between them, so a client
+// can't keep a connection open by sending a byte at a time. The connection is idle from the
+// later of the last bytes read and the last reply, and not at all while queries are in flight.
+struct TimedReader<'a> {
+    stream: &'a TcpStream,
+    idle_timeout: Duration,
+    session_end: Instant,
+    last_read: Instant,
+    in_flight: Arc<InFlight>,
+}
+
+impl Read for TimedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timed_out = || io::Error::from(io::ErrorKind::TimedOut);
+        loop {
+            let now = Instant::now();
+            let remaining = self
+                .session_end
+                .checked_duration_since(now)
+                .filter(|remaining| !remaining.is_zero())
+                .ok_or_else(timed_out)?;
+            // While queries are in flight we only wake up to check whether they've finished
+            let timeout = match self.in_flight.idle_since() {
+                Some(last_finished) => (last_finished.max(self.last_read) + self.idle_timeout)
+                    .checked_duration_since(now)
+                    .filter(|idle_left| !idle_left.is_zero())
+                    .ok_or_else(timed_out)?,
+                None => self.idle_timeout,
+            };
+            self.stream.set_read_timeout(Some(remaining.min(timeout)))?;
+            match self.stream.read(buf) {
+                Err(e) if transport::is_timeout(&e) => continue,
+                result => {
+                    self.last_read = Instant::now();
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+// Reads one length prefixed message, or `None` if the client closed the connection between
+// messages
+pub(crate) fn read_message(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 2];
+    match stream.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u16::from_be_bytes(length) as usize;
+    let mut message = Vec::with_capacity(length);
+    stream.take(length as u64).read_to_end(&mut message)?;
+    if message.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "connection closed {} bytes into a {} byte message",
+                message.len(),
+                length
+            ),
+        ));
+    }
+    Ok(Some(message))
+}
+
+// Other parse errors are left to the server, which replies FORMERR to them as for UDP
+fn check_framing(query: &[u8]) -> Result<(), ParseError> {
+    match DNSMessage::from_bytes_exact(query) {
+        Err(e @ (ParseError::UnexpectedEnd | ParseError::TrailingBytes(_))) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{Shutdown, SocketAddr, UdpSocket};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::config::Config;
+    use crate::edns::{self, EdnsOption};
+    use crate::message::DNSMessage;
+    use crate::record::Rdata;
+    use crate::resolver::StubResolver;
+
+    // A query for `name` A
+    fn query(id: u16, name: &str) -> Vec<u8> {
+        let mut bytes = id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.extend_from_slice(&[0, 0, 1, 0, 1]);
+        bytes
+    }
+
+    fn write_framed(stream: &mut TcpStream, message: &[u8]) {
+        stream
+            .write_all(&(message.len() as u16).to_be_bytes())
+            .unwrap();
+        stream.write_all(message).unwrap();
+    }
+
+    // An upstream that holds back its answers to queries for slow.example
+    fn slow_upstream() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (size, client) = socket.recv_from(&mut buf).unwrap();
+            let query = DNSMessage::from_bytes(&buf[..size]).unwrap();
+            let socket = socket.try_clone().unwrap();
+            thread::spawn(move || {
+                if query.questions[0].name[0] == b"slow" {
+                    thread::sleep(Duration::from_millis(300));
+                }
+                let mut response = query.clone();
+                response.header = query.header.reply(false);
+                socket
+                    .send_to(&response.to_bytes().unwrap(), client)
+                    .unwrap();
+            });
+        });
+        addr
+    }
+
+    // Serves over TCP on an ephemeral port, forwarding to `slow_upstream`, returning its address
+    fn start(config: Config) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(config).with_resolver(StubResolver::new(slow_upstream()));
+        thread::spawn(move || serve(listener, Arc::new(server)));
+        addr
+    }
+
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    // Reads the next reply, returning its ID
+    fn read_reply_id(stream: &mut TcpStream) -> u16 {
+        let reply = read_message(stream).unwrap().unwrap();
+        DNSMessage::from_bytes(&reply).unwrap().header.id
+    }
+
+    #[test]
+    fn pipelined_queries_are_not_held_up_by_a_slow_one() {
+        let mut stream = connect(start(Config::default()));
+        write_framed(&mut stream, &query(1, "slow.example"));
+        write_framed(&mut stream, &query(2, "fast.example"));
+        assert_eq!(read_reply_id(&mut stream), 2);
+        assert_eq!(read_reply_id(&mut stream), 1);
+    }
+
+    #[test]
+    fn queries_past_the_in_flight_cap_wait_their_turn() {
+        let config = Config {
+            tcp_max_in_flight: 1,
+            ..Config::default()
+        };
+        let mut stream = connect(start(config));
+        write_framed(&mut stream, &query(1, "slow.example"));
+        write_framed(&mut stream, &query(2, "fast.example"));
+        assert_eq!(read_reply_id(&mut stream), 1);
+        assert_eq!(read_reply_id(&mut stream), 2);
+    }
+
+    #[test]
+    fn connections_past_the_cap_are_closed() {
+        let config = Config {
+            tcp_max_connections: 1,
+            ..Config::default()
+        };
+        let addr = start(config);
+        let mut first = connect(addr);
+        let mut second = connect(addr);
+        assert!(matches!(read_message(&mut second), Ok(None) | Err(_)));
+
+        write_framed(&mut first, &query(1, "fast.example"));
+        assert_eq!(read_reply_id(&mut first), 1);
+    }
+
+    #[test]
+    fn connection_slots_are_held_until_their_replies_are_done() {
+        let config = Config {
+            tcp_max_connections: 1,
+            ..Config::default()
+        };
+        let addr = start(config);
+        let mut first = connect(addr);
+        write_framed(&mut first, &query(1, "slow.example"));
+        drop(first);
+        thread::sleep(Duration::from_millis(100));
+        let mut second = connect(addr);
+        assert!(matches!(read_message(&mut second), Ok(None) | Err(_)));
+
+        thread::sleep(Duration::from_millis(400));
+        let mut third = connect(addr);
+        write_framed(&mut third, &query(3, "fast.example"));
+        assert_eq!(read_reply_id(&mut third), 3);
+    }
+
+    #[test]
+    fn connection_closes_cleanly_between_messages() {
+        let addr = start(Config::default());
+        let mut stream = connect(addr);
+        write_framed(&mut stream, &query(1, "fast.example"));
+        assert_eq!(read_reply_id(&mut stream), 1);
+        stream.shutdown(Shutdown::Write).unwrap();
+        assert!(matches!(read_message(&mut stream), Ok(None)));
+    }
+
+    #[test]
+    fn a_short_message_closes_the_connection() {
+        let mut stream = connect(start(Config::default()));
+        let query = query(1, "example.com");
+        let mut framed = ((query.len() + 10) as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&query);
+        stream.write_all(&framed).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        assert!(matches!(read_message(&mut stream), Ok(None)));
+    }
+
+    #[test]
+    fn a_message_running_past_its_prefix_closes_the_connection() {
+        let mut stream = connect(start(Config::default()));
+        let mut query = query(1, "example.com");
+        query.extend_from_slice(&[0, 0]);
+        write_framed(&mut stream, &query);
+        assert!(matches!(read_message(&mut stream), Ok(None)));
+    }
+
+    // A query for `name` asking for our keepalive timeout
+    fn keepalive_query(id: u16, name: &str) -> Vec<u8> {
+        let mut message = DNSMessage::from_bytes(&query(id, name)).unwrap();
+        let keepalive = EdnsOption {
+            code: edns::TCP_KEEPALIVE,
+            data: Vec::new(),
+        };
+        message
+            .additional
+            .push(edns::opt_record(1232, vec![keepalive]));
+        message.to_bytes().unwrap()
+    }
+
+    // The keepalive option in `response`, if there is one
+    fn keepalive(response: &[u8]) -> Option<EdnsOption> {
+        let response = DNSMessage::from_bytes(response).unwrap();
+        match &response.edns()?.rdata {
+            Rdata::Opt(options) => options
+                .iter()
+                .find(|option| option.code == edns::TCP_KEEPALIVE)
+                .cloned(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn keepalive_is_advertised_over_tcp_only() {
+        let config = Config {
+            tcp_idle_timeout: Duration::from_secs(7),
+            ..Config::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = connect(listener.local_addr().unwrap());
+        let server = Arc::new(Server::new(config.clone()));
+        thread::spawn(move || serve(listener, server));
+        transport::write_framed(&mut stream, &keepalive_query(1, "example.com")).unwrap();
+        let response = read_message(&mut stream).unwrap().unwrap();
+        // In units of 100ms
+        assert_eq!(keepalive(&response).unwrap().data, [0, 70]);
+
+        let server = Server::new(config);
+        let response = server.handle(&keepalive_query(2, "example.com")).unwrap();
+        assert_eq!(keepalive(&response), None);
+        // Nor is it sent to clients that didn't ask
+        let response = server.handle_tcp(&query(3, "example.com")).unwrap();
+        assert_eq!(keepalive(&response), None);
+    }
+
+    #[test]
+    fn idle_connections_are_closed() {
+        let config = Config {
+            tcp_idle_timeout: Duration::from_millis(200),
+            ..Config::default()
+        };
+        let mut stream = connect(start(config));
+        transport::write_framed(&mut stream, &query(1, "example.com")).unwrap();
+        assert_eq!(read_reply_id(&mut stream), 1);
+        let started = Instant::now();
+        assert!(matches!(read_message(&mut stream), Ok(None)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn connections_are_not_idle_while_a_query_is_outstanding() {
+        let config = Config {
+            tcp_idle_timeout: Duration::from_millis(100),
+            ..Config::default()
+        };
+        let mut stream = connect(start(config));
+        write_framed(&mut stream, &query(1, "slow.example"));
+        assert_eq!(read_reply_id(&mut stream), 1);
+    }
+
+    #[test]
+    fn sessions_end_at_the_maximum_length() {
+        let config = Config {
+            tcp_max_session: Duration::from_millis(300),
+            ..Config::default()
+        };
+        let mut stream = connect(start(config));
+        let started = Instant::now();
+        // Never idle for long, but still cut off
+        let closed = loop {
+            if transport::write_framed(&mut stream, &query(1, "example.com")).is_err() {
+                break true;
+            }
+            match read_message(&mut stream) {
+                Ok(Some(_)) => thread::sleep(Duration::from_millis(50)),
+                _ => break true,
+            }
+            if started.elapsed() > Duration::from_secs(3) {
+                break false;
+            }
+        };
+        assert!(closed);
+    }
+}
@@ -0,0 +1,179 @@
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+// How long to wait for upstream to answer before giving up
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("I/O error talking to upstream: {0}")]
+    Io(#[from] io::Error),
+    #[error("timed out waiting for upstream response")]
+    Timeout,
+}
+
+// Carries a query to an upstream server and brings back its response, both as wire bytes.
+// Implementations are expected to only return a response carrying the query's ID, and to give up
+// with `TransportError::Timeout` once `timeout` has passed without one.
+pub trait Transport: Send + Sync {
+    fn exchange(&self, query: &[u8], timeout: Duration) -> Result<Vec<u8>, TransportError>;
+}
+
+// Sends queries over UDP.
+//
+// By default every query goes out on a freshly bound socket so the OS picks a new random source
+// port each time, which together with the random ID makes spoofed responses hard to land.
+// Alternatively a single connected socket can be reused, which skips the per-query bind and
+// has the kernel drop any datagram that doesn't come from the upstream address.
+pub struct UdpTransport {
+    upstream: SocketAddr,
+    connected: Option<Mutex<UdpSocket>>,
+}
+
+impl UdpTransport {
+    pub fn new(upstream: SocketAddr) -> Self {
+        UdpTransport {
+            upstream,
+            connected: None,
+        }
+    }
+
+    /// Creates a transport that sends every query over one socket connected to `upstream`.
+    pub fn connected(upstream: SocketAddr) -> io::Result<Self> {
+        let socket = bind_ephemeral(upstream)?;
+        socket.connect(upstream)?;
+        Ok(UdpTransport {
+            upstream,
+            connected: Some(Mutex::new(socket)),
+        })
+    }
+
+    // Waits for a response carrying the query's ID. Datagrams from other addresses (which a
+    // connected socket never sees), with a different ID or that aren't responses are ignored
+    // rather than treated as the answer.
+    fn receive(
+        &self,
+        socket: &UdpSocket,
+        query: &[u8],
+        connected: bool,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, TransportError> {
+        let deadline = Instant::now() + timeout;
+        // Forwarded queries carry the client's OPT record, so the upstream sizes its response
+        // to whatever the client advertised, which may be anything up to the UDP maximum
+        let mut buf = vec![0; u16::MAX as usize];
+        loop {
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|remaining| !remaining.is_zero())
+                .ok_or(TransportError::Timeout)?;
+            socket.set_read_timeout(Some(remaining))?;
+
+            let (size, source) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if is_timeout(&e) => return Err(TransportError::Timeout),
+                Err(e) => return Err(e.into()),
+            };
+            if !connected && source != self.upstream {
+                eprintln!("Ignoring datagram from unexpected address {}", source);
+                continue;
+            }
+            let response = &buf[..size];
+            if !answers(response, query) {
+                eprintln!("Ignoring datagram that isn't a response to our query");
+                continue;
+            }
+            return Ok(response.to_vec());
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn exchange(&self, query: &[u8], timeout: Duration) -> Result<Vec<u8>, TransportError> {
+        match &self.connected {
+            Some(socket) => {
+                let socket = socket.lock().unwrap();
+                socket.send(query)?;
+                self.receive(&socket, query, true, timeout)
+            }
+            None => {
+                let socket = bind_ephemeral(self.upstream)?;
+                socket.send_to(query, self.upstream)?;
+                self.receive(&socket, query, false, timeout)
+            }
+        }
+    }
+}
+
+// Sends each query over a new TCP connection, e.g. for answers too large for UDP
+pub struct TcpTransport {
+    upstream: SocketAddr,
+}
+
+impl TcpTransport {
+    pub fn new(upstream: SocketAddr) -> Self {
+        TcpTransport { upstream }
+    }
+}
+
+// Messages on the connection with a different ID or that aren't responses are ignored, as over
+// UDP, and the next one read instead
+impl Transport for TcpTransport {
+    fn exchange(&self, query: &[u8], timeout: Duration) -> Result<Vec<u8>, TransportError> {
+        let mut stream = connect_tcp(self.upstream, timeout)?;
+        write_framed(&mut stream, query)?;
+        loop {
+            let mut length = [0; 2];
+            let mut response = Vec::new();
+            let read = stream.read_exact(&mut length).and_then(|()| {
+                response.resize(u16::from_be_bytes(length) as usize, 0);
+                stream.read_exact(&mut response)
+            });
+            match read {
+                Ok(()) if answers(&response, query) => return Ok(response),
+                Ok(()) => eprintln!("Ignoring TCP message that isn't a response to our query"),
+                Err(e) if is_timeout(&e) => return Err(TransportError::Timeout),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+// Whether `response` is a response carrying the ID of `query`
+fn answers(response: &[u8], query: &[u8]) -> bool {
+    let is_response = response
+        .get(2)
+        .is_some_and(|flags| flags & 0b1000_0000 != 0);
+    is_response && response.get(..2) == query.get(..2)
+}
+
+// Connects to `upstream`, with reads as well as the connection itself limited to `timeout`
+pub(crate) fn connect_tcp(upstream: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&upstream, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+// Writes a message with the 2 byte length prefix TCP needs, in one go
+pub(crate) fn write_framed(stream: &mut TcpStream, message: &[u8]) -> io::Result<()> {
+    let mut framed = Vec::with_capacity(2 + message.len());
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)
+}
+
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+// Binds a socket on an OS chosen port, of the same address family as `upstream`
+fn bind_ephemeral(upstream: SocketAddr) -> io::Result<UdpSocket> {
+    match upstream {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)),
+    }
+}
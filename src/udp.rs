@@ -0,0 +1,403 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::pktinfo::{self, Destination};
+use crate::server::Server;
+
+// Answers queries arriving on `socket` until receiving fails, each on its own thread so a slow one
+// (e.g. waiting on upstream) doesn't hold up those behind it. Once the configured number are being
+// answered we stop reading until one finishes, leaving the rest in the socket buffer, so a flood
+// of queries can't have us start any number of threads. On a socket bound to a wildcard address,
+// each reply is sent from the address its query was sent to.
+pub fn serve(socket: UdpSocket, server: Arc<Server>) {
+    if socket
+        .local_addr()
+        .is_ok_and(|addr| addr.ip().is_unspecified())
+    {
+        if let Err(e) = pktinfo::enable(&socket) {
+            eprintln!("Replies may not come from the address queried: {}", e);
+        }
+    }
+    let socket = Arc::new(socket);
+    let in_flight = Arc::new(InFlight::default());
+    // Room for the largest possible datagram, so one larger than we advertise arrives whole
+    // rather than silently cut short and misparsed
+    let mut buf = vec![0; u16::MAX as usize];
+
+    loop {
+        in_flight.wait_below(server.config().udp_max_in_flight);
+        match pktinfo::recv_from(&socket, &mut buf) {
+            Ok((size, source, destination)) => {
+                eprintln!("Received {} bytes from {}", size, source);
+                let payload_size = server.config().udp_payload_size as usize;
+                if size > payload_size {
+                    eprintln!(
+                        "Query from {} is larger than the {} bytes we advertise",
+                        source, payload_size
+                    );
+                }
+                let query = buf[..size].to_vec();
+                let socket = socket.clone();
+                let server = server.clone();
+                let in_flight = in_flight.clone();
+                in_flight.start();
+                thread::spawn(move || {
+                    answer(&socket, &server, &query, source, destination);
+                    in_flight.finish();
+                });
+            }
+            Err(e) => {
+                eprintln!("Error receiving data: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+// Answers a single query, sending the reply, if there is one, back where it came from
+fn answer(
+    socket: &UdpSocket,
+    server: &Server,
+    query: &[u8],
+    source: SocketAddr,
+    destination: Option<Destination>,
+) {
+    let Some(response) = server.handle(query) else {
+        return;
+    };
+    // UDP sources can be spoofed, so a response much larger than its query is what makes us
+    // useful for reflecting traffic at a victim
+    let factor = response.len() as f64 / query.len() as f64;
+    if factor > server.config().amplification_threshold {
+        eprintln!(
+            "Response to {} is {:.1} times the size of its query",
+            source, factor
+        );
+        server.metrics().record_amplified_response();
+    }
+    // One client we can't reach shouldn't stop us answering the rest
+    if let Err(e) = pktinfo::send_to(socket, &response, source, destination) {
+        eprintln!("Failed to send response to {}: {}", source, e);
+    }
+}
+
+// How many queries are being answered, so that reading can wait for there to be room for another
+#[derive(Default)]
+struct InFlight {
+    count: Mutex<usize>,
+    finished: Condvar,
+}
+
+impl InFlight {
+    fn start(&self) {
+        *self.count.lock().unwrap() += 1;
+    }
+
+    fn finish(&self) {
+        *self.count.lock().unwrap() -= 1;
+        self.finished.notify_all();
+    }
+
+    // Waits until fewer than `max` queries are being answered
+    fn wait_below(&self, max: usize) {
+        let mut count = self.count.lock().unwrap();
+        while *count >= max {
+            count = self.finished.wait(count).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::config::{Config, DebugDelay};
+    use crate::edns::{self, EdnsOption};
+    use crate::header::{Header, HeaderFlags, OpCode, QueryOrReply, ResponseCode};
+    use crate::message::{DNSMessage, MessageBuilder};
+    use crate::name::parse_name;
+    use crate::question::Question;
+    use crate::record::{QClass, QType};
+    use crate::resolver::StubResolver;
+    use crate::zone_file::load_zone;
+
+    fn query(name: &str, qtype: QType) -> DNSMessage {
+        let header = Header {
+            id: 0xbeef,
+            header_flags: HeaderFlags {
+                qr: QueryOrReply::Query,
+                op_code: OpCode::Query,
+                authoritative_answer: false,
+                truncation: false,
+                recursion_desired: true,
+                recursion_available: false,
+                response_code: ResponseCode::NoError,
+            },
+            question_count: 0,
+            answer_record_count: 0,
+            authority_record_count: 0,
+            additional_record_count: 0,
+        };
+        MessageBuilder::new(header)
+            .question(Question {
+                name: parse_name(name),
+                qtype,
+                qclass: QClass::In,
+            })
+            .build()
+    }
+
+    // Serves `server` on a socket bound to an ephemeral port at `ip`, returning its address
+    fn start(server: Arc<Server>, ip: &str) -> SocketAddr {
+        let socket = UdpSocket::bind((ip, 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || serve(socket, server));
+        addr
+    }
+
+    // Sends `query` to `addr`, returning the reply
+    fn exchange(addr: SocketAddr, query: &[u8]) -> DNSMessage {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.send_to(query, addr).unwrap();
+        let mut buf = [0; 4096];
+        let (size, _) = client.recv_from(&mut buf).unwrap();
+        DNSMessage::from_bytes(&buf[..size]).unwrap()
+    }
+
+    #[test]
+    fn delayed_replies_arrive_late_without_holding_up_others() {
+        let config = Config {
+            debug_delays: vec![DebugDelay {
+                name: Some(parse_name("id.server")),
+                delay: Duration::from_millis(300),
+            }],
+            ..Config::default()
+        };
+        let addr = start(Arc::new(Server::new(config)), "127.0.0.1");
+
+        let delayed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let started = Instant::now();
+        delayed
+            .send_to(&query("id.server", QType::Txt).to_bytes().unwrap(), addr)
+            .unwrap();
+        // Sent after the delayed query, but answered first
+        exchange(
+            addr,
+            &query("hostname.bind", QType::Txt).to_bytes().unwrap(),
+        );
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        delayed
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0; 4096];
+        delayed.recv_from(&mut buf).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    // An upstream that holds back its answers to queries for names starting with slow
+    fn slow_upstream() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || loop {
+            let mut buf = [0; 512];
+            let (size, client) = socket.recv_from(&mut buf).unwrap();
+            let query = DNSMessage::from_bytes(&buf[..size]).unwrap();
+            let socket = socket.try_clone().unwrap();
+            thread::spawn(move || {
+                if query.questions[0].name[0] == b"slow" {
+                    thread::sleep(Duration::from_millis(300));
+                }
+                let mut response = query.clone();
+                response.header = query.header.reply(false);
+                socket
+                    .send_to(&response.to_bytes().unwrap(), client)
+                    .unwrap();
+            });
+        });
+        addr
+    }
+
+    // Serves `config` on 127.0.0.1, forwarding to `slow_upstream`
+    fn start_forwarding(config: Config) -> (Arc<Server>, SocketAddr) {
+        let resolver = StubResolver::new(slow_upstream());
+        let server = Arc::new(Server::new(config).with_resolver(resolver));
+        let addr = start(server.clone(), "127.0.0.1");
+        (server, addr)
+    }
+
+    #[test]
+    fn a_slow_query_does_not_hold_up_others() {
+        let (_, addr) = start_forwarding(Config::default());
+        let slow = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let started = Instant::now();
+        slow.send_to(&query("slow.example", QType::A).to_bytes().unwrap(), addr)
+            .unwrap();
+        exchange(addr, &query("fast.example", QType::A).to_bytes().unwrap());
+        assert!(started.elapsed() < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn queries_past_the_in_flight_cap_wait_their_turn() {
+        let config = Config {
+            udp_max_in_flight: 1,
+            ..Config::default()
+        };
+        let (_, addr) = start_forwarding(config);
+        let slow = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let started = Instant::now();
+        slow.send_to(&query("slow.example", QType::A).to_bytes().unwrap(), addr)
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+        exchange(addr, &query("fast.example", QType::A).to_bytes().unwrap());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn queries_are_shed_while_others_are_in_flight() {
+        let config = Config {
+            load_shed_threshold: Some(1),
+            ..Config::default()
+        };
+        let (server, addr) = start_forwarding(config);
+        let slow = UdpSocket::bind("127.0.0.1:0").unwrap();
+        slow.send_to(&query("slow.example", QType::A).to_bytes().unwrap(), addr)
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let response = exchange(addr, &query("fast.example", QType::A).to_bytes().unwrap());
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::ServFail
+        );
+        assert_eq!(server.metrics().shed_queries(), 1);
+        // CHAOS queries are still answered
+        let mut chaos = query("id.server", QType::Txt);
+        chaos.questions[0].qclass = QClass::Ch;
+        let response = exchange(addr, &chaos.to_bytes().unwrap());
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::NoError
+        );
+
+        // Once the slow query is answered, there's room again
+        slow.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        slow.recv_from(&mut [0; 512]).unwrap();
+        let response = exchange(addr, &query("fast.example", QType::A).to_bytes().unwrap());
+        assert_eq!(
+            response.header.header_flags.response_code,
+            ResponseCode::NoError
+        );
+    }
+
+    #[test]
+    fn large_answers_to_small_queries_are_counted() {
+        let mut text = String::from("@ 300 IN SOA ns1 hostmaster 1 7200 900 1209600 300\n");
+        for i in 0..8 {
+            text.push_str(&format!(
+                "big 300 IN TXT \"{}\"\n",
+                i.to_string().repeat(100)
+            ));
+        }
+        let zone = load_zone(&text, &parse_name("example.com")).unwrap();
+        let config = Config {
+            amplification_threshold: 5.0,
+            ..Config::default()
+        };
+        let server = Arc::new(Server::new(config).with_zone(zone));
+        let addr = start(server.clone(), "127.0.0.1");
+
+        exchange(addr, &query("example.com", QType::Soa).to_bytes().unwrap());
+        assert_eq!(server.metrics().amplified_responses(), 0);
+        exchange(
+            addr,
+            &query("big.example.com", QType::Txt).to_bytes().unwrap(),
+        );
+        assert_eq!(server.metrics().amplified_responses(), 1);
+    }
+
+    #[test]
+    fn queries_over_the_payload_size_are_received_whole() {
+        let addr = start(Arc::new(Server::new(Config::default())), "127.0.0.1");
+        for size in [1400, 2000] {
+            let mut query = query("id.server", QType::Txt);
+            query.questions[0].qclass = QClass::Ch;
+            let unpadded = query.to_bytes().unwrap().len() + 11 + 4;
+            // Padding the query out to `size` with the EDNS padding option
+            // https://www.rfc-editor.org/rfc/rfc7830
+            let padding = EdnsOption {
+                code: 12,
+                data: vec![0; size - unpadded],
+            };
+            query.additional.push(edns::opt_record(4096, vec![padding]));
+            let bytes = query.to_bytes().unwrap();
+            assert_eq!(bytes.len(), size);
+
+            let response = exchange(addr, &bytes);
+            assert_eq!(response.header.id, query.header.id);
+            assert_eq!(
+                response.header.header_flags.response_code,
+                ResponseCode::NoError
+            );
+            assert_eq!(response.answers.len(), 1);
+        }
+    }
+
+    #[test]
+    fn in_process_queries_get_the_bytes_a_socket_would() {
+        let text = "$TTL 300\n@ IN SOA ns1 hostmaster 1 7200 900 1209600 300\nwww IN A 192.0.2.1\n";
+        let zone = load_zone(text, &parse_name("example.com")).unwrap();
+        let server = Arc::new(Server::new(Config::default()).with_zone(zone));
+        let addr = start(server.clone(), "127.0.0.1");
+
+        for name in ["www.example.com", "missing.example.com"] {
+            let query = query(name, QType::A).to_bytes().unwrap();
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            client.send_to(&query, addr).unwrap();
+            let mut buf = [0; 4096];
+            let (size, _) = client.recv_from(&mut buf).unwrap();
+            assert_eq!(server.query(&query), buf[..size]);
+        }
+
+        // A reply, which is dropped rather than answered
+        let mut reply = query("www.example.com", QType::A);
+        reply.header.header_flags.qr = QueryOrReply::Reply;
+        assert!(server.query(&reply.to_bytes().unwrap()).is_empty());
+    }
+
+    // Loopback covers all of 127.0.0.0/8 on Linux, so a query to 127.0.0.2 from 127.0.0.1 would
+    // be answered from 127.0.0.1 if the route picked the source
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn wildcard_sockets_reply_from_the_address_queried() {
+        let server = Arc::new(Server::new(Config::default()));
+        for ip in ["0.0.0.0", "::"] {
+            let port = start(server.clone(), ip).port();
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let query = query("id.server", QType::Txt).to_bytes().unwrap();
+            client.send_to(&query, ("127.0.0.2", port)).unwrap();
+            let mut buf = [0; 4096];
+            let (_, source) = client.recv_from(&mut buf).unwrap();
+            assert_eq!(
+                source,
+                SocketAddr::from(([127, 0, 0, 2], port)),
+                "bound to {}",
+                ip
+            );
+        }
+    }
+}
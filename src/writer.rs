@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use crate::error::WriteError;
+use crate::name::MAX_LABEL_LENGTH;
+use crate::record::MAX_CHARACTER_STRING;
+
+// Compression pointers only have 14 bits for the offset
+const MAX_POINTER_OFFSET: usize = 0x3FFF;
+
+// Builds a DNS message, compressing names against those already written.
+// https://www.rfc-editor.org/rfc/rfc1035#section-4.1.4
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+    // Offsets of every name suffix written so far, keyed by the lowercased labels
+    names: HashMap<Vec<Vec<u8>>, u16>,
+    compress: bool,
+    // The first thing found that can't be written, reported by `finish` so that the writes
+    // themselves needn't each be checked
+    error: Option<WriteError>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        Writer {
+            bytes: Vec::new(),
+            names: HashMap::new(),
+            compress: true,
+            error: None,
+        }
+    }
+
+    // A writer that always writes names out in full, for clients that mishandle pointers
+    pub(crate) fn uncompressed() -> Self {
+        Writer {
+            compress: false,
+            ..Writer::new()
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<Vec<u8>, WriteError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.bytes),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    // Overwrites two bytes already written, for lengths only known after what follows them
+    pub(crate) fn set_u16(&mut self, at: usize, value: u16) {
+        self.bytes[at..at + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    pub(crate) fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_be_bytes());
+    }
+
+    // Writes the labels of `name` until a suffix that has already been written is found, which
+    // is then replaced by a pointer to it
+    pub(crate) fn write_name(&mut self, name: &[Vec<u8>]) {
+        if !self.compress {
+            self.write_name_uncompressed(name);
+            return;
+        }
+        for i in 0..name.len() {
+            let suffix: Vec<Vec<u8>> = name[i..].iter().map(|l| l.to_ascii_lowercase()).collect();
+            if let Some(&offset) = self.names.get(&suffix) {
+                self.write_u16(0b1100_0000_0000_0000 | offset);
+                return;
+            }
+            if self.bytes.len() <= MAX_POINTER_OFFSET {
+                self.names.insert(suffix, self.bytes.len() as u16);
+            }
+            self.write_label(&name[i]);
+        }
+        self.write_u8(0);
+    }
+
+    // Writes every label of `name` in full, for names that mustn't be compressed
+    pub(crate) fn write_name_uncompressed(&mut self, name: &[Vec<u8>]) {
+        for label in name {
+            self.write_label(label);
+        }
+        self.write_u8(0);
+    }
+
+    // A label's length byte can't be more than 63 without reading as a pointer, so longer labels
+    // can't be written at all
+    fn write_label(&mut self, label: &[u8]) {
+        if label.len() > MAX_LABEL_LENGTH {
+            self.error
+                .get_or_insert(WriteError::LabelTooLong(label.len()));
+            return;
+        }
+        self.write_u8(label.len() as u8);
+        self.write_bytes(label);
+    }
+
+    // Writes `string` after its length byte. Strings too long for that byte aren't written, as
+    // cutting them short would change the data.
+    pub(crate) fn write_character_string(&mut self, string: &[u8]) {
+        if string.len() > MAX_CHARACTER_STRING {
+            self.error
+                .get_or_insert(WriteError::CharacterStringTooLong(string.len()));
+            return;
+        }
+        self.write_u8(string.len() as u8);
+        self.write_bytes(string);
+    }
+}
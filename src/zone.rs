@@ -0,0 +1,354 @@
+use crate::config::AnyResponse;
+use crate::error::ZoneError;
+use crate::header::ResponseCode;
+use crate::name::{is_subdomain, same_name, wire_length, MAX_NAME_LENGTH};
+use crate::record::{QType, Rdata, ResourceRecord};
+
+// TTL of the HINFO record synthesized in answer to ANY queries
+const ANY_HINFO_TTL: u32 = 3600;
+
+// Data for a zone we are authoritative for, as loaded by `load_zone`
+#[derive(Debug, Clone)]
+pub struct Zone {
+    pub origin: Vec<Vec<u8>>,
+    pub records: Vec<ResourceRecord>,
+}
+
+// What a zone has to say about a question
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ZoneAnswer {
+    pub response_code: ResponseCode,
+    // Clear for referrals to a delegated child zone, which we aren't authoritative for
+    pub authoritative: bool,
+    pub answers: Vec<ResourceRecord>,
+    pub authority: Vec<ResourceRecord>,
+    pub additional: Vec<ResourceRecord>,
+}
+
+impl Zone {
+    /// Checks the records make up a usable zone, which needs an SOA record at its origin.
+    pub fn new(origin: Vec<Vec<u8>>, records: Vec<ResourceRecord>) -> Result<Self, ZoneError> {
+        let zone = Zone { origin, records };
+        if zone.records_at(&zone.origin, QType::Soa).is_empty() {
+            return Err(ZoneError::MissingSoa);
+        }
+        Ok(zone)
+    }
+
+    /// Whether `name` is at or below this zone's origin.
+    pub fn contains(&self, name: &[Vec<u8>]) -> bool {
+        is_subdomain(name, &self.origin)
+    }
+
+    /// Answers a question about `name`, which must be within the zone, following at most
+    /// `max_cname_chain` CNAMEs within it.
+    pub fn answer(&self, name: &[Vec<u8>], qtype: QType, max_cname_chain: usize) -> ZoneAnswer {
+        let mut answer = ZoneAnswer {
+            response_code: ResponseCode::NoError,
+            authoritative: true,
+            answers: Vec::new(),
+            authority: Vec::new(),
+            additional: Vec::new(),
+        };
+        let mut name = name.to_vec();
+
+        for _ in 0..=max_cname_chain {
+            // Anything at or below a delegation point belongs to the child zone. We can only
+            // refer the client there, and only if nothing has been answered yet.
+            if let Some(cut) = self.delegation(&name) {
+                if answer.answers.is_empty() {
+                    answer.authoritative = false;
+                    answer.additional = self.glue(&cut);
+                    answer.authority = cut;
+                }
+                return answer;
+            }
+
+            // A DNAME above the name redirects it, like everything else below the DNAME's owner,
+            // to the same name under the DNAME's target. Along with the DNAME the client gets a
+            // CNAME for the name it asked about, synthesized with the DNAME's TTL so it can't
+            // be cached for longer than the DNAME it came from.
+            // https://www.rfc-editor.org/rfc/rfc6672#section-3.1
+            if let Some((dname, target)) = self.dname_for(&name) {
+                answer.answers.push(dname.clone());
+                if wire_length(&target) > MAX_NAME_LENGTH {
+                    answer.response_code = ResponseCode::YxDomain;
+                    return answer;
+                }
+                answer.answers.push(ResourceRecord {
+                    name: name.clone(),
+                    rtype: QType::Cname,
+                    class: dname.class,
+                    ttl: dname.ttl,
+                    rdata: Rdata::Cname(target.clone()),
+                });
+                if self.contains(&target) {
+                    name = target;
+                    continue;
+                }
+                return answer;
+            }
+
+            let matching = self.records_at(&name, qtype);
+            if !matching.is_empty() {
+                answer.answers.extend(matching.into_iter().cloned());
+                return answer;
+            }
+
+            // An alias answers for every type, and the client also wants whatever it leads to
+            // if that's in this zone too
+            let cname = self.records_at(&name, QType::Cname);
+            if let Some(record) = cname.first() {
+                answer.answers.push((*record).clone());
+                match &record.rdata {
+                    Rdata::Cname(target) if self.contains(target) => {
+                        name = target.clone();
+                        continue;
+                    }
+                    _ => return answer,
+                }
+            }
+
+            // NXDOMAIN only if nothing at all exists at or below the name, as an empty
+            // non-terminal exists even with no records of its own
+            if !self.records.iter().any(|r| is_subdomain(&r.name, &name)) {
+                answer.response_code = ResponseCode::NxDomain;
+            }
+            answer.authority.push(self.negative_soa());
+            return answer;
+        }
+
+        // The chain within the zone loops or is too long to follow
+        answer.response_code = ResponseCode::ServFail;
+        answer.answers.clear();
+        answer
+    }
+
+    /// Answers an ANY question about `name` with a single record, as set by `mode`.
+    pub fn answer_any(
+        &self,
+        name: &[Vec<u8>],
+        mode: AnyResponse,
+        max_cname_chain: usize,
+    ) -> ZoneAnswer {
+        // Referrals and negative answers are the same as for any other type
+        let first = self.records.iter().find(|r| same_name(&r.name, name));
+        let Some(record) = first.filter(|_| self.delegation(name).is_none()) else {
+            return self.answer(name, QType::Any, max_cname_chain);
+        };
+
+        let record = match mode {
+            AnyResponse::Hinfo => ResourceRecord {
+                name: name.to_vec(),
+                rtype: QType::Hinfo,
+                class: record.class,
+                ttl: ANY_HINFO_TTL,
+                rdata: Rdata::Hinfo {
+                    cpu: b"RFC8482".to_vec(),
+                    os: Vec::new(),
+                },
+            },
+            AnyResponse::SingleRecord => record.clone(),
+        };
+        ZoneAnswer {
+            response_code: ResponseCode::NoError,
+            authoritative: true,
+            answers: vec![record],
+            authority: Vec::new(),
+            additional: Vec::new(),
+        }
+    }
+
+    fn records_at(&self, name: &[Vec<u8>], rtype: QType) -> Vec<&ResourceRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.rtype == rtype && same_name(&record.name, name))
+            .collect()
+    }
+
+    // The NS records of the highest zone cut between our origin and `name`, if any
+    fn delegation(&self, name: &[Vec<u8>]) -> Option<Vec<ResourceRecord>> {
+        (self.origin.len() + 1..=name.len())
+            .map(|len| &name[name.len() - len..])
+            .map(|ancestor| self.records_at(ancestor, QType::Ns))
+            .find(|ns| !ns.is_empty())
+            .map(|ns| ns.into_iter().cloned().collect())
+    }
+
+    // The closest DNAME strictly above `name`, and the name it redirects `name` to
+    fn dname_for(&self, name: &[Vec<u8>]) -> Option<(&ResourceRecord, Vec<Vec<u8>>)> {
+        (self.origin.len()..name.len()).rev().find_map(|len| {
+            let (prefix, owner) = name.split_at(name.len() - len);
+            let dname = self.records_at(owner, QType::Dname).into_iter().next()?;
+            let Rdata::Dname(target) = &dname.rdata else {
+                return None;
+            };
+            Some((dname, [prefix, target].concat()))
+        })
+    }
+
+    // Addresses we hold for the name servers of a delegation, without which a client couldn't
+    // reach servers named within the child zone
+    fn glue(&self, delegation: &[ResourceRecord]) -> Vec<ResourceRecord> {
+        let mut glue = Vec::new();
+        for record in delegation {
+            if let Rdata::Ns(server) = &record.rdata {
+                for rtype in [QType::A, QType::Aaaa] {
+                    glue.extend(self.records_at(server, rtype).into_iter().cloned());
+                }
+            }
+        }
+        glue
+    }
+
+    // The SOA record to put in the authority section of a negative answer. Its TTL is capped to
+    // the SOA minimum, which is how long the negative answer may be cached.
+    // https://www.rfc-editor.org/rfc/rfc2308#section-3
+    fn negative_soa(&self) -> ResourceRecord {
+        let mut soa = self.records_at(&self.origin, QType::Soa)[0].clone();
+        if let Rdata::Soa { minimum, .. } = soa.rdata {
+            soa.ttl = soa.ttl.min(minimum);
+        }
+        soa
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::name::parse_name;
+    use crate::resolver::DEFAULT_MAX_CNAME_CHAIN;
+    use crate::zone_file::load_zone;
+
+    const SOA: &str = "@ 3600 IN SOA ns1 hostmaster 1 7200 900 1209600 300\n";
+
+    // Loads `records` into a zone for example.com, after an SOA
+    fn load(records: &str) -> Zone {
+        load_zone(&format!("{}{}", SOA, records), &parse_name("example.com")).unwrap()
+    }
+
+    const RECORDS: &str = "\
+www 300 IN A 192.0.2.1
+alias 300 IN CNAME www
+a.b 300 IN A 192.0.2.2
+child 300 IN NS ns.child
+ns.child 300 IN A 192.0.2.53
+loop1 300 IN CNAME loop2
+loop2 300 IN CNAME loop1
+";
+
+    #[test]
+    fn aliases_are_followed_within_the_zone() {
+        let answer = load(RECORDS).answer(
+            &parse_name("alias.example.com"),
+            QType::A,
+            DEFAULT_MAX_CNAME_CHAIN,
+        );
+        assert_eq!(answer.response_code, ResponseCode::NoError);
+        assert!(answer.authoritative);
+        let types: Vec<QType> = answer.answers.iter().map(|r| r.rtype).collect();
+        assert_eq!(types, [QType::Cname, QType::A]);
+    }
+
+    #[test]
+    fn negative_answers_carry_the_soa() {
+        let zone = load(RECORDS);
+        let nxdomain = zone.answer(
+            &parse_name("missing.example.com"),
+            QType::A,
+            DEFAULT_MAX_CNAME_CHAIN,
+        );
+        assert_eq!(nxdomain.response_code, ResponseCode::NxDomain);
+        // Capped to the SOA minimum
+        assert_eq!(nxdomain.authority[0].ttl, 300);
+
+        // b.example.com has nothing of its own, but exists as a.b.example.com does
+        for name in ["www.example.com", "b.example.com"] {
+            let nodata = zone.answer(&parse_name(name), QType::Mx, DEFAULT_MAX_CNAME_CHAIN);
+            assert_eq!(nodata.response_code, ResponseCode::NoError);
+            assert!(nodata.answers.is_empty());
+            assert_eq!(nodata.authority[0].rtype, QType::Soa);
+        }
+    }
+
+    #[test]
+    fn delegations_get_referrals_with_glue() {
+        let answer = load(RECORDS).answer(
+            &parse_name("www.child.example.com"),
+            QType::A,
+            DEFAULT_MAX_CNAME_CHAIN,
+        );
+        assert!(!answer.authoritative);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.authority[0].rtype, QType::Ns);
+        assert_eq!(
+            answer.additional[0].name,
+            parse_name("ns.child.example.com")
+        );
+    }
+
+    #[test]
+    fn cname_chains_are_followed_up_to_the_limit() {
+        let zone =
+            load("a 300 IN CNAME b\nb 300 IN CNAME c\nc 300 IN CNAME d\nd 300 IN A 192.0.2.1\n");
+        let name = parse_name("a.example.com");
+
+        let answer = zone.answer(&name, QType::A, 3);
+        assert_eq!(answer.response_code, ResponseCode::NoError);
+        let types: Vec<QType> = answer.answers.iter().map(|r| r.rtype).collect();
+        assert_eq!(types, [QType::Cname, QType::Cname, QType::Cname, QType::A]);
+
+        let answer = zone.answer(&name, QType::A, 2);
+        assert_eq!(answer.response_code, ResponseCode::ServFail);
+        assert!(answer.answers.is_empty());
+    }
+
+    #[test]
+    fn cname_loops_end_in_servfail() {
+        let answer = load(RECORDS).answer(
+            &parse_name("loop1.example.com"),
+            QType::A,
+            DEFAULT_MAX_CNAME_CHAIN,
+        );
+        assert_eq!(answer.response_code, ResponseCode::ServFail);
+        assert!(answer.answers.is_empty());
+    }
+
+    #[test]
+    fn any_gets_a_single_real_record_in_that_mode() {
+        let zone = load("www 300 IN A 192.0.2.1\nwww 300 IN A 192.0.2.2\nwww 300 IN MX 10 mail\n");
+        let name = parse_name("www.example.com");
+
+        let answer = zone.answer_any(&name, AnyResponse::SingleRecord, DEFAULT_MAX_CNAME_CHAIN);
+        assert!(answer.authoritative);
+        let [record] = answer.answers.as_slice() else {
+            panic!("expected one record: {:?}", answer.answers);
+        };
+        assert!(zone.records.contains(record));
+
+        let answer = zone.answer_any(&name, AnyResponse::Hinfo, DEFAULT_MAX_CNAME_CHAIN);
+        let [record] = answer.answers.as_slice() else {
+            panic!("expected one record: {:?}", answer.answers);
+        };
+        assert_eq!(record.rtype, QType::Hinfo);
+    }
+
+    #[test]
+    fn synthesized_cnames_take_the_dname_ttl() {
+        let zone = load("old 1234 IN DNAME new\nwww.new 300 IN A 192.0.2.1\n");
+        let answer = zone.answer(
+            &parse_name("www.old.example.com"),
+            QType::A,
+            DEFAULT_MAX_CNAME_CHAIN,
+        );
+        let [dname, cname, a] = answer.answers.as_slice() else {
+            panic!("expected DNAME, CNAME and A: {:?}", answer.answers);
+        };
+        assert_eq!(dname.rtype, QType::Dname);
+        assert_eq!(cname.rtype, QType::Cname);
+        assert_eq!(cname.name, parse_name("www.old.example.com"));
+        assert_eq!(cname.rdata, Rdata::Cname(parse_name("www.new.example.com")));
+        assert_eq!(cname.ttl, 1234);
+        assert_eq!(a.ttl, 300);
+    }
+}
@@ -0,0 +1,719 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::base32hex;
+use crate::base64;
+use crate::error::ZoneError;
+use crate::name::is_subdomain;
+use crate::record::{split_text, QClass, QType, Rdata, ResourceRecord, MAX_CHARACTER_STRING};
+use crate::zone::Zone;
+
+// A word from a zone file, with whether it was written in quotes
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+// One record or directive, which may have spanned several lines inside parentheses
+struct Entry {
+    line: usize,
+    // Entries starting with whitespace have no owner and reuse the previous record's
+    owner_omitted: bool,
+    tokens: Vec<Token>,
+}
+
+/// Parses zone data in master file format for the zone at `origin`. Relative names are taken to
+/// be relative to `origin` until a `$ORIGIN` directive changes it.
+///
+/// A record's TTL is the one written on it if there is one, otherwise the value of the most
+/// recent `$TTL` directive above it, so a later `$TTL` only affects the records after it. Before
+/// any `$TTL`, a record without a TTL takes the previous record's, as in RFC 1035.
+///
+/// Records of types we have no presentation format for can be written in the RFC 3597 generic
+/// form, e.g. `host 3600 IN TYPE65280 \# 4 0a000001`.
+/// https://www.rfc-editor.org/rfc/rfc1035#section-5
+pub fn load_zone(text: &str, origin: &[Vec<u8>]) -> Result<Zone, ZoneError> {
+    Zone::new(origin.to_vec(), load_records(text, origin)?)
+}
+
+/// Parses records in master file format as `load_zone` does, without requiring them to make up
+/// a zone, e.g. DNSSEC trust anchors given as DS or DNSKEY records. Every record must be at or
+/// below `origin`, which is the root for a file of names from anywhere.
+pub fn load_records(text: &str, origin: &[Vec<u8>]) -> Result<Vec<ResourceRecord>, ZoneError> {
+    let mut current_origin = origin.to_vec();
+    // Set by $TTL, used for any record without its own TTL
+    let mut default_ttl = None;
+    let mut previous: Option<ResourceRecord> = None;
+    let mut records = Vec::new();
+
+    for entry in entries(text)? {
+        let line = entry.line;
+        let syntax = |message: String| ZoneError::Syntax { line, message };
+        let mut tokens = entry.tokens.iter().peekable();
+
+        let first = tokens.peek().map(|token| token.text.as_str());
+        match first {
+            Some("$ORIGIN") => {
+                tokens.next();
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| syntax("$ORIGIN requires a name".to_string()))?;
+                current_origin = parse_name(&name.text, &current_origin);
+                continue;
+            }
+            Some("$TTL") => {
+                tokens.next();
+                let ttl = tokens
+                    .next()
+                    .and_then(|ttl| ttl.text.parse().ok())
+                    .ok_or_else(|| syntax("$TTL requires a number of seconds".to_string()))?;
+                default_ttl = Some(ttl);
+                continue;
+            }
+            Some(directive) if directive.starts_with('$') => {
+                return Err(syntax(format!("unsupported directive {}", directive)));
+            }
+            _ => {}
+        }
+
+        let name = if entry.owner_omitted {
+            previous
+                .as_ref()
+                .map(|record| record.name.clone())
+                .ok_or_else(|| syntax("first record has no owner name".to_string()))?
+        } else {
+            let owner = tokens.next().expect("entries are never empty");
+            parse_name(&owner.text, &current_origin)
+        };
+        if !is_subdomain(&name, origin) {
+            return Err(syntax("record is outside the zone".to_string()));
+        }
+
+        // The TTL and class are both optional and may come in either order
+        let mut ttl = None;
+        let mut class = None;
+        let rtype = loop {
+            let token = tokens
+                .next()
+                .ok_or_else(|| syntax("missing record type".to_string()))?;
+            if let (None, Ok(value)) = (ttl, token.text.parse::<u32>()) {
+                ttl = Some(value);
+            } else if let (None, Some(value)) = (class, parse_class(&token.text)) {
+                class = Some(value);
+            } else {
+                break parse_type(&token.text)
+                    .ok_or_else(|| syntax(format!("unknown record type {}", token.text)))?;
+            }
+        };
+
+        // Without an explicit TTL use the $TTL in effect at this point in the file, or failing
+        // that the previous record's
+        let ttl = ttl
+            .or(default_ttl)
+            .or(previous.as_ref().map(|record| record.ttl))
+            .ok_or_else(|| syntax("record has no TTL and no $TTL is set".to_string()))?;
+        let class = class
+            .or(previous.as_ref().map(|record| record.class))
+            .unwrap_or(QClass::In);
+        let rdata_tokens: Vec<&Token> = tokens.collect();
+        let rdata = parse_rdata(rtype, &rdata_tokens, &current_origin).map_err(syntax)?;
+
+        let record = ResourceRecord {
+            name,
+            rtype,
+            class,
+            ttl,
+            rdata,
+        };
+        records.push(record.clone());
+        previous = Some(record);
+    }
+
+    Ok(records)
+}
+
+// Splits the text into entries, dropping comments and blank lines and joining lines held
+// together by parentheses
+fn entries(text: &str) -> Result<Vec<Entry>, ZoneError> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let entry = current.get_or_insert_with(|| Entry {
+            line: line_number,
+            owner_omitted: line.starts_with([' ', '\t']),
+            tokens: Vec::new(),
+        });
+        tokenize(line, &mut entry.tokens, &mut depth).map_err(|message| ZoneError::Syntax {
+            line: line_number,
+            message,
+        })?;
+        if depth == 0 {
+            let entry = current.take().expect("entry was just inserted");
+            if !entry.tokens.is_empty() {
+                entries.push(entry);
+            }
+        }
+    }
+
+    match current {
+        Some(entry) if depth > 0 => Err(ZoneError::Syntax {
+            line: entry.line,
+            message: "unclosed parenthesis".to_string(),
+        }),
+        _ => Ok(entries),
+    }
+}
+
+// Appends the words on a line to `tokens`, tracking parentheses in `depth`. Quoted strings keep
+// their escapes, which are only interpreted once we know the value is a character-string.
+fn tokenize(line: &str, tokens: &mut Vec<Token>, depth: &mut usize) -> Result<(), String> {
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '(' => *depth += 1,
+            ')' => {
+                *depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| "unbalanced parenthesis".to_string())?;
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            text.push('\\');
+                            text.extend(chars.next());
+                        }
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated quoted string".to_string()),
+                    }
+                }
+                tokens.push(Token { text, quoted: true });
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut text = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token {
+                    text,
+                    quoted: false,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// Names ending in a dot are absolute, "@" is the origin itself and anything else is relative
+// to the origin
+fn parse_name(name: &str, origin: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    if name == "@" {
+        return origin.to_vec();
+    }
+    let mut labels = crate::name::parse_name(name);
+    if !name.ends_with('.') {
+        labels.extend_from_slice(origin);
+    }
+    labels
+}
+
+fn parse_class(text: &str) -> Option<QClass> {
+    match text.to_ascii_uppercase().as_str() {
+        "IN" => Some(QClass::In),
+        "CH" => Some(QClass::Ch),
+        "HS" => Some(QClass::Hs),
+        other => other
+            .strip_prefix("CLASS")
+            .and_then(|n| n.parse::<u16>().ok())
+            .map(QClass::from),
+    }
+}
+
+fn parse_type(text: &str) -> Option<QType> {
+    let rtype = match text.to_ascii_uppercase().as_str() {
+        "A" => QType::A,
+        "NS" => QType::Ns,
+        "CNAME" => QType::Cname,
+        "SOA" => QType::Soa,
+        "HINFO" => QType::Hinfo,
+        "MX" => QType::Mx,
+        "TXT" => QType::Txt,
+        "SPF" => QType::Spf,
+        "AAAA" => QType::Aaaa,
+        "SRV" => QType::Srv,
+        "CERT" => QType::Cert,
+        "DNAME" => QType::Dname,
+        "APL" => QType::Apl,
+        "DS" => QType::Ds,
+        "RRSIG" => QType::Rrsig,
+        "NSEC" => QType::Nsec,
+        "DNSKEY" => QType::Dnskey,
+        "NSEC3" => QType::Nsec3,
+        "NSEC3PARAM" => QType::Nsec3Param,
+        other => QType::from(other.strip_prefix("TYPE")?.parse::<u16>().ok()?),
+    };
+    Some(rtype)
+}
+
+fn parse_rdata(rtype: QType, tokens: &[&Token], origin: &[Vec<u8>]) -> Result<Rdata, String> {
+    if tokens
+        .first()
+        .is_some_and(|token| !token.quoted && token.text == "\\#")
+    {
+        return parse_generic_rdata(rtype, &tokens[1..]);
+    }
+
+    let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+    let expect = |count: usize| {
+        if texts.len() == count {
+            Ok(())
+        } else {
+            Err(format!("{} RDATA takes {} fields", rtype, count))
+        }
+    };
+    let number = |text: &str| {
+        text.parse::<u32>()
+            .map_err(|_| format!("invalid number {}", text))
+    };
+    let field = |text: &str| {
+        text.parse::<u16>()
+            .map_err(|_| format!("invalid number {}", text))
+    };
+    let byte = |text: &str| {
+        text.parse::<u8>()
+            .map_err(|_| format!("invalid number {}", text))
+    };
+
+    match rtype {
+        QType::A => {
+            expect(1)?;
+            let address = texts[0].parse::<Ipv4Addr>();
+            Ok(Rdata::A(address.map_err(|_| "invalid IPv4 address")?))
+        }
+        QType::Aaaa => {
+            expect(1)?;
+            let address = texts[0].parse::<Ipv6Addr>();
+            Ok(Rdata::Aaaa(address.map_err(|_| "invalid IPv6 address")?))
+        }
+        QType::Ns => {
+            expect(1)?;
+            Ok(Rdata::Ns(parse_name(texts[0], origin)))
+        }
+        QType::Cname => {
+            expect(1)?;
+            Ok(Rdata::Cname(parse_name(texts[0], origin)))
+        }
+        QType::Dname => {
+            expect(1)?;
+            Ok(Rdata::Dname(parse_name(texts[0], origin)))
+        }
+        QType::Mx => {
+            expect(2)?;
+            let preference = texts[0]
+                .parse()
+                .map_err(|_| format!("invalid preference {}", texts[0]))?;
+            Ok(Rdata::Mx {
+                preference,
+                exchange: parse_name(texts[1], origin),
+            })
+        }
+        QType::Srv => {
+            expect(4)?;
+            let field = |text: &str| {
+                text.parse::<u16>()
+                    .map_err(|_| format!("invalid SRV field {}", text))
+            };
+            Ok(Rdata::Srv {
+                priority: field(texts[0])?,
+                weight: field(texts[1])?,
+                port: field(texts[2])?,
+                target: parse_name(texts[3], origin),
+            })
+        }
+        QType::Soa => {
+            expect(7)?;
+            Ok(Rdata::Soa {
+                mname: parse_name(texts[0], origin),
+                rname: parse_name(texts[1], origin),
+                serial: number(texts[2])?,
+                refresh: number(texts[3])?,
+                retry: number(texts[4])?,
+                expire: number(texts[5])?,
+                minimum: number(texts[6])?,
+            })
+        }
+        QType::Hinfo => {
+            expect(2)?;
+            let cpu = unescape(texts[0])?;
+            let os = unescape(texts[1])?;
+            if cpu.len() > MAX_CHARACTER_STRING || os.len() > MAX_CHARACTER_STRING {
+                return Err("HINFO strings can be at most 255 bytes".to_string());
+            }
+            Ok(Rdata::Hinfo { cpu, os })
+        }
+        QType::Txt | QType::Spf => {
+            if tokens.is_empty() {
+                return Err(format!("{} RDATA needs at least one string", rtype));
+            }
+            // Each string is kept as its own character-string, with any longer than a
+            // character-string can hold split across several
+            let mut strings = Vec::new();
+            for token in tokens {
+                strings.extend(split_text(&unescape(&token.text)?));
+            }
+            Ok(Rdata::Txt(strings))
+        }
+        QType::Nsec3 | QType::Nsec3Param => {
+            let fields = if rtype == QType::Nsec3 { 5 } else { 4 };
+            if texts.len() < fields || (rtype == QType::Nsec3Param && texts.len() != fields) {
+                return Err(format!("{} RDATA takes at least {} fields", rtype, fields));
+            }
+            let hash_algorithm = byte(texts[0])?;
+            let flags = byte(texts[1])?;
+            let iterations = texts[2]
+                .parse()
+                .map_err(|_| format!("invalid iterations {}", texts[2]))?;
+            let salt = parse_salt(texts[3])?;
+            if rtype == QType::Nsec3Param {
+                return Ok(Rdata::Nsec3Param {
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                });
+            }
+            let next_hashed_owner = base32hex::decode(texts[4])
+                .filter(|hash| !hash.is_empty() && hash.len() <= MAX_CHARACTER_STRING)
+                .ok_or_else(|| format!("invalid next hashed owner {}", texts[4]))?;
+            let type_bitmaps = texts[5..]
+                .iter()
+                .map(|text| parse_type(text).ok_or_else(|| format!("unknown type {}", text)))
+                .collect::<Result<_, _>>()?;
+            Ok(Rdata::Nsec3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner,
+                type_bitmaps,
+            })
+        }
+        // The digest, key and signature may be split into several words, like generic RDATA
+        QType::Ds => {
+            if texts.len() < 4 {
+                return Err("DS RDATA takes at least 4 fields".to_string());
+            }
+            let digest = decode_hex(&texts[3..].concat())
+                .ok_or_else(|| "DS digest is not valid hex".to_string())?;
+            Ok(Rdata::Ds {
+                key_tag: field(texts[0])?,
+                algorithm: byte(texts[1])?,
+                digest_type: byte(texts[2])?,
+                digest,
+            })
+        }
+        QType::Dnskey => {
+            if texts.len() < 4 {
+                return Err("DNSKEY RDATA takes at least 4 fields".to_string());
+            }
+            let public_key = base64::decode(&texts[3..].concat())
+                .ok_or_else(|| "DNSKEY public key is not valid base 64".to_string())?;
+            Ok(Rdata::Dnskey {
+                flags: field(texts[0])?,
+                protocol: byte(texts[1])?,
+                algorithm: byte(texts[2])?,
+                public_key,
+            })
+        }
+        QType::Rrsig => {
+            if texts.len() < 9 {
+                return Err("RRSIG RDATA takes at least 9 fields".to_string());
+            }
+            let signature = base64::decode(&texts[8..].concat())
+                .ok_or_else(|| "RRSIG signature is not valid base 64".to_string())?;
+            Ok(Rdata::Rrsig {
+                type_covered: parse_type(texts[0])
+                    .ok_or_else(|| format!("unknown type {}", texts[0]))?,
+                algorithm: byte(texts[1])?,
+                labels: byte(texts[2])?,
+                original_ttl: number(texts[3])?,
+                expiration: parse_timestamp(texts[4])?,
+                inception: parse_timestamp(texts[5])?,
+                key_tag: field(texts[6])?,
+                signer_name: parse_name(texts[7], origin),
+                signature,
+            })
+        }
+        _ => Err(format!(
+            "no presentation format for {}, use the generic \\# form",
+            rtype
+        )),
+    }
+}
+
+// A salt is given in hex, or as "-" when there is none
+fn parse_salt(text: &str) -> Result<Vec<u8>, String> {
+    if text == "-" {
+        return Ok(Vec::new());
+    }
+    decode_hex(text)
+        .filter(|salt| salt.len() <= MAX_CHARACTER_STRING)
+        .ok_or_else(|| format!("invalid salt {}", text))
+}
+
+// An RRSIG time, either YYYYMMDDHHmmSS in UTC or a plain number of seconds since 1970. Dates
+// past 2106 wrap round, as the field is compared in serial number arithmetic.
+// https://www.rfc-editor.org/rfc/rfc4034#section-3.2
+fn parse_timestamp(text: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time {}", text);
+    if text.len() != 14 {
+        return text.parse().map_err(|_| invalid());
+    }
+    if !text.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let part = |range: std::ops::Range<usize>| text[range].parse::<i64>().unwrap();
+    let (year, month, day) = (part(0..4), part(4..6), part(6..8));
+    let (hour, minute, second) = (part(8..10), part(10..12), part(12..14));
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+    // Days since 1970 from the date, the inverse of the conversion in record.rs, following
+    // http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    if seconds < 0 {
+        return Err(invalid());
+    }
+    Ok(seconds as u32)
+}
+
+// The RFC 3597 generic form: `\#`, the RDATA length, then the RDATA as hex which may be split
+// into several words
+// https://www.rfc-editor.org/rfc/rfc3597#section-5
+fn parse_generic_rdata(rtype: QType, tokens: &[&Token]) -> Result<Rdata, String> {
+    let (length, hex) = tokens
+        .split_first()
+        .ok_or_else(|| "\\# must be followed by the RDATA length".to_string())?;
+    let length: usize = length
+        .text
+        .parse()
+        .map_err(|_| format!("invalid RDATA length {}", length.text))?;
+
+    let digits: String = hex.iter().map(|token| token.text.as_str()).collect();
+    let bytes = decode_hex(&digits).ok_or_else(|| "RDATA is not valid hex".to_string())?;
+    if bytes.len() != length {
+        return Err(format!(
+            "RDATA length is {} but {} bytes were given",
+            length,
+            bytes.len()
+        ));
+    }
+    Rdata::from_bytes(rtype, &bytes).map_err(|e| e.to_string())
+}
+
+pub(crate) fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let pairs = text.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+// Interprets the escapes in a character-string: \DDD for a byte in decimal, or \X for X itself
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match rest {
+            [d1, d2, d3, tail @ ..] if [d1, d2, d3].iter().all(|d| d.is_ascii_digit()) => {
+                let value = (d1 - b'0') as u16 * 100 + (d2 - b'0') as u16 * 10 + (d3 - b'0') as u16;
+                bytes.push(u8::try_from(value).map_err(|_| format!("invalid escape \\{}", value))?);
+                rest = tail;
+            }
+            [escaped, tail @ ..] => {
+                bytes.push(*escaped);
+                rest = tail;
+            }
+            [] => return Err("string ends with a lone backslash".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::name::parse_name;
+
+    const SOA: &str = "@ 3600 IN SOA ns1 hostmaster 1 7200 900 1209600 300\n";
+
+    // Loads `records` into a zone for example.com, after an SOA
+    fn load(records: &str) -> Zone {
+        load_zone(&format!("{}{}", SOA, records), &parse_name("example.com")).unwrap()
+    }
+
+    // The records at `name` of type `rtype`
+    fn records(zone: &Zone, name: &str, rtype: QType) -> Vec<ResourceRecord> {
+        let name = parse_name(name);
+        zone.records
+            .iter()
+            .filter(|record| record.name == name && record.rtype == rtype)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn names_owners_and_ttls_carry_over() {
+        let zone = load(
+            "\
+$TTL 600
+www IN A 192.0.2.1
+    IN AAAA 2001:db8::1 ; same owner
+$ORIGIN sub.example.com.
+mail 60 MX 10 mx.example.net.
+",
+        );
+        let [a] = records(&zone, "www.example.com", QType::A)
+            .try_into()
+            .unwrap();
+        assert_eq!(a.ttl, 600);
+        assert_eq!(a.rdata, Rdata::A(Ipv4Addr::new(192, 0, 2, 1)));
+        let [aaaa] = records(&zone, "www.example.com", QType::Aaaa)
+            .try_into()
+            .unwrap();
+        assert_eq!(aaaa.rdata, Rdata::Aaaa("2001:db8::1".parse().unwrap()));
+        let [mx] = records(&zone, "mail.sub.example.com", QType::Mx)
+            .try_into()
+            .unwrap();
+        assert_eq!(mx.ttl, 60);
+        assert_eq!(
+            mx.rdata,
+            Rdata::Mx {
+                preference: 10,
+                exchange: parse_name("mx.example.net"),
+            }
+        );
+    }
+
+    #[test]
+    fn generic_records_load_as_raw_bytes() {
+        let zone = load("host 3600 IN TYPE65280 \\# 4 0a00 0001\n");
+        let [record] = records(&zone, "host.example.com", QType::Unknown(65280))
+            .try_into()
+            .unwrap();
+        assert_eq!(record.ttl, 3600);
+        assert_eq!(record.rdata, Rdata::Raw(vec![0x0a, 0x00, 0x00, 0x01]));
+    }
+
+    #[test]
+    fn generic_form_of_a_known_type_is_parsed() {
+        let zone = load("host 3600 IN A \\# 4 c0000201\n");
+        let [record] = records(&zone, "host.example.com", QType::A)
+            .try_into()
+            .unwrap();
+        assert_eq!(record.rdata, Rdata::A(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn generic_length_must_match_the_data() {
+        let text = format!("{}host 3600 IN TYPE65280 \\# 5 0a000001\n", SOA);
+        let error = load_zone(&text, &parse_name("example.com")).unwrap_err();
+        assert!(matches!(error, ZoneError::Syntax { line: 2, .. }));
+    }
+
+    #[test]
+    fn zones_need_an_soa() {
+        let error = load_zone("www 300 IN A 192.0.2.1\n", &parse_name("example.com"));
+        assert!(matches!(error, Err(ZoneError::MissingSoa)));
+    }
+
+    #[test]
+    fn spf_records_load_like_txt() {
+        let zone = load("@ 300 IN SPF \"v=spf1\" \"-all\"\n");
+        let [record] = records(&zone, "example.com", QType::Spf)
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            record.rdata,
+            Rdata::Txt(vec![b"v=spf1".to_vec(), b"-all".to_vec()])
+        );
+    }
+
+    #[test]
+    fn ttl_directives_apply_to_the_records_after_them() {
+        let text = "\
+$TTL 100
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+a IN A 192.0.2.1
+b 42 IN A 192.0.2.2
+$TTL 200
+c IN A 192.0.2.3
+d 43 IN A 192.0.2.4
+";
+        let zone = load_zone(text, &parse_name("example.com")).unwrap();
+        let ttl = |name: &str| records(&zone, name, QType::A)[0].ttl;
+        assert_eq!(ttl("a.example.com"), 100);
+        assert_eq!(ttl("b.example.com"), 42);
+        assert_eq!(ttl("c.example.com"), 200);
+        assert_eq!(ttl("d.example.com"), 43);
+    }
+
+    #[test]
+    fn dnssec_records_load_from_presentation_format() {
+        let records = load_records(
+            "\
+example. 3600 IN DS 50837 8 2 8ECB5D0E DB17D52A
+example. 3600 IN DNSKEY 257 3 8 ( AwEA AQ== )
+www.example. 3600 IN RRSIG A 8 2 3600 20700101000000 1709210096 50837 example. AQID
+",
+            &[],
+        )
+        .unwrap();
+        let texts: Vec<String> = records.iter().map(|record| record.to_string()).collect();
+        assert_eq!(
+            texts,
+            [
+                "example. 3600 IN DS 50837 8 2 8ECB5D0EDB17D52A",
+                "example. 3600 IN DNSKEY 257 3 8 AwEAAQ==",
+                "www.example. 3600 IN RRSIG A 8 2 3600 20700101000000 20240229123456 50837 example. AQID",
+            ]
+        );
+
+        for time in ["20701301000000", "2070010100000x", "-1"] {
+            let text = format!(
+                "www.example. 3600 IN RRSIG A 8 2 3600 {} 20200101000000 50837 example. AQID\n",
+                time
+            );
+            assert!(load_records(&text, &[]).is_err(), "{}", time);
+        }
+    }
+}